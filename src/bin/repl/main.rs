@@ -6,18 +6,49 @@ extern crate time;
 extern crate nom;
 extern crate futures;
 extern crate failure;
+extern crate libc;
 
 mod print_results;
 mod fmt_table;
 
 use std::env;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 
 use futures::executor::block_on;
-use ruba::{Ruba, TableStats};
+use ruba::{CancellationHandle, Ruba, TableStats, QueryError, SyntaxError};
 use time::precise_time_ns;
 use failure::Fail;
 
 const LOAD_CHUNK_SIZE: usize = 1 << 16;
+const PROMPT: &str = "ruba> ";
+
+/// Set by the SIGINT handler, reset once the running query has been cancelled.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Cancels the currently running query when Ctrl-C is pressed instead of terminating the process.
+fn cancel_on_ctrl_c() -> Arc<Mutex<Option<CancellationHandle>>> {
+    let running_query = Arc::new(Mutex::new(None::<CancellationHandle>));
+    let watched = running_query.clone();
+    unsafe {
+        libc::signal(libc::SIGINT, handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(50));
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            if let Some(ref handle) = *watched.lock().unwrap() {
+                handle.cancel();
+            }
+        }
+    });
+    running_query
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -45,8 +76,12 @@ fn print_table_stats(stats: &[TableStats], start_time: u64) {
     for table in stats {
         let size = (table.batches_bytes + table.buffer_bytes) as f64 / 1024f64 / 1024f64;
         println!("\n# Table `{}` ({} rows, {:.2} MiB) #", &table.name, table.rows, size);
-        for &(ref columname, heapsize) in &table.size_per_column {
-            println!("{}: {:.2}MiB", columname, heapsize as f64 / 1024. / 1024.);
+        for &(ref columname, heapsize, compression_ratio) in &table.size_per_column {
+            if compression_ratio > 1.0 {
+                println!("{}: {:.2}MiB ({:.1}x compressed)", columname, heapsize as f64 / 1024. / 1024., compression_ratio);
+            } else {
+                println!("{}: {:.2}MiB", columname, heapsize as f64 / 1024. / 1024.);
+            }
         }
     }
 }
@@ -54,7 +89,8 @@ fn print_table_stats(stats: &[TableStats], start_time: u64) {
 fn repl(ruba: &Ruba) {
     let mut rl = rustyline::Editor::<()>::new();
     rl.load_history(".ruba_history").ok();
-    while let Ok(mut s) = rl.readline("ruba> ") {
+    let running_query = cancel_on_ctrl_c();
+    while let Ok(mut s) = rl.readline(PROMPT) {
         if let Some('\n') = s.chars().next_back() {
             s.pop();
         }
@@ -71,19 +107,25 @@ fn repl(ruba: &Ruba) {
 
         let mut print_trace = false;
         let mut s: &str = &s;
+        let mut indent = PROMPT.len();
         if s.starts_with(":trace") {
             print_trace = true;
             s = &s[7..];
+            indent += 7;
         }
 
         let query = ruba.run_query(s);
-        match block_on(query) {
+        *running_query.lock().unwrap() = Some(query.cancellation_handle());
+        let result = block_on(query);
+        *running_query.lock().unwrap() = None;
+        match result {
             Ok((result, trace)) => {
                 if print_trace {
                     trace.print();
                 }
                 match result {
                     Ok(output) => print_results::print_query_result(&output),
+                    Err(QueryError::SyntaxError(ref err)) => print_syntax_error(err, indent),
                     Err(mut fail) =>{
                         println!("{}", fail);
                         while let Some(cause) = fail.cause() {
@@ -100,3 +142,14 @@ fn repl(ruba: &Ruba) {
     }
     rl.save_history(".ruba_history").ok();
 }
+
+/// Points at the error position directly below the user's input, or shows the offending line if the query spans
+/// multiple lines.
+fn print_syntax_error(err: &SyntaxError, indent: usize) {
+    if err.line == 1 {
+        println!("{}^", " ".repeat(indent + err.column - 1));
+    } else {
+        println!("{}", err.caret_snippet());
+    }
+    println!("Syntax error at line {}, column {}: {}", err.line, err.column, err.message());
+}
@@ -17,6 +17,9 @@ pub fn print_query_result(results: &QueryOutput) {
              results.stats.rows_scanned,
              fmt_time,
              results.stats.rows_scanned as f64 / rt as f64);
+    if results.stats.batches_skipped > 0 {
+        println!("Skipped {} batches.", results.stats.batches_skipped);
+    }
     println!();
     println!("{}\n", format_results(&results.colnames, &results.rows));
 }
@@ -1,12 +1,14 @@
 use std::cell::Ref;
 use std::collections::HashMap;
-use std::hash::BuildHasherDefault;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash};
 use std::marker::PhantomData;
+use std::mem;
 
 use engine::typed_vec::TypedVec;
 use engine::vector_op::*;
 use engine::vector_op::types::*;
-use ingest::raw_val::RawVal;
+use mem_store::value::Val;
 use seahash::SeaHasher;
 
 type HashMapSea<K, V> = HashMap<K, V, BuildHasherDefault<SeaHasher>>;
@@ -51,21 +53,17 @@ pub struct HashMapGrouping<T> {
     input: BufferRef,
     unique_out: BufferRef,
     grouping_key_out: BufferRef,
-    cardinality_out: BufferRef,
     t: PhantomData<T>,
 }
 
 impl<T: IntVecType<T> + IntoUsize> HashMapGrouping<T> {
     pub fn boxed<'a>(input: BufferRef,
                      unique_out: BufferRef,
-                     grouping_key_out: BufferRef,
-                     cardinality_out: BufferRef,
-                     _max_index: usize) -> BoxedOperator<'a> {
+                     grouping_key_out: BufferRef) -> BoxedOperator<'a> {
         Box::new(HashMapGrouping::<T> {
             input,
             unique_out,
             grouping_key_out,
-            cardinality_out,
             t: PhantomData
         })
     }
@@ -73,8 +71,7 @@ impl<T: IntVecType<T> + IntoUsize> HashMapGrouping<T> {
 
 impl<'a, T: IntVecType<T> + IntoUsize> VecOperator<'a> for HashMapGrouping<T> {
     fn execute(&mut self, scratchpad: &mut Scratchpad<'a>) {
-        let (unique, grouping_key, cardinality) = {
-            let mut count = T::zero();
+        let (unique, grouping, map_size) = {
             let grouping_key = Ref::map(scratchpad.get(self.input), T::unwrap);
             let mut grouping = Vec::with_capacity(grouping_key.len());
             let mut groups = Vec::new();
@@ -82,16 +79,186 @@ impl<'a, T: IntVecType<T> + IntoUsize> VecOperator<'a> for HashMapGrouping<T> {
             for i in grouping_key.iter() {
                 grouping.push(*map.entry(i).or_insert_with(|| {
                     groups.push(*i);
-                    let old = count;
-                    count = count + T::one();
-                    old
+                    (groups.len() - 1) as u32
                 }));
             }
-            (grouping, groups, count.to_usize().unwrap())
+            let order = sort_groups(groups.len(), |group| groups[group], &mut grouping);
+            let unique = order.into_iter().map(|group| groups[group]).collect();
+            (unique, grouping, map_heap_size(&map))
         };
+        scratchpad.track_temporary(map_size);
         scratchpad.set(self.unique_out, T::wrap(unique));
-        scratchpad.set(self.grouping_key_out, T::wrap(grouping_key));
-        scratchpad.set(self.cardinality_out, TypedVec::Constant(RawVal::Int(cardinality as i64)));
+        scratchpad.set(self.grouping_key_out, u32::wrap(grouping));
+    }
+}
+
+#[derive(Debug)]
+pub struct RowGrouping<K> {
+    columns: Vec<BufferRef>,
+    packing: Vec<(i64, u32)>,
+    grouping_key_out: BufferRef,
+    representative_rows_out: BufferRef,
+    k: PhantomData<K>,
+}
+
+impl<K: RowKey> RowGrouping<K> {
+    pub fn boxed<'a>(columns: Vec<BufferRef>,
+                     packing: Vec<(i64, u32)>,
+                     grouping_key_out: BufferRef,
+                     representative_rows_out: BufferRef) -> BoxedOperator<'a> {
+        Box::new(RowGrouping::<K> {
+            columns,
+            packing,
+            grouping_key_out,
+            representative_rows_out,
+            k: PhantomData,
+        })
+    }
+}
+
+impl<'a, K: RowKey> VecOperator<'a> for RowGrouping<K> {
+    fn execute(&mut self, scratchpad: &mut Scratchpad<'a>) {
+        let (grouping, representative_rows, keys_size) = {
+            let columns = self.columns.iter().map(|&column| scratchpad.get(column)).collect::<Vec<_>>();
+            let keys = K::row_keys(&columns, &self.packing);
+            let mut grouping = Vec::with_capacity(keys.len());
+            let mut rows = Vec::new();
+            let mut map = HashMapSea::default();
+            for (row, key) in keys.iter().enumerate() {
+                grouping.push(*map.entry(key).or_insert_with(|| {
+                    rows.push(row);
+                    (rows.len() - 1) as u32
+                }));
+            }
+            let order = sort_groups(rows.len(), |group| &keys[rows[group]], &mut grouping);
+            let representative_rows = order.into_iter().map(|group| rows[group]).collect();
+            let keys_size = map_heap_size(&map) + keys.iter().map(|key| mem::size_of::<K>() + key.heap_size()).sum::<usize>();
+            (grouping, representative_rows, keys_size)
+        };
+        scratchpad.track_temporary(keys_size);
+        scratchpad.set(self.grouping_key_out, u32::wrap(grouping));
+        scratchpad.set(self.representative_rows_out, TypedVec::USize(representative_rows));
+    }
+}
+
+/// Approximate memory allocated by a hash map, ignoring memory owned by its keys and values.
+fn map_heap_size<K, V>(map: &HashMapSea<K, V>) -> usize {
+    map.capacity() * (mem::size_of::<K>() + mem::size_of::<V>() + mem::size_of::<u64>())
+}
+
+/// Renumbers the groups in `grouping` to follow the sort order of their keys, which allows results from different
+/// batches to be merged. Returns the original group numbers in sort order.
+fn sort_groups<K: Ord, F: Fn(usize) -> K>(count: usize, key: F, grouping: &mut [u32]) -> Vec<usize> {
+    let mut order = (0..count).collect::<Vec<_>>();
+    order.sort_unstable_by_key(|&group| key(group));
+    let mut rank = vec![0; count];
+    for (i, &group) in order.iter().enumerate() {
+        rank[group] = i as u32;
+    }
+    for group in grouping.iter_mut() {
+        *group = rank[*group as usize];
+    }
+    order
+}
+
+/// Key that identifies the group of a row when grouping by multiple columns.
+pub trait RowKey: Hash + Eq + Ord + fmt::Debug + 'static {
+    fn row_keys(columns: &[Ref<TypedVec>], packing: &[(i64, u32)]) -> Vec<Self> where Self: Sized;
+    /// Memory allocated by the key.
+    fn heap_size(&self) -> usize { 0 }
+}
+
+/// Packs the value of each column minus its offset into a field of the given width.
+impl RowKey for u128 {
+    fn row_keys(columns: &[Ref<TypedVec>], packing: &[(i64, u32)]) -> Vec<u128> {
+        let mut keys = vec![0; columns[0].len()];
+        for (column, &(offset, width)) in columns.iter().zip(packing) {
+            match **column {
+                TypedVec::Integer(ref data) => pack(&mut keys, data, offset, width),
+                TypedVec::EncodedU8(ref data, _) => pack(&mut keys, data, offset, width),
+                TypedVec::EncodedU16(ref data, _) => pack(&mut keys, data, offset, width),
+                TypedVec::EncodedU32(ref data, _) => pack(&mut keys, data, offset, width),
+                TypedVec::BorrowedEncodedU8(data, _) => pack(&mut keys, data, offset, width),
+                TypedVec::BorrowedEncodedU16(data, _) => pack(&mut keys, data, offset, width),
+                TypedVec::BorrowedEncodedU32(data, _) => pack(&mut keys, data, offset, width),
+                ref other => panic!("cannot pack {:?} into grouping key", other.get_type()),
+            }
+        }
+        keys
+    }
+}
+
+fn pack<T: Into<i64> + Copy>(keys: &mut [u128], data: &[T], offset: i64, width: u32) {
+    for (key, &value) in keys.iter_mut().zip(data) {
+        *key = (*key << width) | (value.into() - offset) as u128;
+    }
+}
+
+/// Concatenates an encoding of each column value which sorts bytewise in the same order as the values.
+impl RowKey for Vec<u8> {
+    fn row_keys(columns: &[Ref<TypedVec>], _: &[(i64, u32)]) -> Vec<Vec<u8>> {
+        let mut keys = vec![Vec::new(); columns[0].len()];
+        for column in columns {
+            match **column {
+                TypedVec::String(ref data) => for (key, value) in keys.iter_mut().zip(data) { push_str(key, value) },
+                TypedVec::Integer(ref data) => for (key, &value) in keys.iter_mut().zip(data) { push_i64(key, value) },
+                TypedVec::Mixed(ref data) => for (key, value) in keys.iter_mut().zip(data) { push_val(key, value) },
+                TypedVec::EncodedU8(ref data, _) => push_ints(&mut keys, data, 1),
+                TypedVec::EncodedU16(ref data, _) => push_ints(&mut keys, data, 2),
+                TypedVec::EncodedU32(ref data, _) => push_ints(&mut keys, data, 4),
+                TypedVec::BorrowedEncodedU8(data, _) => push_ints(&mut keys, data, 1),
+                TypedVec::BorrowedEncodedU16(data, _) => push_ints(&mut keys, data, 2),
+                TypedVec::BorrowedEncodedU32(data, _) => push_ints(&mut keys, data, 4),
+                TypedVec::Empty(_) => {}
+                ref other => panic!("cannot use {:?} as grouping key", other.get_type()),
+            }
+        }
+        keys
+    }
+
+    fn heap_size(&self) -> usize { self.capacity() }
+}
+
+fn push_ints<T: Into<i64> + Copy>(keys: &mut [Vec<u8>], data: &[T], bytes: usize) {
+    for (key, &value) in keys.iter_mut().zip(data) {
+        push_big_endian(key, value.into() as u64, bytes);
+    }
+}
+
+fn push_big_endian(key: &mut Vec<u8>, value: u64, bytes: usize) {
+    for i in (0..bytes).rev() {
+        key.push((value >> (8 * i)) as u8);
+    }
+}
+
+fn push_i64(key: &mut Vec<u8>, value: i64) {
+    // Flipping the sign bit makes negative numbers sort before positive ones
+    push_big_endian(key, (value as u64) ^ (1 << 63), 8);
+}
+
+fn push_str(key: &mut Vec<u8>, value: &str) {
+    // Zero bytes are escaped so that the terminator sorts before any continuation of the string
+    for &byte in value.as_bytes() {
+        key.push(byte);
+        if byte == 0 {
+            key.push(1);
+        }
+    }
+    key.extend_from_slice(&[0, 0]);
+}
+
+fn push_val(key: &mut Vec<u8>, value: &Val) {
+    match *value {
+        Val::Null => key.push(0),
+        Val::Bool(b) => key.extend_from_slice(&[1, b as u8]),
+        Val::Integer(i) => {
+            key.push(2);
+            push_i64(key, i);
+        }
+        Val::Str(s) => {
+            key.push(3);
+            push_str(key, s);
+        }
     }
 }
 
@@ -200,3 +367,19 @@ impl<'a, T, U> VecOperator<'a> for VecSum<T, U> where
         scratchpad.set(self.output, result);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_map_grouping_counts_map_memory() {
+        let mut scratchpad = Scratchpad::new(3);
+        scratchpad.set(BufferRef(0), TypedVec::Integer((0..1000).collect()));
+        let mut grouping = HashMapGrouping::<i64>::boxed(BufferRef(0), BufferRef(1), BufferRef(2));
+        grouping.execute(&mut scratchpad);
+        let buffers = scratchpad.heap_size();
+        assert!(scratchpad.take_peak_heap_size() >= buffers + 1000 * 12);
+        assert_eq!(scratchpad.take_peak_heap_size(), buffers);
+    }
+}
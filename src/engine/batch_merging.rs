@@ -1,16 +1,36 @@
-use std::cmp::{max, min};
-use std::fmt::Debug;
+use std::cmp::{max, min, Ordering};
+use std::mem;
 use std::usize;
 
+use bit_vec::BitVec;
 use engine::aggregator::Aggregator;
 use engine::typed_vec::TypedVec;
-use engine::types::*;
-use engine::vector_op::types::VecType;
-
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
+use mem_store::point_codec::PointCodec;
+use ::QueryError;
+
+
+/// Evaluates `$merge` with `$l` and `$r` bound to the values of two partial results that `unify` converted into the
+/// same representation.
+macro_rules! merge_values {
+    ($left:expr, $right:expr, |$l:ident, $r:ident| $merge:expr) => {
+        match (&$left, &$right) {
+            (&TypedVec::String(ref $l), &TypedVec::String(ref $r)) => $merge,
+            (&TypedVec::Integer(ref $l), &TypedVec::Integer(ref $r)) => $merge,
+            (&TypedVec::Mixed(ref $l), &TypedVec::Mixed(ref $r)) => $merge,
+            (&TypedVec::Raw(ref $l), &TypedVec::Raw(ref $r)) => $merge,
+            (&TypedVec::EncodedU32(ref $l, _), &TypedVec::EncodedU32(ref $r, _)) => $merge,
+            (left, right) =>
+                bail!(QueryError::FatalError, "Cannot merge partial results of type {:?} and {:?}", left.get_type(), right.get_type()),
+        }
+    }
+}
 
 pub struct BatchResult<'a> {
     pub group_by: Option<Vec<TypedVec<'a>>>,
     pub sort_by: Option<usize>,
+    pub descending: bool,
     pub select: Vec<TypedVec<'a>>,
     pub aggregators: Vec<Aggregator>,
     pub level: u32,
@@ -26,6 +46,108 @@ impl<'a> BatchResult<'a> {
     }
 }
 
+impl<'a> HeapSizeOf for BatchResult<'a> {
+    fn heap_size_of_children(&self) -> usize {
+        let group_by = self.group_by.as_ref()
+            .map_or(0, |cols| cols.iter().map(|col| col.heap_size_of_children()).sum());
+        group_by + self.select.iter().map(|col| col.heap_size_of_children()).sum::<usize>()
+    }
+}
+
+/// Partial result that does not borrow from the batch it was computed on, so that it can outlive the query.
+pub struct OwnedBatchResult {
+    group_by: Option<Vec<OwnedVec>>,
+    sort_by: Option<usize>,
+    descending: bool,
+    select: Vec<OwnedVec>,
+    aggregators: Vec<Aggregator>,
+    level: u32,
+    batch_count: usize,
+}
+
+enum OwnedVec {
+    Integer(Vec<i64>),
+    Raw(Vec<RawVal>),
+    Boolean(BitVec),
+    USize(Vec<usize>),
+    Constant(RawVal),
+    Empty(usize),
+}
+
+impl OwnedBatchResult {
+    /// Decodes all values. Groups are ordered by their decoded keys since codes of different batches cannot be compared.
+    pub fn from_result(result: BatchResult) -> Result<OwnedBatchResult, QueryError> {
+        let result = order_by_group_key(result)?;
+        Ok(OwnedBatchResult {
+            group_by: result.group_by.map(|cols| cols.into_iter().map(OwnedVec::from_vec).collect()),
+            sort_by: result.sort_by,
+            descending: result.descending,
+            select: result.select.into_iter().map(OwnedVec::from_vec).collect(),
+            aggregators: result.aggregators,
+            level: result.level,
+            batch_count: result.batch_count,
+        })
+    }
+
+    pub fn to_result(&self) -> BatchResult<'static> {
+        BatchResult {
+            group_by: self.group_by.as_ref().map(|cols| cols.iter().map(OwnedVec::to_vec).collect()),
+            sort_by: self.sort_by,
+            descending: self.descending,
+            select: self.select.iter().map(OwnedVec::to_vec).collect(),
+            aggregators: self.aggregators.clone(),
+            level: self.level,
+            batch_count: self.batch_count,
+        }
+    }
+}
+
+impl OwnedVec {
+    fn from_vec(vec: TypedVec) -> OwnedVec {
+        match vec.decode() {
+            TypedVec::Integer(data) => OwnedVec::Integer(data),
+            TypedVec::Raw(data) => OwnedVec::Raw(data),
+            TypedVec::Boolean(data) => OwnedVec::Boolean(data),
+            TypedVec::USize(data) => OwnedVec::USize(data),
+            TypedVec::Constant(value) => OwnedVec::Constant(value),
+            TypedVec::Empty(len) => OwnedVec::Empty(len),
+            other => OwnedVec::Raw((0..other.len()).map(|i| other.get_raw(i)).collect()),
+        }
+    }
+
+    fn to_vec(&self) -> TypedVec<'static> {
+        match *self {
+            OwnedVec::Integer(ref data) => TypedVec::Integer(data.clone()),
+            OwnedVec::Raw(ref data) => TypedVec::Raw(data.clone()),
+            OwnedVec::Boolean(ref data) => TypedVec::Boolean(data.clone()),
+            OwnedVec::USize(ref data) => TypedVec::USize(data.clone()),
+            OwnedVec::Constant(ref value) => TypedVec::Constant(value.clone()),
+            OwnedVec::Empty(len) => TypedVec::Empty(len),
+        }
+    }
+}
+
+impl HeapSizeOf for OwnedBatchResult {
+    fn heap_size_of_children(&self) -> usize {
+        let group_by = self.group_by.as_ref()
+            .map_or(0, |cols| cols.iter().map(|col| col.heap_size_of_children()).sum());
+        group_by + self.select.iter().map(|col| col.heap_size_of_children()).sum::<usize>()
+    }
+}
+
+impl HeapSizeOf for OwnedVec {
+    fn heap_size_of_children(&self) -> usize {
+        match *self {
+            OwnedVec::Integer(ref data) => data.heap_size_of_children(),
+            OwnedVec::Raw(ref data) => data.heap_size_of_children(),
+            OwnedVec::Boolean(ref data) => data.capacity() / 8,
+            OwnedVec::USize(ref data) => data.heap_size_of_children(),
+            OwnedVec::Constant(ref value) => value.heap_size_of_children(),
+            OwnedVec::Empty(_) => 0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum MergeOp {
     TakeLeft,
@@ -34,56 +156,49 @@ pub enum MergeOp {
 }
 
 #[derive(Debug, PartialEq)]
-// TODO(clemens): u16 will not always be large enough
 struct Premerge {
-    left: u16,
-    right: u16,
+    left: usize,
+    right: usize,
 }
 
-pub fn combine<'a>(batch1: BatchResult<'a>, batch2: BatchResult<'a>, limit: usize) -> BatchResult<'a> {
+pub fn combine<'a>(batch1: BatchResult<'a>, batch2: BatchResult<'a>, limit: usize) -> Result<BatchResult<'a>, QueryError> {
     match (batch1.group_by, batch2.group_by) {
         // Aggregation query
         (Some(g1), Some(g2)) => {
+            let mut unified = Vec::with_capacity(g1.len());
+            for (left, right) in g1.into_iter().zip(g2) {
+                unified.push(unify(left, right)?);
+            }
+            let (g1, g2): (Vec<_>, Vec<_>) = unified.into_iter().unzip();
+            let shared_codecs = g1.iter().map(shared_codec).collect::<Vec<_>>();
             let (group_by_cols, ops) = if g1.len() == 1 {
-                // TODO(clemens): other types, val coercion
-                let (merged_grouping, ops) = match (g1[0].get_type(), g2[0].get_type()) {
-                    (EncodingType::Str, EncodingType::Str) =>
-                        merge_deduplicate(g1[0].cast_ref_str(), g2[0].cast_ref_str()),
-                    (EncodingType::U8, EncodingType::U8) =>
-                        merge_deduplicate(g1[0].cast_ref_u8().0, g2[0].cast_ref_u8().0),
-                    (EncodingType::I64, EncodingType::I64) =>
-                        merge_deduplicate(g1[0].cast_ref_i64(), g2[0].cast_ref_i64()),
-                    (t1, t2) => unimplemented!("{:?}, {:?}", t1, t2),
-                };
+                let (merged_grouping, ops) = merge_values!(g1[0], g2[0], |l, r| merge_deduplicate(l, r));
                 (vec![merged_grouping], ops)
             } else {
-                let initial_partitioning = match (g1[0].get_type(), g2[0].get_type()) {
-                    (EncodingType::Str, EncodingType::Str) => partition::<&str>(&g1[0], &g2[0], usize::MAX),
-                    (EncodingType::I64, EncodingType::I64) => partition::<i64>(&g1[0], &g2[0], usize::MAX),
-                    (t1, t2) => unimplemented!("{:?}, {:?}", t1, t2),
-                };
-
-                // TODO(clemens): subpartitionings
-                assert!(g1.len() == 2);
-
-                let (merged_grouping, ops) = match (g1[1].get_type(), g2[1].get_type()) {
-                    (EncodingType::Str, EncodingType::Str) =>
-                        merge_deduplicate_partitioned::<&str>(&initial_partitioning, &g1[1], &g2[1]),
-                    (EncodingType::I64, EncodingType::I64) =>
-                        merge_deduplicate_partitioned::<i64>(&initial_partitioning, &g1[1], &g2[1]),
-                    (t1, t2) => unimplemented!("{:?}, {:?}", t1, t2),
-                };
+                // Partition both sides into runs of equal values of all but the last grouping column
+                let last = g1.len() - 1;
+                let mut partitioning = merge_values!(g1[0], g2[0], |l, r| partition(l, r, usize::MAX));
+                for i in 1..last {
+                    partitioning = merge_values!(g1[i], g2[i], |l, r| subpartition(&partitioning, l, r));
+                }
+
+                let (merged_grouping, ops) = merge_values!(g1[last], g2[last],
+                                                           |l, r| merge_deduplicate_partitioned(&partitioning, l, r));
 
                 let mut group_by_cols = Vec::with_capacity(g1.len());
-                group_by_cols.push(match (g1[0].get_type(), g2[0].get_type()) {
-                    (EncodingType::Str, EncodingType::Str) => merge_drop::<&str>(&g1[0], &g2[0], &ops),
-                    (EncodingType::I64, EncodingType::I64) => merge_drop::<i64>(&g1[0], &g2[0], &ops),
-                    (t1, t2) => unimplemented!("{:?}, {:?}", t1, t2),
-                });
+                for i in 0..last {
+                    group_by_cols.push(merge_values!(g1[i], g2[i], |l, r| merge_drop(l, r, &ops)));
+                }
                 group_by_cols.push(merged_grouping);
 
                 (group_by_cols, ops)
             };
+            let group_by_cols = group_by_cols.into_iter().zip(shared_codecs)
+                .map(|(col, codec)| match codec {
+                    Some(codec) => with_codec(col, codec),
+                    None => col,
+                })
+                .collect();
 
             let mut aggregates = Vec::with_capacity(batch1.aggregators.len());
             for (i, aggregator) in batch1.aggregators.iter().enumerate() {
@@ -93,57 +208,52 @@ pub fn combine<'a>(batch1: BatchResult<'a>, batch2: BatchResult<'a>, limit: usiz
                     &ops, *aggregator);
                 aggregates.push(merged);
             }
-            BatchResult {
+            Ok(BatchResult {
                 group_by: Some(group_by_cols),
                 sort_by: None,
+                descending: false,
                 select: aggregates,
                 aggregators: batch1.aggregators,
                 level: batch1.level + 1,
                 batch_count: batch1.batch_count + batch2.batch_count,
-            }
+            })
         }
         // No aggregation
         (None, None) => {
             match batch1.sort_by {
                 // Sort query
                 Some(index) => {
-                    let (merged_sort_col, ops) = {
-                        let s1 = &batch1.select[index];
-                        let s2 = &batch2.select[index];
-                        match (s1.get_type(), s2.get_type()) {
-                            (EncodingType::Str, EncodingType::Str) =>
-                                merge_sort(s1.cast_ref_str(), s2.cast_ref_str(), limit),
-                            (EncodingType::I64, EncodingType::I64) =>
-                                merge_sort(s1.cast_ref_i64(), s2.cast_ref_i64(), limit),
-                            (t1, t2) => unimplemented!("{:?}, {:?}", t1, t2),
-                        }
-                    };
+                    let mut select1 = Vec::with_capacity(batch1.select.len());
+                    let mut select2 = Vec::with_capacity(batch1.select.len());
+                    for (left, right) in batch1.select.into_iter().zip(batch2.select) {
+                        // Codes of shared dictionaries are not ordered like the strings they encode
+                        let (left, right) = unify(widen(left), widen(right))?;
+                        select1.push(left);
+                        select2.push(right);
+                    }
+                    let s1 = mem::replace(&mut select1[index], TypedVec::Empty(0));
+                    let s2 = mem::replace(&mut select2[index], TypedVec::Empty(0));
+                    let (merged_sort_col, ops) = merge_values!(s1, s2, |l, r| merge_sort(l, r, limit, batch1.descending));
 
-                    let mut result = Vec::with_capacity(batch1.select.len());
-                    for (i, (col1, col2)) in batch1.select.into_iter().zip(batch2.select).enumerate() {
+                    let mut result = Vec::with_capacity(select1.len());
+                    for (i, (col1, col2)) in select1.into_iter().zip(select2).enumerate() {
                         if i == index {
                             result.push(TypedVec::Empty(0));
                         } else {
-                            let merged = match (col1.get_type(), col2.get_type()) {
-                                (EncodingType::Str, EncodingType::Str) =>
-                                    merge(col1.cast_ref_str(), col2.cast_ref_str(), &ops),
-                                (EncodingType::I64, EncodingType::I64) =>
-                                    merge(col1.cast_ref_i64(), col2.cast_ref_i64(), &ops),
-                                (t1, t2) => unimplemented!("{:?}, {:?}", t1, t2),
-                            };
-                            result.push(merged);
+                            result.push(merge_values!(col1, col2, |l, r| merge(l, r, &ops)));
                         }
                     }
                     result[index] = merged_sort_col;
 
-                    BatchResult {
+                    Ok(BatchResult {
                         group_by: None,
                         sort_by: Some(index),
+                        descending: batch1.descending,
                         select: result,
                         aggregators: Vec::new(),
                         level: batch1.level + 1,
                         batch_count: batch1.batch_count + batch2.batch_count,
-                    }
+                    })
                 }
                 // Select query
                 None => {
@@ -154,22 +264,114 @@ pub fn combine<'a>(batch1: BatchResult<'a>, batch2: BatchResult<'a>, limit: usiz
                         };
                         result.push(col1.extend(col2, count))
                     }
-                    BatchResult {
+                    Ok(BatchResult {
                         group_by: None,
                         sort_by: None,
+                        descending: false,
                         select: result,
                         aggregators: Vec::new(),
                         level: batch1.level + 1,
                         batch_count: batch1.batch_count + batch2.batch_count,
-                    }
+                    })
                 }
             }
         }
-        _ => panic!("Trying to merge incompatible batch results"),
+        _ => bail!(QueryError::FatalError, "Trying to merge incompatible batch results"),
+    }
+}
+
+/// Groups on codes of shared dictionaries are ordered by code rather than by value. Decodes them and sorts all groups
+/// by their decoded keys, so that the final output is ordered by grouping key like that of any other grouping.
+pub fn order_by_group_key(result: BatchResult) -> Result<BatchResult, QueryError> {
+    let mut group_by = Vec::new();
+    match result.group_by {
+        Some(ref cols) if cols.iter().any(|col| shared_codec(col).is_some()) => for col in cols {
+            group_by.push(comparable(widen(col.gather(&(0..col.len()).collect::<Vec<_>>())))?);
+        },
+        _ => return Ok(result),
     }
+    let mut indices = (0..result.len()).collect::<Vec<_>>();
+    indices.sort_by(|&i, &j| {
+        group_by.iter()
+            .map(|col| match *col {
+                TypedVec::String(ref data) => data[i].cmp(data[j]),
+                TypedVec::Integer(ref data) => data[i].cmp(&data[j]),
+                TypedVec::Raw(ref data) => data[i].cmp(&data[j]),
+                TypedVec::Mixed(ref data) => data[i].partial_cmp(&data[j]).unwrap_or(Ordering::Equal),
+                _ => Ordering::Equal,
+            })
+            .find(|&ordering| ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(BatchResult {
+        group_by: Some(group_by.iter().map(|col| col.gather(&indices)).collect()),
+        select: result.select.iter().map(|col| col.gather(&indices)).collect(),
+        ..result
+    })
 }
 
-fn merge_deduplicate<'a, T: PartialOrd + Copy + Debug + 'a>(left: &[T], right: &[T]) -> (TypedVec<'a>, Vec<MergeOp>)
+/// Converts two partial results of the same column into a common representation that can be merged.
+/// Batches may use different codecs for the same column, or contain values of different types.
+fn unify<'a>(left: TypedVec<'a>, right: TypedVec<'a>) -> Result<(TypedVec<'a>, TypedVec<'a>), QueryError> {
+    if let (Some(left_codec), Some(right_codec)) = (shared_codec(&left), shared_codec(&right)) {
+        if left_codec.shared_dictionary_id() == right_codec.shared_dictionary_id() {
+            // Later snapshots of a shared dictionary can decode all codes of earlier ones
+            let codec = if left_codec.max_cardinality() >= right_codec.max_cardinality() { left_codec } else { right_codec };
+            return Ok((with_codec(left, codec), with_codec(right, codec)));
+        }
+    }
+    let left = comparable(widen(left))?;
+    let right = comparable(widen(right))?;
+    Ok(match (&left, &right) {
+        (&TypedVec::String(_), &TypedVec::String(_)) |
+        (&TypedVec::Integer(_), &TypedVec::Integer(_)) |
+        (&TypedVec::Mixed(_), &TypedVec::Mixed(_)) |
+        (&TypedVec::Raw(_), &TypedVec::Raw(_)) => (left, right),
+        (&TypedVec::Raw(_), _) | (_, &TypedVec::Raw(_)) => (into_raw(left)?, into_raw(right)?),
+        _ => (left.into_mixed(), right.into_mixed()),
+    })
+}
+
+/// Converts decoded values into strings, integers or `Val`s that borrow from the batch, or into owned values if they
+/// cannot be borrowed.
+fn comparable(vec: TypedVec) -> Result<TypedVec, QueryError> {
+    match vec {
+        TypedVec::String(_) | TypedVec::Integer(_) | TypedVec::Mixed(_) | TypedVec::Empty(_) => Ok(vec),
+        other => into_raw(other),
+    }
+}
+
+fn into_raw(vec: TypedVec) -> Result<TypedVec, QueryError> {
+    match vec {
+        TypedVec::Boolean(_) | TypedVec::USize(_) | TypedVec::Constant(_) =>
+            bail!(QueryError::FatalError, "Cannot merge partial results of type {:?}", vec.get_type()),
+        TypedVec::Raw(_) => Ok(vec),
+        other => Ok(TypedVec::Raw((0..other.len()).map(|i| other.get_raw(i)).collect())),
+    }
+}
+
+/// Decodes encoded values with their codec, codes of different batches cannot be compared.
+fn widen(vec: TypedVec) -> TypedVec {
+    vec.decode()
+}
+
+/// Codec of codes from a table-wide dictionary, which can be merged without decoding.
+fn shared_codec<'a>(vec: &TypedVec<'a>) -> Option<&'a PointCodec<u32>> {
+    match *vec {
+        TypedVec::EncodedU32(_, codec) | TypedVec::BorrowedEncodedU32(_, codec)
+        if codec.shared_dictionary_id().is_some() => Some(codec),
+        _ => None,
+    }
+}
+
+fn with_codec<'a>(vec: TypedVec<'a>, codec: &'a PointCodec<u32>) -> TypedVec<'a> {
+    match vec {
+        TypedVec::EncodedU32(data, _) => TypedVec::EncodedU32(data, codec),
+        other => TypedVec::EncodedU32(other.cast_ref_u32().0.to_vec(), codec),
+    }
+}
+
+fn merge_deduplicate<'a, T: PartialOrd + Clone + 'a>(left: &[T], right: &[T]) -> (TypedVec<'a>, Vec<MergeOp>)
     where Vec<T>: Into<TypedVec<'a>> {
     // TODO(clemens): figure out maths for precise estimate + variance derived from how much grouping reduced cardinality
     let output_len_estimate = max(left.len(), right.len()) + min(left.len(), right.len()) / 2;
@@ -183,18 +385,18 @@ fn merge_deduplicate<'a, T: PartialOrd + Copy + Debug + 'a>(left: &[T], right: &
             ops.push(MergeOp::MergeRight);
             j += 1;
         } else if left[i] <= right[j] {
-            result.push(left[i]);
+            result.push(left[i].clone());
             ops.push(MergeOp::TakeLeft);
             i += 1;
         } else {
-            result.push(right[j]);
+            result.push(right[j].clone());
             ops.push(MergeOp::TakeRight);
             j += 1;
         }
     }
 
     for x in left[i..].iter() {
-        result.push(*x);
+        result.push(x.clone());
         ops.push(MergeOp::TakeLeft);
     }
     if j < right.len() && result.last() == Some(&right[j]) {
@@ -202,18 +404,17 @@ fn merge_deduplicate<'a, T: PartialOrd + Copy + Debug + 'a>(left: &[T], right: &
         j += 1;
     }
     for x in right[j..].iter() {
-        result.push(*x);
+        result.push(x.clone());
         ops.push(MergeOp::TakeRight);
     }
 
     (result.into(), ops)
 }
 
-fn merge_deduplicate_partitioned<'a, T: VecType<T> + 'a>(partitioning: &[Premerge],
-                                                         left: &TypedVec<'a>,
-                                                         right: &TypedVec<'a>) -> (TypedVec<'a>, Vec<MergeOp>) {
-    let left = T::unwrap(left);
-    let right = T::unwrap(right);
+fn merge_deduplicate_partitioned<'a, T: PartialOrd + Clone + 'a>(partitioning: &[Premerge],
+                                                                 left: &[T],
+                                                                 right: &[T]) -> (TypedVec<'a>, Vec<MergeOp>)
+    where Vec<T>: Into<TypedVec<'a>> {
     let output_len_estimate = max(left.len(), right.len()) + min(left.len(), right.len()) / 2;
     let mut result = Vec::with_capacity(output_len_estimate);
     let mut ops = Vec::<MergeOp>::with_capacity(output_len_estimate);
@@ -222,46 +423,40 @@ fn merge_deduplicate_partitioned<'a, T: VecType<T> + 'a>(partitioning: &[Premerg
     let mut j = 0;
     for group in partitioning {
         let mut last = None;
-        let i_max = i + group.left as usize;
-        let j_max = j + group.right as usize;
-        // println!("i_max = {}, j_max = {}", i_max, j_max);
+        let i_max = i + group.left;
+        let j_max = j + group.right;
         for _ in 0..(group.left + group.right) {
-            // println!("i = {}, j = {}, last = {:?}", i, j, last);
-            // println!("{:?} {:?}", left.get(i), right.get(j));
-            if j < j_max && last == Some(right[j]) {
+            if j < j_max && last == Some(&right[j]) {
                 ops.push(MergeOp::MergeRight);
                 j += 1;
             } else if i < i_max && (j >= j_max || left[i] <= right[j]) {
-                result.push(left[i]);
+                result.push(left[i].clone());
                 ops.push(MergeOp::TakeLeft);
-                last = Some(left[i]);
+                last = Some(&left[i]);
                 i += 1;
             } else {
-                result.push(right[j]);
+                result.push(right[j].clone());
                 ops.push(MergeOp::TakeRight);
-                last = Some(right[j]);
+                last = Some(&right[j]);
                 j += 1;
             }
-            // println!("{:?}", ops.last().unwrap());
         }
     }
-    (T::wrap(result), ops)
+    (result.into(), ops)
 }
 
-fn partition<'a, T: VecType<T>>(left: &TypedVec<'a>, right: &TypedVec<'a>, limit: usize) -> Vec<Premerge> {
+fn partition<T: PartialOrd>(left: &[T], right: &[T], limit: usize) -> Vec<Premerge> {
     let mut result = Vec::new();
-    let left = T::unwrap(left);
-    let right = T::unwrap(right);
     let mut i = 0;
     let mut j = 0;
     while i < left.len() && j < right.len() && i + j < limit {
         let mut partition = Premerge { left: 0, right: 0 };
-        let elem = if left[i] <= right[j] { left[i] } else { right[j] };
-        while i < left.len() && elem == left[i] {
+        let elem = if left[i] <= right[j] { &left[i] } else { &right[j] };
+        while i < left.len() && *elem == left[i] {
             partition.left += 1;
             i += 1;
         }
-        while j < right.len() && elem == right[j] {
+        while j < right.len() && *elem == right[j] {
             partition.right += 1;
             j += 1;
         }
@@ -270,28 +465,50 @@ fn partition<'a, T: VecType<T>>(left: &TypedVec<'a>, right: &TypedVec<'a>, limit
 
     // Remaining elements on left
     while i < left.len() && i + j < limit {
-        let elem = left[i];
         let i_start = i;
-        while i < left.len() && elem == left[i] {
+        while i < left.len() && left[i_start] == left[i] {
             i += 1;
         }
-        result.push(Premerge { left: (i - i_start) as u16, right: 0 });
+        result.push(Premerge { left: i - i_start, right: 0 });
     }
 
     // Remaining elements on right
     while j < right.len() && i + j < limit {
-        let elem = right[j];
         let j_start = j;
-        while j < right.len() && elem == right[j] {
+        while j < right.len() && right[j_start] == right[j] {
             j += 1;
         }
-        result.push(Premerge { right: (j - j_start) as u16, left: 0 });
+        result.push(Premerge { right: j - j_start, left: 0 });
     }
     result
 }
 
-// TODO(clemens): implement descending ordering
-fn merge_sort<'a, T: PartialOrd + Copy + Debug + 'a>(left: &[T], right: &[T], limit: usize) -> (TypedVec<'a>, Vec<bool>)
+/// Splits each partition further into runs of equal values in `left` and `right`.
+fn subpartition<T: PartialOrd>(partitioning: &[Premerge], left: &[T], right: &[T]) -> Vec<Premerge> {
+    let mut result = Vec::with_capacity(partitioning.len());
+    let mut i = 0;
+    let mut j = 0;
+    for group in partitioning {
+        let i_max = i + group.left;
+        let j_max = j + group.right;
+        while i < i_max || j < j_max {
+            let elem = if j == j_max || (i < i_max && left[i] <= right[j]) { &left[i] } else { &right[j] };
+            let mut partition = Premerge { left: 0, right: 0 };
+            while i < i_max && *elem == left[i] {
+                partition.left += 1;
+                i += 1;
+            }
+            while j < j_max && *elem == right[j] {
+                partition.right += 1;
+                j += 1;
+            }
+            result.push(partition);
+        }
+    }
+    result
+}
+
+fn merge_sort<'a, T: PartialOrd + Clone + 'a>(left: &[T], right: &[T], limit: usize, descending: bool) -> (TypedVec<'a>, Vec<bool>)
     where Vec<T>: Into<TypedVec<'a>> {
     let mut result = Vec::with_capacity(left.len() + right.len());
     let mut ops = Vec::<bool>::with_capacity(left.len() + right.len());
@@ -299,23 +516,24 @@ fn merge_sort<'a, T: PartialOrd + Copy + Debug + 'a>(left: &[T], right: &[T], li
     let mut i = 0;
     let mut j = 0;
     while i < left.len() && j < right.len() && i + j < limit {
-        if left[i] <= right[j] {
-            result.push(left[i]);
+        let take_left = if descending { left[i] >= right[j] } else { left[i] <= right[j] };
+        if take_left {
+            result.push(left[i].clone());
             ops.push(true);
             i += 1;
         } else {
-            result.push(right[j]);
+            result.push(right[j].clone());
             ops.push(false);
             j += 1;
         }
     }
 
     for x in left[i..min(left.len(), limit - j)].iter() {
-        result.push(*x);
+        result.push(x.clone());
         ops.push(true);
     }
     for x in right[j..min(right.len(), limit - i)].iter() {
-        result.push(*x);
+        result.push(x.clone());
         ops.push(false);
     }
 
@@ -347,27 +565,25 @@ fn merge_aggregate(left: &[i64], right: &[i64], ops: &[MergeOp], aggregator: Agg
     result.into()
 }
 
-fn merge<'a, T: PartialOrd + Copy + Debug + 'a>(left: &[T], right: &[T], ops: &[bool]) -> TypedVec<'a>
+fn merge<'a, T: Clone + 'a>(left: &[T], right: &[T], ops: &[bool]) -> TypedVec<'a>
     where Vec<T>: Into<TypedVec<'a>> {
     let mut result = Vec::with_capacity(ops.len());
     let mut i = 0;
     let mut j = 0;
     for take_left in ops {
         if *take_left {
-            result.push(left[i]);
+            result.push(left[i].clone());
             i += 1;
         } else {
-            result.push(right[j]);
+            result.push(right[j].clone());
             j += 1;
         }
     }
     result.into()
 }
 
-fn merge_drop<'a, T: VecType<T> + 'a>(left: &TypedVec<'a>, right: &TypedVec<'a>, ops: &[MergeOp]) -> TypedVec<'a>
+fn merge_drop<'a, T: Clone + 'a>(left: &[T], right: &[T], ops: &[MergeOp]) -> TypedVec<'a>
     where Vec<T>: Into<TypedVec<'a>> {
-    let left = T::unwrap(left);
-    let right = T::unwrap(right);
     // TODO(clemens): this is an overestimate
     let mut result = Vec::with_capacity(ops.len());
     let mut i = 0;
@@ -375,11 +591,11 @@ fn merge_drop<'a, T: VecType<T> + 'a>(left: &TypedVec<'a>, right: &TypedVec<'a>,
     for op in ops {
         match *op {
             MergeOp::TakeLeft => {
-                result.push(left[i]);
+                result.push(left[i].clone());
                 i += 1;
             }
             MergeOp::TakeRight => {
-                result.push(right[j]);
+                result.push(right[j].clone());
                 j += 1;
             }
             MergeOp::MergeRight => {
@@ -387,18 +603,24 @@ fn merge_drop<'a, T: VecType<T> + 'a>(left: &TypedVec<'a>, right: &TypedVec<'a>,
             }
         }
     }
-    T::wrap(result)
+    result.into()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use engine::vector_op::types::IdentityCodec;
+    use mem_store::column::ColumnData;
+    use mem_store::column_builder::*;
+    use mem_store::value::Val;
+    use bit_vec::BitVec;
+    use ingest::raw_val::RawVal;
 
     #[test]
     fn test_multipass_grouping() {
         let left1 = vec!["A", "A", "A", "C", "P"];
         let right1 = vec!["A", "A", "B", "C", "X", "X", "Z"];
-        let result = partition::<&str>(&<&str>::wrap(left1), &<&str>::wrap(right1), 10);
+        let result = partition(&left1, &right1, 10);
         assert_eq!(result, vec![
             Premerge { left: 3, right: 2 },
             Premerge { left: 0, right: 1 },
@@ -409,8 +631,8 @@ mod tests {
 
         let left2 = vec![1, 3, 7, 2, 1];
         let right2 = vec![3, 5, 0, 2, 1, 2, 1];
-        let (merging, merge_ops) = merge_deduplicate_partitioned::<u32>(&result, &u32::wrap(left2), &u32::wrap(right2));
-        assert_eq!(u32::unwrap(&merging), &[1, 3, 5, 7, 0, 2, 1, 1, 2]);
+        let (merging, merge_ops) = merge_deduplicate_partitioned::<u32>(&result, &left2, &right2);
+        assert_eq!(merging.cast_ref_u32().0, &[1, 3, 5, 7, 0, 2, 1, 1, 2]);
         use self::MergeOp::*;
         assert_eq!(&merge_ops, &[
             TakeLeft,
@@ -426,4 +648,117 @@ mod tests {
             TakeRight,
         ]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_subpartition() {
+        let partitioning = vec![
+            Premerge { left: 3, right: 2 },
+            Premerge { left: 0, right: 1 },
+            Premerge { left: 2, right: 0 },
+        ];
+        let left = vec![1, 1, 2, 4, 5];
+        let right = vec![1, 3, 0];
+        let result = subpartition::<i64>(&partitioning, &left, &right);
+        assert_eq!(result, vec![
+            Premerge { left: 2, right: 1 },
+            Premerge { left: 1, right: 0 },
+            Premerge { left: 0, right: 1 },
+            Premerge { left: 0, right: 1 },
+            Premerge { left: 1, right: 0 },
+            Premerge { left: 1, right: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_combine_heterogeneous_groupings() {
+        let batch = |grouping: TypedVec<'static>, counts: Vec<i64>| BatchResult {
+            group_by: Some(vec![grouping]),
+            sort_by: None,
+            descending: false,
+            select: vec![TypedVec::Integer(counts)],
+            aggregators: vec![Aggregator::Count],
+            level: 0,
+            batch_count: 1,
+        };
+
+        let left = batch(TypedVec::Integer(vec![1, 5]), vec![2, 3]);
+        let right = batch(TypedVec::EncodedU8(vec![5, 7], &IdentityCodec), vec![1, 1]);
+        let result = combine(left, right, usize::MAX).unwrap();
+        assert_eq!(result.group_by.as_ref().unwrap()[0].cast_ref_i64(), &[1, 5, 7]);
+        assert_eq!(result.select[0].cast_ref_i64(), &[2, 4, 1]);
+
+        let right = batch(TypedVec::String(vec!["a", "b"]), vec![4, 1]);
+        let result = combine(result, right, usize::MAX).unwrap();
+        assert_eq!(result.group_by.as_ref().unwrap()[0].cast_ref_mixed(),
+                   &[Val::Integer(1), Val::Integer(5), Val::Integer(7), Val::Str("a"), Val::Str("b")]);
+        assert_eq!(result.select[0].cast_ref_i64(), &[2, 4, 1, 4, 1]);
+
+        let right = batch(TypedVec::Empty(1), vec![6]);
+        let result = combine(result, right, usize::MAX).unwrap();
+        assert_eq!(result.group_by.as_ref().unwrap()[0].cast_ref_mixed()[0], Val::Null);
+        assert_eq!(result.select[0].cast_ref_i64(), &[6, 2, 4, 1, 4, 1]);
+    }
+
+    #[test]
+    fn test_combine_offset_encoded_groupings() {
+        let batch = |grouping: TypedVec<'static>, counts: Vec<i64>| BatchResult {
+            group_by: Some(vec![grouping]),
+            sort_by: None,
+            descending: false,
+            select: vec![TypedVec::Integer(counts)],
+            aggregators: vec![Aggregator::Count],
+            level: 0,
+            batch_count: 1,
+        };
+        let mut builder = IntColBuilder::new();
+        builder.push(&100);
+        builder.push(&102);
+        let column: &'static ColumnData = Box::leak(builder.finalize());
+
+        let left = batch(TypedVec::Integer(vec![100, 101]), vec![2, 3]);
+        let right = batch(column.to_codec().unwrap().get_encoded(), vec![1, 1]);
+        let result = combine(left, right, usize::MAX).unwrap();
+        assert_eq!(result.group_by.as_ref().unwrap()[0].cast_ref_i64(), &[100, 101, 102]);
+        assert_eq!(result.select[0].cast_ref_i64(), &[3, 3, 1]);
+    }
+
+    #[test]
+    fn test_combine_owned_and_borrowed_values() {
+        let sorted = |values: TypedVec<'static>| BatchResult {
+            group_by: None,
+            sort_by: Some(0),
+            descending: false,
+            select: vec![values],
+            aggregators: Vec::new(),
+            level: 0,
+            batch_count: 1,
+        };
+        // Decompressed strings are owned by the partial result
+        let left = sorted(TypedVec::Raw(vec![RawVal::Str("a".to_string()), RawVal::Str("c".to_string())]));
+        let right = sorted(TypedVec::String(vec!["b"]));
+        let result = combine(left, right, usize::MAX).unwrap();
+        match result.select[0] {
+            TypedVec::Raw(ref values) => assert_eq!(
+                values, &[RawVal::Str("a".to_string()), RawVal::Str("b".to_string()), RawVal::Str("c".to_string())]),
+            ref other => panic!("Expected owned values, got {:?}", other.get_type()),
+        }
+
+        let grouped = |grouping: TypedVec<'static>, counts: Vec<i64>| BatchResult {
+            group_by: Some(vec![grouping]),
+            sort_by: None,
+            descending: false,
+            select: vec![TypedVec::Integer(counts)],
+            aggregators: vec![Aggregator::Count],
+            level: 0,
+            batch_count: 1,
+        };
+        let left = grouped(TypedVec::Raw(vec![RawVal::Str("x".to_string()), RawVal::Null]), vec![2, 1]);
+        let right = grouped(TypedVec::Raw(vec![RawVal::Str("x".to_string())]), vec![3]);
+        let result = combine(left, right, usize::MAX).unwrap();
+        assert_eq!(result.select[0].cast_ref_i64(), &[5, 1]);
+
+        let left = grouped(TypedVec::Boolean(BitVec::from_elem(1, true)), vec![1]);
+        let right = grouped(TypedVec::Integer(vec![1]), vec![1]);
+        assert!(combine(left, right, usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::{HashMap, HashSet};
+use std::usize;
+
+use ::QueryError;
+use bit_vec::BitVec;
+use engine::query_plan::{QueryPlan, QueryExecutor};
+use engine::query_plan;
+use engine::query_task;
+use engine::typed_vec::TypedVec;
+use engine::types::EncodingType;
+use mem_store::batch::Batch;
+use mem_store::column::Column;
+use mem_store::null_column::NullColumn;
+use syntax::expression::Expr;
+
+
+/// Evaluates `predicate` on all rows of `batch`. Columns that don't exist in `batch` are read as null.
+pub fn matching_rows(predicate: &Expr, batch: &Batch) -> Result<BitVec, QueryError> {
+    let len = batch.len();
+    let mut referenced_cols = HashSet::new();
+    predicate.add_colnames(&mut referenced_cols);
+    let null_cols = referenced_cols.iter()
+        .filter(|&col| !batch.cols().iter().any(|c| c.name() == col))
+        .map(|col| Column::new(col.clone(), Box::new(NullColumn::new(len))))
+        .collect::<Vec<_>>();
+    let readers = query_task::query_readers(batch, &referenced_cols);
+    let columns = batch.cols().iter()
+        .chain(null_cols.iter())
+        .chain(readers.iter())
+        .map(|col| (col.name(), col))
+        .collect::<HashMap<_, _>>();
+
+    let mut executor = QueryExecutor::default();
+    let (plan, plan_type) = QueryPlan::create_query_plan(predicate, &columns)?;
+    match plan_type.encoding_type() {
+        EncodingType::BitVec => {}
+        other => bail!(QueryError::TypeError, "Delete predicate {:?} has type {:?}, expected boolean", predicate, other),
+    }
+    let matches = query_plan::prepare(plan, &mut executor);
+    let mut results = executor.run(usize::MAX)?;
+    match results.collect(matches) {
+        TypedVec::Boolean(matches) => Ok(matches),
+        other => bail!(QueryError::FatalError, "Expected boolean vector, found {:?}", other.get_type()),
+    }
+}
@@ -0,0 +1,65 @@
+use engine::query_task::{QueryOutput, QueryStats};
+use ingest::raw_val::RawVal;
+use mem_store::table::{ColumnDescription, ColumnStats, TableStats};
+
+
+pub fn show_tables(mut tables: Vec<TableStats>) -> QueryOutput {
+    tables.sort_by(|t1, t2| t1.name.cmp(&t2.name));
+    QueryOutput {
+        colnames: vec!["name".to_string(), "rows".to_string(), "batches".to_string(), "bytes".to_string()],
+        rows: tables.into_iter()
+            .map(|t| vec![
+                RawVal::Str(t.name),
+                RawVal::Int((t.rows + t.buffer_length) as i64),
+                RawVal::Int(t.batches as i64),
+                RawVal::Int((t.batches_bytes + t.buffer_bytes) as i64),
+            ])
+            .collect(),
+        stats: QueryStats::default(),
+    }
+}
+
+pub fn describe(mut columns: Vec<ColumnDescription>) -> QueryOutput {
+    columns.sort_by(|c1, c2| c1.name.cmp(&c2.name));
+    QueryOutput {
+        colnames: vec!["column".to_string(), "type".to_string(), "codec".to_string(), "nullable".to_string()],
+        rows: columns.into_iter()
+            .map(|c| {
+                let types = c.types.iter().map(|t| format!("{:?}", t).to_lowercase()).collect::<Vec<_>>();
+                vec![
+                    RawVal::Str(c.name),
+                    RawVal::Str(types.join(", ")),
+                    RawVal::Str(c.codecs.join(", ")),
+                    RawVal::Str(c.nullable.to_string()),
+                ]
+            })
+            .collect(),
+        stats: QueryStats::default(),
+    }
+}
+
+pub fn show_stats(mut columns: Vec<ColumnStats>) -> QueryOutput {
+    columns.sort_by(|c1, c2| c1.name.cmp(&c2.name));
+    QueryOutput {
+        colnames: vec!["column".to_string(), "bytes".to_string(), "rows".to_string(),
+                       "batches".to_string(), "distinct".to_string()],
+        rows: columns.into_iter()
+            .map(|c| vec![
+                RawVal::Str(c.name),
+                RawVal::Int(c.bytes as i64),
+                RawVal::Int(c.rows as i64),
+                RawVal::Int(c.batches as i64),
+                RawVal::Int(c.distinct as i64),
+            ])
+            .collect(),
+        stats: QueryStats::default(),
+    }
+}
+
+pub fn deleted_rows(count: usize) -> QueryOutput {
+    QueryOutput {
+        colnames: vec!["deleted".to_string()],
+        rows: vec![vec![RawVal::Int(count as i64)]],
+        stats: QueryStats::default(),
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use bit_vec::BitVec;
+use engine::query_plan::{self, QueryExecutor, QueryPlan};
+use engine::query_task::query_readers;
+use engine::typed_vec::TypedVec;
+use ingest::raw_val::RawVal;
+use mem_store::batch::Batch;
+use mem_store::column::Column;
+use mem_store::point_codec::PointCodec;
+use mem_store::raw_col::RawCol;
+use syntax::expression::Expr;
+use ::QueryError;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JoinClause {
+    pub table: String,
+    pub fact_key: String,
+    pub dim_key: String,
+}
+
+/// Hash table over all rows of a (small) dimension table, keyed on the join column.
+pub struct DimensionTable {
+    int_index: HashMap<i64, usize>,
+    str_index: HashMap<String, usize>,
+    columns: Vec<(String, Vec<RawVal>)>,
+}
+
+/// Dimension columns produced by probing a single batch of the fact table.
+pub struct JoinedColumns {
+    pub cols: Vec<Column>,
+    /// Rows of the fact batch that found a match. `None` if all rows matched.
+    pub matches: Option<BitVec>,
+}
+
+impl DimensionTable {
+    pub fn build(batches: &[Batch], key: &str) -> Result<DimensionTable, QueryError> {
+        let mut dim = DimensionTable {
+            int_index: HashMap::new(),
+            str_index: HashMap::new(),
+            columns: Vec::new(),
+        };
+        let mut row_count = 0;
+        for batch in batches {
+            let len = batch.len();
+            let colnames = batch.cols().iter().map(|col| col.name().to_string()).collect();
+            let readers = query_readers(batch, &colnames);
+            for col in batch.cols() {
+                let col = readers.iter().find(|reader| reader.name() == col.name()).unwrap_or(col);
+                let values = read_column(col)?;
+                if col.name() == key {
+                    for i in 0..values.len() {
+                        if batch.deleted().map_or(false, |deleted| deleted[i]) {
+                            continue;
+                        }
+                        let new_entry = match values.get_raw(i) {
+                            RawVal::Int(int) => dim.int_index.insert(int, row_count + i).is_none(),
+                            RawVal::Str(string) => dim.str_index.insert(string, row_count + i).is_none(),
+                            RawVal::Null => true,
+                        };
+                        if !new_entry {
+                            bail!(QueryError::SchemaViolation, "Join key {} is not unique in dimension table", key)
+                        }
+                    }
+                } else {
+                    let index = match dim.columns.iter().position(|c| c.0 == col.name()) {
+                        Some(index) => index,
+                        None => {
+                            dim.columns.push((col.name().to_string(), vec![RawVal::Null; row_count]));
+                            dim.columns.len() - 1
+                        }
+                    };
+                    for i in 0..values.len() {
+                        dim.columns[index].1.push(values.get_raw(i));
+                    }
+                }
+            }
+            row_count += len;
+            for &mut (_, ref mut values) in &mut dim.columns {
+                values.resize(row_count, RawVal::Null);
+            }
+        }
+        Ok(dim)
+    }
+
+    pub fn colnames(&self) -> impl Iterator<Item=&str> {
+        self.columns.iter().map(|c| c.0.as_str())
+    }
+
+    /// Looks up the rows of `batch` in the dimension table, reading the join key from `key`. Only materializes the
+    /// dimension columns in `referenced_cols` that are not shadowed by a fact column of the same name.
+    pub fn probe(&self,
+                 batch: &Batch,
+                 key: Option<&Column>,
+                 referenced_cols: &HashSet<String>) -> Result<JoinedColumns, QueryError> {
+        let len = batch.cols().get(0).map_or(0, |c| c.len());
+        let rows = match key {
+            Some(col) => self.lookup_all(&read_column(col)?),
+            None => vec![None; len],
+        };
+
+        let mut matches = BitVec::with_capacity(len);
+        for row in &rows {
+            matches.push(row.is_some());
+        }
+        let cols = self.columns.iter()
+            .filter(|&&(ref name, _)| referenced_cols.contains(name) && !batch.cols().iter().any(|c| c.name() == name))
+            .map(|&(ref name, ref values)| {
+                let mut raw_col = RawCol::new();
+                for row in &rows {
+                    raw_col.push(row.map_or(RawVal::Null, |i| values[i].clone()));
+                }
+                Column::new(name.clone(), raw_col.finalize())
+            })
+            .collect();
+
+        Ok(JoinedColumns {
+            cols,
+            matches: if matches.all() { None } else { Some(matches) },
+        })
+    }
+
+    fn lookup_all(&self, keys: &TypedVec) -> Vec<Option<usize>> {
+        match *keys {
+            TypedVec::Integer(ref ints) => ints.iter().map(|i| self.int_index.get(i).cloned()).collect(),
+            TypedVec::String(ref strs) => strs.iter().map(|s| self.str_index.get(*s).cloned()).collect(),
+            TypedVec::EncodedU8(ref codes, codec) => self.lookup_encoded(codes, codec),
+            TypedVec::EncodedU16(ref codes, codec) => self.lookup_encoded(codes, codec),
+            TypedVec::EncodedU32(ref codes, codec) => self.lookup_encoded(codes, codec),
+            TypedVec::BorrowedEncodedU8(codes, codec) => self.lookup_encoded(codes, codec),
+            TypedVec::BorrowedEncodedU16(codes, codec) => self.lookup_encoded(codes, codec),
+            TypedVec::BorrowedEncodedU32(codes, codec) => self.lookup_encoded(codes, codec),
+            _ => (0..keys.len()).map(|i| self.lookup(keys.get_raw(i))).collect(),
+        }
+    }
+
+    /// Looks up encoded keys, decoding each distinct value only once.
+    fn lookup_encoded<T: Copy + Eq + Hash>(&self, codes: &[T], codec: &PointCodec<T>) -> Vec<Option<usize>> {
+        let mut rows = HashMap::new();
+        codes.iter()
+            .map(|&code| *rows.entry(code).or_insert_with(|| self.lookup(codec.to_raw(code))))
+            .collect()
+    }
+
+    fn lookup(&self, key: RawVal) -> Option<usize> {
+        match key {
+            RawVal::Int(int) => self.int_index.get(&int).cloned(),
+            RawVal::Str(string) => self.str_index.get(&string).cloned(),
+            RawVal::Null => None,
+        }
+    }
+}
+
+/// Reads all values of `column` through a query plan. Values of encoded columns stay encoded.
+fn read_column(column: &Column) -> Result<TypedVec, QueryError> {
+    let mut columns = HashMap::new();
+    columns.insert(column.name(), column);
+    let (plan, _) = QueryPlan::create_query_plan(&Expr::ColName(column.name().to_string()), &columns)?;
+    let mut executor = QueryExecutor::default();
+    let values = query_plan::prepare(plan, &mut executor);
+    Ok(executor.run(usize::MAX)?.collect(values))
+}
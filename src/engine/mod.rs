@@ -3,8 +3,12 @@ mod batch_merging;
 mod query_plan;
 pub mod vector_op;
 pub mod aggregator;
+pub mod delete;
 pub mod filter;
+pub mod introspection;
+pub mod join;
 pub mod query;
+pub mod query_cache;
 pub mod query_task;
 pub mod typed_vec;
 pub mod types;
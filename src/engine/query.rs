@@ -1,23 +1,33 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::iter::Iterator;
+use std::ptr;
+use std::{i64, usize};
 
 use ::QueryError;
+use bit_vec::BitVec;
 use engine::aggregator::*;
 use engine::batch_merging::*;
 use engine::filter::Filter;
-use engine::query_plan::{QueryPlan, QueryExecutor};
+use engine::join::JoinClause;
+use engine::query_plan::{Grouping, QueryPlan, QueryExecutor};
 use engine::query_plan;
+use engine::typed_vec::TypedVec;
 use engine::types::EncodingType;
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
 use mem_store::column::Column;
 use syntax::expression::*;
 use syntax::limit::*;
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Query {
     pub select: Vec<Expr>,
+    /// Deduplicates result rows by grouping on all selected columns
+    pub distinct: bool,
     pub table: String,
+    pub join: Option<JoinClause>,
     pub filter: Expr,
     pub aggregate: Vec<(Aggregator, Expr)>,
     pub order_by: Option<String>,
@@ -28,17 +38,12 @@ pub struct Query {
 
 impl Query {
     #[inline(never)] // produces more useful profiles
-    pub fn run<'a>(&self, columns: &HashMap<&'a str, &'a Column>) -> Result<BatchResult<'a>, QueryError> {
+    pub fn run<'a>(&self,
+               columns: &HashMap<&'a str, &'a Column>,
+               filter_mask: Option<&BitVec>,
+               memory_budget: usize) -> Result<BatchResult<'a>, QueryError> {
         let mut executor = QueryExecutor::default();
-
-        let (filter_plan, filter_type) = QueryPlan::create_query_plan(&self.filter, columns)?;
-        match filter_type.encoding_type() {
-            EncodingType::BitVec => {
-                let mut compiled_filter = query_plan::prepare(filter_plan, &mut executor);
-                executor.set_filter(Filter::BitVec(compiled_filter));
-            }
-            _ => {}
-        }
+        self.prepare_filter(columns, filter_mask, &mut executor)?;
 
         let mut select = Vec::new();
         if let Some(index) = self.order_by_index {
@@ -50,7 +55,8 @@ impl Query {
             let sort_indices = query_plan::prepare(
                 QueryPlan::SortIndices(
                     Box::new(QueryPlan::ReadBuffer(sort_column)),
-                    self.order_desc),
+                    self.order_desc,
+                    self.limit.combined().unwrap_or(usize::MAX)),
                 &mut executor);
             executor.new_stage();
             executor.set_filter(Filter::Indices(sort_indices));
@@ -64,12 +70,13 @@ impl Query {
         }
 
         //println!("{}", &executor);
-        let mut results = executor.run();
+        let mut results = executor.run(memory_budget)?;
         let select = select.into_iter().map(|i| results.collect(i)).collect();
 
         Ok(BatchResult {
             group_by: None,
             sort_by: self.order_by_index,
+            descending: self.order_desc,
             select,
             aggregators: Vec::with_capacity(0),
             level: 0,
@@ -78,47 +85,69 @@ impl Query {
     }
 
     #[inline(never)] // produces more useful profiles
-    pub fn run_aggregate<'a>(&self, columns: &HashMap<&'a str, &'a Column>) -> Result<BatchResult<'a>, QueryError> {
+    pub fn run_aggregate<'a>(&self,
+                         columns: &HashMap<&'a str, &'a Column>,
+                         filter_mask: Option<&BitVec>,
+                         memory_budget: usize) -> Result<BatchResult<'a>, QueryError> {
         trace_start!("run_aggregate");
 
-        let mut executor = QueryExecutor::default();
-
-        let (filter_plan, filter_type) = QueryPlan::create_query_plan(&self.filter, columns)?;
-        match filter_type.encoding_type() {
-            EncodingType::BitVec => {
-                let mut compiled_filter = query_plan::prepare(filter_plan, &mut executor);
-                executor.set_filter(Filter::BitVec(compiled_filter));
+        if let Some(result) = self.aggregate_runs(columns, filter_mask) {
+            if result.heap_size_of_children() > memory_budget {
+                bail!(QueryError::MemoryLimitExceeded,
+                      "aggregating runs of a single batch requires {} bytes, only {} bytes are available",
+                      result.heap_size_of_children(), memory_budget);
             }
-            _ => {}
+            return Ok(result);
         }
 
-        let (grouping_key_plan, grouping_key_type, max_grouping_key, decode_plans) =
-            QueryPlan::compile_grouping_key(&self.select, columns)?;
-        let raw_grouping_key = query_plan::prepare(grouping_key_plan, &mut executor);
-
-        let (encoded_group_by_column, grouping_key, aggregation_cardinality) =
-        // TODO(clemens): refine criterion
-        // TODO(clemens): can often collect group_by from non-zero positions in aggregation result
-            if max_grouping_key < 1 << 16 && grouping_key_type.is_positive_integer() {
-                let max_grouping_key_buf = executor.new_buffer();
-                (query_plan::prepare_unique(
-                    raw_grouping_key,
-                    grouping_key_type.encoding_type(),
-                    max_grouping_key as usize,
-                    &mut executor),
-                 raw_grouping_key,
-                 max_grouping_key_buf)
-            } else {
-                query_plan::prepare_hashmap_grouping(
-                    raw_grouping_key,
-                    grouping_key_type.encoding_type(),
-                    max_grouping_key as usize,
-                    &mut executor)
-            };
+        let mut executor = QueryExecutor::default();
+        self.prepare_filter(columns, filter_mask, &mut executor)?;
 
-        executor.set_encoded_group_by(encoded_group_by_column);
-        // TODO(clemens): fix for multiple groups
-        // let groups = groups.order_preserving();
+        // Upper bound on the number of groups
+        let row_count = columns.values().next().map_or(0, |column| column.len());
+        let (grouping_key, grouping_key_type, max_grouping_key, decode_plans) =
+            match QueryPlan::compile_grouping_key(&self.select, columns)? {
+                Grouping::Packed { plan, key_type, max_key, decode_plans } => {
+                    let raw_grouping_key = query_plan::prepare(plan, &mut executor);
+                    // TODO(clemens): refine criterion
+                    if max_key < 1 << 16 && key_type.is_positive_integer() {
+                        let unique = query_plan::prepare_unique(
+                            raw_grouping_key,
+                            key_type.encoding_type(),
+                            max_key as usize,
+                            &mut executor);
+                        executor.set_encoded_group_by(unique);
+                        (raw_grouping_key, key_type.encoding_type(), max_key as usize, decode_plans)
+                    } else {
+                        let (unique, grouping_key) = query_plan::prepare_hashmap_grouping(
+                            raw_grouping_key,
+                            key_type.encoding_type(),
+                            &mut executor);
+                        executor.set_encoded_group_by(unique);
+                        (grouping_key, EncodingType::U32, row_count, decode_plans)
+                    }
+                }
+                Grouping::Rows { columns: grouping_columns, packing } => {
+                    let mut inputs = Vec::with_capacity(grouping_columns.len());
+                    let mut codecs = Vec::with_capacity(grouping_columns.len());
+                    for (plan, plan_type) in grouping_columns {
+                        inputs.push(query_plan::prepare(plan, &mut executor));
+                        codecs.push(plan_type.codec);
+                    }
+                    let (grouping_key, representative_rows) =
+                        query_plan::prepare_row_grouping(inputs.clone(), packing, &mut executor);
+                    let decode_plans = inputs.into_iter().zip(codecs)
+                        .map(|(input, codec)| {
+                            let plan = QueryPlan::Gather(Box::new(QueryPlan::ReadBuffer(input)), representative_rows);
+                            match codec {
+                                Some(codec) => query_plan::decode_group_key(plan, codec),
+                                None => plan,
+                            }
+                        })
+                        .collect();
+                    (grouping_key, EncodingType::U32, row_count, decode_plans)
+                }
+            };
 
         let mut result = Vec::new();
         for &(aggregator, ref expr) in &self.aggregate {
@@ -129,8 +158,8 @@ impl Query {
                 plan,
                 plan_type,
                 grouping_key,
-                grouping_key_type.encoding_type(),
-                max_grouping_key as usize,
+                grouping_key_type,
+                max_grouping_key,
                 aggregator,
                 &mut executor)?;
             result.push(aggregate)
@@ -157,7 +186,7 @@ impl Query {
         executor.set_filter(Filter::Indices(sort_indices));*/
 
         //println!("{}", &executor);
-        let mut results = executor.run();
+        let mut results = executor.run(memory_budget)?;
         let select = result.into_iter().map(|i| results.collect(i)).collect();
         let group_by = grouping_columns.into_iter().map(|i| results.collect(i)).collect();
 
@@ -165,6 +194,7 @@ impl Query {
         Ok(BatchResult {
             group_by: Some(group_by),
             sort_by: None,
+            descending: false,
             select: select,
             aggregators: self.aggregate.iter().map(|x| x.0).collect(),
             level: 0,
@@ -172,6 +202,138 @@ impl Query {
         })
     }
 
+    /// Evaluates unfiltered counts and sums grouped by a single run-length encoded column directly on its runs.
+    fn aggregate_runs<'a>(&self,
+                          columns: &HashMap<&'a str, &'a Column>,
+                          filter_mask: Option<&BitVec>) -> Option<BatchResult<'a>> {
+        match self.filter {
+            Expr::Const(RawVal::Int(1)) if filter_mask.is_none() && self.select.len() == 1 => {}
+            _ => return None,
+        }
+        let colname = match self.select[0] {
+            Expr::ColName(ref colname) => colname,
+            _ => return None,
+        };
+        let (values, lengths) = columns.get(colname.as_str())?.data().runs()?;
+        let (group_by, counts, sums) = match values {
+            TypedVec::Integer(ref values) => {
+                let (keys, counts) = group_runs(values, &lengths);
+                let sums = keys.iter().zip(&counts).map(|(&key, &count)| key * count).collect::<Vec<_>>();
+                (TypedVec::Integer(keys), counts, Some(sums))
+            }
+            TypedVec::String(ref values) => {
+                let (keys, counts) = group_runs(values, &lengths);
+                (TypedVec::String(keys), counts, None)
+            }
+            _ => return None,
+        };
+
+        let mut select = Vec::with_capacity(self.aggregate.len());
+        for &(aggregator, ref expr) in &self.aggregate {
+            let aggregate = match (aggregator, expr) {
+                (Aggregator::Count, &Expr::Const(_)) => counts.clone(),
+                (Aggregator::Count, &Expr::ColName(ref name)) if columns.contains_key(name.as_str()) => counts.clone(),
+                (Aggregator::Sum, &Expr::ColName(ref name)) if name == colname => sums.clone()?,
+                _ => return None,
+            };
+            select.push(TypedVec::Integer(aggregate));
+        }
+        Some(BatchResult {
+            group_by: Some(vec![group_by]),
+            sort_by: None,
+            descending: false,
+            select,
+            aggregators: self.aggregate.iter().map(|x| x.0).collect(),
+            level: 0,
+            batch_count: 1,
+        })
+    }
+
+    /// Compiles the `where` clause and sets it as filter on `executor`.
+    /// `filter_mask` selects rows that are eligible independently of the query (e.g. rows with a join partner).
+    fn prepare_filter<'a>(&self,
+                          columns: &HashMap<&'a str, &'a Column>,
+                          filter_mask: Option<&BitVec>,
+                          executor: &mut QueryExecutor<'a>) -> Result<(), QueryError> {
+        if let Some((rows, residual)) = self.index_lookup(columns) {
+            let rows = match filter_mask {
+                Some(mask) => rows.into_iter().filter(|&row| mask.get(row).unwrap_or(false)).collect::<Vec<_>>(),
+                None => rows,
+            };
+            if let Some(residual) = residual {
+                let (residual_plan, residual_type) = QueryPlan::create_query_plan(&residual, columns)?;
+                if let EncodingType::BitVec = residual_type.encoding_type() {
+                    let len = columns.values().next().map_or(0, |column| column.len());
+                    let mut mask = BitVec::from_elem(len, false);
+                    for row in rows {
+                        mask.set(row, true);
+                    }
+                    let filter_plan = QueryPlan::And(Box::new(residual_plan), Box::new(QueryPlan::BitVecConstant(mask)));
+                    let compiled_filter = query_plan::prepare(filter_plan, executor);
+                    executor.set_filter(Filter::BitVec(compiled_filter));
+                    return Ok(());
+                }
+            }
+            let indices = query_plan::prepare(QueryPlan::IndicesConstant(rows), executor);
+            executor.set_filter(Filter::Indices(indices));
+            return Ok(());
+        }
+        let (filter_plan, filter_type) = QueryPlan::create_query_plan(&self.filter, columns)?;
+        let filter_plan = match (filter_type.encoding_type(), filter_mask) {
+            (EncodingType::BitVec, Some(mask)) =>
+                QueryPlan::And(Box::new(filter_plan), Box::new(QueryPlan::BitVecConstant(mask.clone()))),
+            (EncodingType::BitVec, None) => filter_plan,
+            (_, Some(mask)) => QueryPlan::BitVecConstant(mask.clone()),
+            (_, None) => return Ok(()),
+        };
+        let compiled_filter = query_plan::prepare(filter_plan, executor);
+        executor.set_filter(Filter::BitVec(compiled_filter));
+        Ok(())
+    }
+
+    /// Rows matching some conjuncts of the filter according to an index, and the remaining conjuncts, if any.
+    /// Uses string indexes for conjuncts of the form `column = 'value'`, and range lookups for comparisons of an
+    /// integer column with constants, intersecting the ranges of all such comparisons on the same column.
+    fn index_lookup(&self, columns: &HashMap<&str, &Column>) -> Option<(Vec<usize>, Option<Expr>)> {
+        let conjuncts = conjuncts(&self.filter);
+        for conjunct in &conjuncts {
+            let (rows, used) = if let Some((colname, value)) = string_equality(conjunct) {
+                match columns.get(colname).and_then(|column| column.data().lookup_str(value)) {
+                    Some(rows) => (rows, vec![*conjunct]),
+                    None => continue,
+                }
+            } else if let Some((colname, _, _)) = integer_range(conjunct) {
+                let ranges = conjuncts.iter()
+                    .filter_map(|&expr| match integer_range(expr) {
+                        Some((name, min, max)) if name == colname => Some((expr, min, max)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let min = ranges.iter().map(|&(_, min, _)| min).max().unwrap();
+                let max = ranges.iter().map(|&(_, _, max)| max).min().unwrap();
+                match columns.get(colname).and_then(|column| column.data().lookup_range(min, max)) {
+                    Some(rows) => (rows, ranges.into_iter().map(|(expr, _, _)| expr).collect()),
+                    None => continue,
+                }
+            } else {
+                continue;
+            };
+            let residual = conjuncts.iter()
+                .filter(|&&expr| !used.iter().any(|&used| ptr::eq(used, expr)))
+                .fold(None, |residual, &expr| Some(match residual {
+                    Some(residual) => Expr::Func(FuncType::And, Box::new(residual), Box::new(expr.clone())),
+                    None => expr.clone(),
+                }));
+            return Some((rows, residual));
+        }
+        None
+    }
+
+    /// Column name and value of filters of the form `column = 'value'`.
+    pub fn string_equality(&self) -> Option<(&str, &str)> {
+        string_equality(&self.filter)
+    }
+
     pub fn is_select_star(&self) -> bool {
         if self.select.len() == 1 {
             match self.select[0] {
@@ -183,6 +345,18 @@ impl Query {
         }
     }
 
+    /// Replaces time macros such as `$LAST_HOUR` with filters relative to `now` (in seconds).
+    pub fn resolve_time_macros(self, now: i64) -> Query {
+        Query {
+            select: self.select.into_iter().map(|expr| expr.resolve_time_macros(now)).collect(),
+            filter: self.filter.resolve_time_macros(now),
+            aggregate: self.aggregate.into_iter()
+                .map(|(aggregator, expr)| (aggregator, expr.resolve_time_macros(now)))
+                .collect(),
+            ..self
+        }
+    }
+
     pub fn result_column_names(&self) -> Vec<String> {
         let mut anon_columns = -1;
         let select_cols = self.select
@@ -222,4 +396,66 @@ impl Query {
     }
 }
 
+/// Distinct run values in ascending order, together with the total number of rows of each value.
+fn group_runs<T: Ord + Copy>(values: &[T], lengths: &[usize]) -> (Vec<T>, Vec<i64>) {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&run| values[run]);
+    let mut keys = Vec::new();
+    let mut counts = Vec::new();
+    for run in order {
+        if keys.last() == Some(&values[run]) {
+            *counts.last_mut().unwrap() += lengths[run] as i64;
+        } else {
+            keys.push(values[run]);
+            counts.push(lengths[run] as i64);
+        }
+    }
+    (keys, counts)
+}
 
+/// Operands of nested `And` expressions, or `expr` itself.
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match *expr {
+        Expr::Func(FuncType::And, ref lhs, ref rhs) => {
+            let mut result = conjuncts(lhs);
+            result.extend(conjuncts(rhs));
+            result
+        }
+        ref expr => vec![expr],
+    }
+}
+
+/// Column name and value of expressions of the form `column = 'value'`.
+fn string_equality(expr: &Expr) -> Option<(&str, &str)> {
+    match *expr {
+        Expr::Func(FuncType::Equals, ref lhs, ref rhs) => match (&**lhs, &**rhs) {
+            (&Expr::ColName(ref name), &Expr::Const(RawVal::Str(ref value))) |
+            (&Expr::Const(RawVal::Str(ref value)), &Expr::ColName(ref name)) => Some((name, value)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Column name and inclusive bounds of expressions that compare an integer column with a constant.
+fn integer_range(expr: &Expr) -> Option<(&str, i64, i64)> {
+    let (ftype, lhs, rhs) = match *expr {
+        Expr::Func(ftype, ref lhs, ref rhs) => (ftype, &**lhs, &**rhs),
+        _ => return None,
+    };
+    let (ftype, name, value) = match (lhs, rhs) {
+        (&Expr::ColName(ref name), &Expr::Const(RawVal::Int(value))) => (ftype, name, value),
+        (&Expr::Const(RawVal::Int(value)), &Expr::ColName(ref name)) => match ftype {
+            FuncType::LT => (FuncType::GT, name, value),
+            FuncType::GT => (FuncType::LT, name, value),
+            ftype => (ftype, name, value),
+        },
+        _ => return None,
+    };
+    match ftype {
+        FuncType::Equals => Some((name, value, value)),
+        FuncType::LT => Some((name, i64::MIN, value.checked_sub(1)?)),
+        FuncType::GT => Some((name, value.checked_add(1)?, i64::MAX)),
+        _ => None,
+    }
+}
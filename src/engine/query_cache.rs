@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use engine::batch_merging::{BatchResult, OwnedBatchResult};
+use engine::query::Query;
+use engine::query_task::QueryOutput;
+use heapsize::HeapSizeOf;
+use syntax::expression::Expr;
+
+
+/// Queries with time macros such as `$LAST_HOUR` reuse results computed within the same interval of this many seconds.
+const TIME_MACRO_RESOLUTION_SECS: i64 = 60;
+
+/// Canonical form of a query, equal for queries that differ only in formatting or in the order of operands of
+/// commutative functions. Time macros remain symbolic, so repeated queries with time macros have the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueryKey {
+    query: Query,
+    /// Interval the time macros of the query were resolved in, `None` if the query contains no time macros
+    time_bucket: Option<i64>,
+    dimension_versions: Vec<usize>,
+}
+
+impl QueryKey {
+    /// `now` is the time in seconds that the time macros of `query` are resolved with.
+    pub fn new(query: &Query, now: i64, dimension_versions: Vec<usize>) -> QueryKey {
+        let query = Query {
+            select: query.select.iter().map(Expr::canonical).collect(),
+            filter: query.filter.canonical(),
+            aggregate: query.aggregate.iter().map(|&(aggregator, ref expr)| (aggregator, expr.canonical())).collect(),
+            ..query.clone()
+        };
+        let has_time_macros = query.filter.has_time_macros()
+            || query.select.iter().chain(query.aggregate.iter().map(|&(_, ref expr)| expr)).any(Expr::has_time_macros);
+        QueryKey {
+            query,
+            time_bucket: if has_time_macros { Some(now / TIME_MACRO_RESOLUTION_SECS) } else { None },
+            dimension_versions,
+        }
+    }
+}
+
+/// Identifies the result of a query on a specific version of the tables it reads.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    query: QueryKey,
+    table_versions: Vec<usize>,
+}
+
+impl CacheKey {
+    pub fn new(query: QueryKey, table_versions: Vec<usize>) -> CacheKey {
+        CacheKey { query, table_versions }
+    }
+
+    pub fn query(&self) -> &QueryKey { &self.query }
+}
+
+/// Identifies the partial result of a query on a single batch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PartialKey {
+    query: QueryKey,
+    /// Selected expressions after expanding `select *`, which depends on the columns of all queried batches
+    select: Vec<Expr>,
+    batch: usize,
+}
+
+impl PartialKey {
+    pub fn new(query: &QueryKey, select: &[Expr], batch: usize) -> PartialKey {
+        PartialKey { query: query.clone(), select: select.to_vec(), batch }
+    }
+}
+
+/// Stores results of recent queries and their partial results on individual batches, so that repeated queries only
+/// scan batches that were added since. Evicts the least recently used entries once `max_bytes` is exceeded.
+pub struct QueryCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry<QueryOutput>>,
+    partials: HashMap<PartialKey, CacheEntry<OwnedBatchResult>>,
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    hits: usize,
+    misses: usize,
+    partial_hits: usize,
+}
+
+struct CacheEntry<T> {
+    value: T,
+    bytes: usize,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+    /// Partial results on single batches that were reused
+    pub partial_hits: usize,
+    pub partial_entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+}
+
+impl QueryCache {
+    pub fn new(max_bytes: usize) -> QueryCache {
+        QueryCache {
+            state: Mutex::new(CacheState { max_bytes, ..CacheState::default() }),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<QueryOutput> {
+        let mut state = self.state.lock().unwrap();
+        let output = state.touch(|state| &mut state.entries, key).map(|output| output.clone());
+        if output.is_some() { state.hits += 1 } else { state.misses += 1 }
+        output
+    }
+
+    /// Caches `output` unless it is larger than the cache itself.
+    pub fn insert(&self, key: CacheKey, output: QueryOutput) {
+        let bytes = output.rows.heap_size_of_children() + output.colnames.heap_size_of_children();
+        self.state.lock().unwrap().insert(|state| &mut state.entries, key, output, bytes);
+    }
+
+    pub fn get_partial(&self, key: &PartialKey) -> Option<BatchResult<'static>> {
+        let mut state = self.state.lock().unwrap();
+        let result = state.touch(|state| &mut state.partials, key).map(OwnedBatchResult::to_result);
+        if result.is_some() { state.partial_hits += 1 }
+        result
+    }
+
+    pub fn insert_partial(&self, key: PartialKey, result: OwnedBatchResult) {
+        let bytes = result.heap_size_of_children();
+        self.state.lock().unwrap().insert(|state| &mut state.partials, key, result, bytes);
+    }
+
+    /// Whether partial results are worth computing in a form that can be cached.
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().max_bytes > 0
+    }
+
+    /// Changes the size bound of the cache, evicting entries if necessary. A size of 0 disables caching.
+    pub fn set_max_bytes(&self, max_bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.max_bytes = max_bytes;
+        state.evict();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+            partial_hits: state.partial_hits,
+            partial_entries: state.partials.len(),
+            bytes: state.bytes,
+            max_bytes: state.max_bytes,
+        }
+    }
+}
+
+impl CacheState {
+    /// Marks the entry for `key` in the map returned by `entries` as used and returns its value.
+    fn touch<'a, K, T, F>(&'a mut self, entries: F, key: &K) -> Option<&'a T>
+        where K: Eq + Hash + 'a, F: Fn(&mut CacheState) -> &mut HashMap<K, CacheEntry<T>> {
+        self.tick += 1;
+        let tick = self.tick;
+        entries(self).get_mut(key).map(|entry| {
+            entry.last_used = tick;
+            &entry.value
+        })
+    }
+
+    /// Inserts `value` into the map returned by `entries` unless it is larger than the cache itself.
+    fn insert<K, T, F>(&mut self, entries: F, key: K, value: T, bytes: usize)
+        where K: Eq + Hash, F: Fn(&mut CacheState) -> &mut HashMap<K, CacheEntry<T>> {
+        if bytes > self.max_bytes { return; }
+        self.tick += 1;
+        let entry = CacheEntry { value, bytes, last_used: self.tick };
+        if let Some(previous) = entries(self).insert(key, entry) {
+            self.bytes -= previous.bytes;
+        }
+        self.bytes += bytes;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.max_bytes {
+            let oldest_entry = self.entries.iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(key, entry)| (key.clone(), entry.last_used));
+            let oldest_partial = self.partials.iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(key, entry)| (key.clone(), entry.last_used));
+            let bytes = match (oldest_entry, oldest_partial) {
+                (Some((key, last_used)), Some((_, partial_last_used))) if last_used < partial_last_used =>
+                    self.entries.remove(&key).unwrap().bytes,
+                (_, Some((key, _))) => self.partials.remove(&key).unwrap().bytes,
+                (Some((key, _)), None) => self.entries.remove(&key).unwrap().bytes,
+                (None, None) => unreachable!("cache holds {} bytes without entries", self.bytes),
+            };
+            self.bytes -= bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::query_task::QueryStats;
+    use ingest::raw_val::RawVal;
+    use syntax::parser;
+    use syntax::statement::Statement;
+
+    fn output(value: &str) -> QueryOutput {
+        QueryOutput {
+            colnames: Vec::new(),
+            rows: vec![vec![RawVal::Str(value.to_string())]],
+            stats: QueryStats::default(),
+        }
+    }
+
+    fn query_key(query: &str, now: i64) -> QueryKey {
+        match parser::parse(query) {
+            Ok(Statement::Select(mut queries)) => QueryKey::new(&queries.pop().unwrap(), now, Vec::new()),
+            _ => panic!("Expected select query: {}", query),
+        }
+    }
+
+    fn key(column: &str) -> CacheKey {
+        CacheKey::new(query_key(&format!("select {} from t;", column), 0), vec![0])
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let entry_bytes = output("a").rows.heap_size_of_children();
+        let cache = QueryCache::new(2 * entry_bytes);
+        cache.insert(key("a"), output("a"));
+        cache.insert(key("b"), output("b"));
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), output("c"));
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("c")).is_some());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn test_query_key() {
+        assert_eq!(query_key("select a from t where (b = 1) and (c > 2);", 0),
+                   query_key("SELECT a FROM t WHERE (c > 2) AND (1 = b);", 0));
+        assert!(query_key("select a from t where b < 1;", 0) != query_key("select a from t where 1 < b;", 0));
+        assert_eq!(query_key("select a from t where $LAST_HOUR;", 120), query_key("select a from t where $LAST_HOUR;", 179));
+        assert!(query_key("select a from t where $LAST_HOUR;", 120) != query_key("select a from t where $LAST_HOUR;", 180));
+        assert!(query_key("select a from t where $LAST_HOUR;", 0) != query_key("select a from t where $LAST_DAY;", 0));
+        assert_eq!(query_key("select a from t;", 0), query_key("select a from t;", 1000));
+    }
+}
@@ -1,9 +1,12 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::i64;
 
 use ::QueryError;
+use bit_vec::BitVec;
 use engine::aggregator::Aggregator;
 use engine::filter::Filter;
+use engine::typed_vec::TypedVec;
 use engine::types::*;
 use engine::vector_op::*;
 use engine::vector_op::vector_operator::BufferRef;
@@ -21,6 +24,7 @@ pub enum QueryPlan<'a> {
     // TODO(clemens): make it possible to replace this with Decode(ReadColumn)
 
     DecodeWith(Box<QueryPlan<'a>>, &'a ColumnCodec),
+    WrapEncoded(Box<QueryPlan<'a>>, &'a ColumnCodec),
     TypeConversion(Box<QueryPlan<'a>>, EncodingType, EncodingType),
 
     EncodeStrConstant(Box<QueryPlan<'a>>, &'a ColumnCodec),
@@ -28,17 +32,25 @@ pub enum QueryPlan<'a> {
 
     BitPack(Box<QueryPlan<'a>>, Box<QueryPlan<'a>>, i64),
     BitUnpack(Box<QueryPlan<'a>>, u8, u8),
+    AddConstant(Box<QueryPlan<'a>>, i64),
+    Gather(Box<QueryPlan<'a>>, BufferRef),
 
     LessThanVS(EncodingType, Box<QueryPlan<'a>>, Box<QueryPlan<'a>>),
+    GreaterThanVS(EncodingType, Box<QueryPlan<'a>>, Box<QueryPlan<'a>>),
     EqualsVS(EncodingType, Box<QueryPlan<'a>>, Box<QueryPlan<'a>>),
     And(Box<QueryPlan<'a>>, Box<QueryPlan<'a>>),
     Or(Box<QueryPlan<'a>>, Box<QueryPlan<'a>>),
 
-    SortIndices(Box<QueryPlan<'a>>, bool),
+    SortIndices(Box<QueryPlan<'a>>, bool, usize),
+    /// Compares encoded values of a column with the range `min..=max` without materializing them.
+    /// Falls back to the original comparison if rows are filtered.
+    RangeMask(&'a ColumnCodec, i64, i64, Box<QueryPlan<'a>>),
 
     EncodedGroupByPlaceholder,
 
     Constant(RawVal),
+    BitVecConstant(BitVec),
+    IndicesConstant(Vec<usize>),
 }
 
 pub struct QueryExecutor<'a> {
@@ -81,12 +93,13 @@ impl<'a> QueryExecutor<'a> {
         self.stages.last_mut().unwrap().filter = filter;
     }
 
-    pub fn run(&mut self) -> Scratchpad<'a> {
+    /// Executes all operators, failing as soon as the buffers hold more than `memory_budget` bytes.
+    pub fn run(&mut self, memory_budget: usize) -> Result<Scratchpad<'a>, QueryError> {
         let mut scratchpad = Scratchpad::new(self.count);
         for stage in &mut self.stages {
-            stage.run(&mut scratchpad);
+            stage.run(&mut scratchpad, memory_budget)?;
         }
-        scratchpad
+        Ok(scratchpad)
     }
 }
 
@@ -116,10 +129,17 @@ impl<'a> ExecutorStage<'a> {
         self.ops.push(op);
     }
 
-    fn run(&mut self, scratchpad: &mut Scratchpad<'a>) {
+    fn run(&mut self, scratchpad: &mut Scratchpad<'a>, memory_budget: usize) -> Result<(), QueryError> {
         for op in &mut self.ops {
             op.execute(scratchpad);
+            let heap_size = scratchpad.take_peak_heap_size();
+            if heap_size > memory_budget {
+                bail!(QueryError::MemoryLimitExceeded,
+                      "buffers for a single batch require {} bytes, only {} bytes are available",
+                      heap_size, memory_budget);
+            }
         }
+        Ok(())
     }
 }
 
@@ -136,7 +156,12 @@ pub fn prepare<'a>(plan: QueryPlan<'a>, result: &mut QueryExecutor<'a>) -> Buffe
             Filter::Indices(filter) => Box::new(IndexEncoded::new(col, filter, result.new_buffer())),
         }
         QueryPlan::Constant(ref c) => Box::new(Constant::new(c.clone(), result.new_buffer())),
+        QueryPlan::BitVecConstant(bit_vec) =>
+            Box::new(VectorConstant { val: TypedVec::Boolean(bit_vec), output: result.new_buffer() }),
+        QueryPlan::IndicesConstant(indices) =>
+            Box::new(VectorConstant { val: TypedVec::USize(indices), output: result.new_buffer() }),
         QueryPlan::DecodeWith(plan, codec) => Box::new(DecodeWith::new(prepare(*plan, result), result.new_buffer(), codec)),
+        QueryPlan::WrapEncoded(plan, codec) => Box::new(WrapEncoded::new(prepare(*plan, result), result.new_buffer(), codec)),
         QueryPlan::TypeConversion(plan, initial_type, target_type) =>
             VecOperator::type_conversion(prepare(*plan, result), result.new_buffer(), initial_type, target_type),
         QueryPlan::EncodeStrConstant(plan, codec) =>
@@ -147,8 +172,14 @@ pub fn prepare<'a>(plan: QueryPlan<'a>, result: &mut QueryExecutor<'a>) -> Buffe
             VecOperator::bit_shift_left_add(prepare(*lhs, result), prepare(*rhs, result), result.new_buffer(), shift_amount),
         QueryPlan::BitUnpack(inner, shift, width) =>
             VecOperator::bit_unpack(prepare(*inner, result), result.new_buffer(), shift, width),
+        QueryPlan::AddConstant(inner, constant) =>
+            VecOperator::add_constant(prepare(*inner, result), result.new_buffer(), constant),
+        QueryPlan::Gather(plan, indices) =>
+            VecOperator::gather(prepare(*plan, result), indices, result.new_buffer()),
         QueryPlan::LessThanVS(left_type, lhs, rhs) =>
             VecOperator::less_than_vs(left_type, prepare(*lhs, result), prepare(*rhs, result), result.new_buffer()),
+        QueryPlan::GreaterThanVS(left_type, lhs, rhs) =>
+            VecOperator::greater_than_vs(left_type, prepare(*lhs, result), prepare(*rhs, result), result.new_buffer()),
         QueryPlan::EqualsVS(left_type, lhs, rhs) =>
             VecOperator::equals_vs(left_type, prepare(*lhs, result), prepare(*rhs, result), result.new_buffer()),
         QueryPlan::Or(lhs, rhs) => {
@@ -168,8 +199,12 @@ pub fn prepare<'a>(plan: QueryPlan<'a>, result: &mut QueryExecutor<'a>) -> Buffe
             return inplace;
         }
         QueryPlan::EncodedGroupByPlaceholder => return result.encoded_group_by().unwrap(),
-        QueryPlan::SortIndices(plan, descending) =>
-            VecOperator::sort_indices(prepare(*plan, result), result.new_buffer(), descending),
+        QueryPlan::SortIndices(plan, descending, limit) =>
+            VecOperator::sort_indices(prepare(*plan, result), result.new_buffer(), descending, limit),
+        QueryPlan::RangeMask(col, min, max, comparison) => match result.filter() {
+            Filter::None => Box::new(RangeMask::new(col, min, max, result.new_buffer())),
+            Filter::BitVec(_) | Filter::Indices(_) => return prepare(*comparison, result),
+        }
         QueryPlan::ReadBuffer(buffer) => return buffer,
     };
     result.push(operation);
@@ -185,16 +220,25 @@ pub fn prepare_unique(raw_grouping_key: BufferRef,
     output
 }
 
+/// Returns the sorted unique grouping keys and the (dense, `U32`) group index of each row.
 pub fn prepare_hashmap_grouping(raw_grouping_key: BufferRef,
                                 grouping_key_type: EncodingType,
-                                max_cardinality: usize,
-                                result: &mut QueryExecutor) -> (BufferRef, BufferRef, BufferRef) {
+                                result: &mut QueryExecutor) -> (BufferRef, BufferRef) {
     let unique_out = result.new_buffer();
     let grouping_key_out = result.new_buffer();
-    let cardinality_out = result.new_buffer();
     result.push(VecOperator::hash_map_grouping(
-        raw_grouping_key, unique_out, grouping_key_out, cardinality_out, grouping_key_type, max_cardinality));
-    (unique_out, grouping_key_out, cardinality_out)
+        raw_grouping_key, unique_out, grouping_key_out, grouping_key_type));
+    (unique_out, grouping_key_out)
+}
+
+/// Returns the (dense, `U32`) group index of each row and the index of one row per group, both in group order.
+pub fn prepare_row_grouping(columns: Vec<BufferRef>,
+                            packing: Option<Vec<(i64, u32)>>,
+                            result: &mut QueryExecutor) -> (BufferRef, BufferRef) {
+    let grouping_key_out = result.new_buffer();
+    let representative_rows_out = result.new_buffer();
+    result.push(VecOperator::row_grouping(columns, packing, grouping_key_out, representative_rows_out));
+    (grouping_key_out, representative_rows_out)
 }
 
 // TODO(clemens): add QueryPlan::Aggregation and merge with prepare function
@@ -232,6 +276,42 @@ pub fn prepare_aggregation<'a, 'b>(plan: QueryPlan<'a>,
     Ok(output_location)
 }
 
+/// Strategy for determining the group of each row.
+pub enum Grouping<'a> {
+    /// All grouping columns are packed into a single integer per row, ordered in the same way as the grouping columns.
+    /// `decode_plans` reconstruct the grouping columns from the unique keys (`EncodedGroupByPlaceholder`).
+    Packed {
+        plan: QueryPlan<'a>,
+        key_type: Type<'a>,
+        max_key: i64,
+        decode_plans: Vec<QueryPlan<'a>>,
+    },
+    /// Grouping columns that don't fit into 64 bits are grouped in a hash table, keyed either by a `u128` with the
+    /// given `(offset, width)` for each column or by an order preserving byte string.
+    Rows {
+        columns: Vec<(QueryPlan<'a>, Type<'a>)>,
+        packing: Option<Vec<(i64, u32)>>,
+    },
+}
+
+fn bits_required(max: i64) -> u32 {
+    64 - (max as u64).leading_zeros()
+}
+
+fn to_i64(plan: QueryPlan, encoding_type: EncodingType) -> QueryPlan {
+    match encoding_type {
+        EncodingType::I64 => plan,
+        t => QueryPlan::TypeConversion(Box::new(plan), t, EncodingType::I64),
+    }
+}
+
+fn from_i64(plan: QueryPlan, encoding_type: EncodingType) -> QueryPlan {
+    match encoding_type {
+        EncodingType::I64 => plan,
+        t => QueryPlan::TypeConversion(Box::new(plan), EncodingType::I64, t),
+    }
+}
+
 pub fn order_preserving<'a>(typed_plan: (QueryPlan<'a>, Type<'a>)) -> (QueryPlan<'a>, Type<'a>) {
     if typed_plan.1.is_order_preserving() {
         typed_plan
@@ -240,6 +320,24 @@ pub fn order_preserving<'a>(typed_plan: (QueryPlan<'a>, Type<'a>)) -> (QueryPlan
     }
 }
 
+/// Like `order_preserving`, but keeps codes of shared dictionaries which can be grouped across batches.
+fn groupable<'a>(typed_plan: (QueryPlan<'a>, Type<'a>)) -> (QueryPlan<'a>, Type<'a>) {
+    if typed_plan.1.codec.map_or(false, |c| c.shared_dictionary_id().is_some()) {
+        typed_plan
+    } else {
+        order_preserving(typed_plan)
+    }
+}
+
+/// Decodes grouping keys, except for codes of shared dictionaries which are only decoded for the final output.
+pub fn decode_group_key<'a>(plan: QueryPlan<'a>, codec: &'a ColumnCodec) -> QueryPlan<'a> {
+    if codec.shared_dictionary_id().is_some() {
+        QueryPlan::WrapEncoded(Box::new(plan), codec)
+    } else {
+        QueryPlan::DecodeWith(Box::new(plan), codec)
+    }
+}
+
 impl<'a> QueryPlan<'a> {
     pub fn create_query_plan<'b>(expr: &Expr,
                                  columns: &HashMap<&'b str, &'b Column>) -> Result<(QueryPlan<'b>, Type<'b>), QueryError> {
@@ -263,8 +361,9 @@ impl<'a> QueryPlan<'a> {
                     (BasicType::Integer, BasicType::Integer) => {
                         let plan = if type_rhs.is_scalar {
                             if type_lhs.is_encoded() {
-                                let encoded = QueryPlan::EncodeIntConstant(Box::new(plan_rhs), type_lhs.codec.unwrap());
-                                QueryPlan::LessThanVS(type_lhs.encoding_type(), Box::new(plan_lhs), Box::new(encoded))
+                                let encoded = QueryPlan::EncodeIntConstant(Box::new(plan_rhs.clone()), type_lhs.codec.unwrap());
+                                let plan = QueryPlan::LessThanVS(type_lhs.encoding_type(), Box::new(plan_lhs.clone()), Box::new(encoded));
+                                plan.with_range_mask(&plan_lhs, &plan_rhs, |value| (i64::MIN, value.saturating_sub(1)))
                             } else {
                                 QueryPlan::LessThanVS(type_lhs.encoding_type(), Box::new(plan_lhs), Box::new(plan_rhs))
                             }
@@ -273,9 +372,32 @@ impl<'a> QueryPlan<'a> {
                         };
                         (plan, Type::new(BasicType::Boolean, None).mutable())
                     }
+                    (BasicType::Null, _) | (_, BasicType::Null) => QueryPlan::null_comparison(&plan_lhs, &plan_rhs)?,
                     _ => bail!(QueryError::TypeError, "{:?} < {:?}", type_lhs, type_rhs)
                 }
             }
+            Func(GT, ref lhs, ref rhs) => {
+                let (plan_lhs, type_lhs) = QueryPlan::create_query_plan(lhs, columns)?;
+                let (plan_rhs, type_rhs) = QueryPlan::create_query_plan(rhs, columns)?;
+                match (type_lhs.decoded, type_rhs.decoded) {
+                    (BasicType::Integer, BasicType::Integer) => {
+                        let plan = if type_rhs.is_scalar {
+                            if type_lhs.is_encoded() {
+                                let encoded = QueryPlan::EncodeIntConstant(Box::new(plan_rhs.clone()), type_lhs.codec.unwrap());
+                                let plan = QueryPlan::GreaterThanVS(type_lhs.encoding_type(), Box::new(plan_lhs.clone()), Box::new(encoded));
+                                plan.with_range_mask(&plan_lhs, &plan_rhs, |value| (value.saturating_add(1), i64::MAX))
+                            } else {
+                                QueryPlan::GreaterThanVS(type_lhs.encoding_type(), Box::new(plan_lhs), Box::new(plan_rhs))
+                            }
+                        } else {
+                            bail!(QueryError::NotImplemented, "> operator only implemented for column > constant")
+                        };
+                        (plan, Type::new(BasicType::Boolean, None).mutable())
+                    }
+                    (BasicType::Null, _) | (_, BasicType::Null) => QueryPlan::null_comparison(&plan_lhs, &plan_rhs)?,
+                    _ => bail!(QueryError::TypeError, "{:?} > {:?}", type_lhs, type_rhs)
+                }
+            }
             Func(Equals, ref lhs, ref rhs) => {
                 let (plan_lhs, type_lhs) = QueryPlan::create_query_plan(lhs, columns)?;
                 let (plan_rhs, type_rhs) = QueryPlan::create_query_plan(rhs, columns)?;
@@ -296,8 +418,9 @@ impl<'a> QueryPlan<'a> {
                     (BasicType::Integer, BasicType::Integer) => {
                         let plan = if type_rhs.is_scalar {
                             if type_lhs.is_encoded() {
-                                let encoded = QueryPlan::EncodeIntConstant(Box::new(plan_rhs), type_lhs.codec.unwrap());
-                                QueryPlan::EqualsVS(type_lhs.encoding_type(), Box::new(plan_lhs), Box::new(encoded))
+                                let encoded = QueryPlan::EncodeIntConstant(Box::new(plan_rhs.clone()), type_lhs.codec.unwrap());
+                                let plan = QueryPlan::EqualsVS(type_lhs.encoding_type(), Box::new(plan_lhs.clone()), Box::new(encoded));
+                                plan.with_range_mask(&plan_lhs, &plan_rhs, |value| (value, value))
                             } else {
                                 QueryPlan::EqualsVS(type_lhs.encoding_type(), Box::new(plan_lhs), Box::new(plan_rhs))
                             }
@@ -306,6 +429,7 @@ impl<'a> QueryPlan<'a> {
                         };
                         (plan, Type::new(BasicType::Boolean, None).mutable())
                     }
+                    (BasicType::Null, _) | (_, BasicType::Null) => QueryPlan::null_comparison(&plan_lhs, &plan_rhs)?,
                     _ => bail!(QueryError::TypeError, "{:?} = {:?}", type_lhs, type_rhs)
                 }
             }
@@ -331,76 +455,121 @@ impl<'a> QueryPlan<'a> {
     }
 
     pub fn compile_grouping_key<'b>(exprs: &[Expr],
-                                    columns: &HashMap<&'b str, &'b Column>) -> Result<(QueryPlan<'b>, Type<'b>, i64, Vec<QueryPlan<'b>>), QueryError> {
-        if exprs.len() == 1 {
-            QueryPlan::create_query_plan(&exprs[0], columns)
-                .map(|(gk_plan, gk_type)| {
-                    let max_cardinality = QueryPlan::encoding_range(&gk_plan).map_or(1 << 63, |i| i.1);
-                    let decoded_group_by = gk_type.codec.map_or(
-                        QueryPlan::EncodedGroupByPlaceholder,
-                        |codec| QueryPlan::DecodeWith(
-                            Box::new(QueryPlan::EncodedGroupByPlaceholder),
-                            codec));
-                    (gk_plan.clone(), gk_type, max_cardinality, vec![decoded_group_by])
-                })
-        } else if exprs.len() == 2 {
-            let mut total_width = 0;
-            let mut largest_key = 0;
-            let mut plan = None;
-            let mut decode_plans = Vec::with_capacity(exprs.len());
-            for expr in exprs.iter().rev() {
-                let (query_plan, plan_type) = QueryPlan::create_query_plan(expr, columns)?;
-                // TODO(clemens): Potentially subtract min if min is negative or this makes grouping key fit into 64 bits
-                if let Some((min, max)) = QueryPlan::encoding_range(&query_plan) {
-                    if min < 0 {
-                        plan = None;
-                        break;
-                    }
-                    let query_plan = QueryPlan::TypeConversion(Box::new(query_plan),
-                                                               plan_type.encoding_type(),
-                                                               EncodingType::I64);
-                    let bits = (max as f64).log2().floor() as i64 + 1;
-                    if total_width == 0 {
-                        plan = Some(query_plan);
-                    } else {
-                        plan = plan.map(|plan|
-                            QueryPlan::BitPack(Box::new(plan), Box::new(query_plan), total_width));
-                    }
+                                    columns: &HashMap<&'b str, &'b Column>) -> Result<Grouping<'b>, QueryError> {
+        if exprs.is_empty() {
+            bail!(QueryError::NotImplemented, "Aggregation without grouping columns")
+        }
+        let mut plans = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            let (plan, plan_type) = groupable(QueryPlan::create_query_plan(expr, columns)?);
+            if plan_type.is_scalar {
+                bail!(QueryError::NotImplemented, "Grouping by constant {:?}", expr)
+            }
+            plans.push((plan, plan_type));
+        }
 
-                    let mut decode_plan = QueryPlan::BitUnpack(
-                        Box::new(QueryPlan::EncodedGroupByPlaceholder),
-                        total_width as u8,
-                        bits as u8);
-                    decode_plan = QueryPlan::TypeConversion(
-                        Box::new(decode_plan),
-                        EncodingType::I64,
-                        plan_type.encoding_type());
-                    if let Some(codec) = plan_type.codec {
-                        decode_plan = QueryPlan::DecodeWith(
-                            Box::new(decode_plan),
-                            codec)
-                    }
-                    decode_plans.push(decode_plan);
+        let ranges = match plans.iter().map(|plan| plan.0.encoding_range()).collect::<Option<Vec<_>>>() {
+            Some(ranges) => ranges,
+            None => return Ok(Grouping::Rows { columns: plans, packing: None }),
+        };
+        if plans.len() == 1 && ranges[0].0 >= 0 {
+            let (plan, plan_type) = plans.pop().unwrap();
+            let decoded_group_by = plan_type.codec.map_or(
+                QueryPlan::EncodedGroupByPlaceholder,
+                |codec| decode_group_key(QueryPlan::EncodedGroupByPlaceholder, codec));
+            return Ok(Grouping::Packed {
+                plan,
+                key_type: plan_type,
+                max_key: ranges[0].1,
+                decode_plans: vec![decoded_group_by],
+            });
+        }
 
-                    largest_key += max << total_width;
-                    total_width += bits;
-                } else {
-                    plan = None;
-                    break;
-                }
-            }
+        let widths = match ranges.iter().map(|&(min, max)| max.checked_sub(min).map(bits_required)).collect::<Option<Vec<_>>>() {
+            Some(widths) => widths,
+            // Range of a column spans more than 63 bits
+            None => return Ok(Grouping::Rows { columns: plans, packing: None }),
+        };
+        let total_width = widths.iter().sum::<u32>();
+        if total_width > 63 {
+            let packing = if total_width <= 128 {
+                Some(ranges.iter().zip(&widths).map(|(&(min, _), &width)| (min, width)).collect())
+            } else {
+                None
+            };
+            return Ok(Grouping::Rows { columns: plans, packing });
+        }
 
-            if let Some(plan) = plan {
-                if total_width <= 64 {
-                    decode_plans.reverse();
-                    return Ok((plan, Type::new(BasicType::Integer, None), largest_key, decode_plans));
-                }
+        // The first column occupies the most significant bits, so keys sort in the same order as the grouping columns
+        let mut plan = None;
+        let mut total_width = 0;
+        let mut largest_key = 0;
+        let mut decode_plans = Vec::with_capacity(exprs.len());
+        for ((query_plan, plan_type), (&(min, max), &width)) in plans.into_iter().zip(ranges.iter().zip(&widths)).rev() {
+            let mut query_plan = to_i64(query_plan, plan_type.encoding_type());
+            if min != 0 {
+                query_plan = QueryPlan::AddConstant(Box::new(query_plan), -min);
             }
-            // TODO(clemens): add u8, u16, u32, u128 grouping keys
-            // TODO(clemens): implement general case using bites slice as grouping key
-            bail!(QueryError::NotImplemented, "Failed to pack group by columns into 64 bit value")
-        } else {
-            bail!(QueryError::NotImplemented, "Can only group by one or two columns. Actual: {}", exprs.len())
+            plan = Some(match plan {
+                None => query_plan,
+                Some(plan) => QueryPlan::BitPack(Box::new(plan), Box::new(query_plan), i64::from(total_width)),
+            });
+
+            let mut decode_plan = QueryPlan::BitUnpack(
+                Box::new(QueryPlan::EncodedGroupByPlaceholder),
+                total_width as u8,
+                width as u8);
+            if min != 0 {
+                decode_plan = QueryPlan::AddConstant(Box::new(decode_plan), min);
+            }
+            decode_plan = from_i64(decode_plan, plan_type.encoding_type());
+            if let Some(codec) = plan_type.codec {
+                decode_plan = decode_group_key(decode_plan, codec)
+            }
+            decode_plans.push(decode_plan);
+
+            largest_key += (max - min) << total_width;
+            total_width += width;
+        }
+        decode_plans.reverse();
+        Ok(Grouping::Packed {
+            plan: plan.unwrap(),
+            key_type: Type::new(BasicType::Integer, None),
+            max_key: largest_key,
+            decode_plans,
+        })
+    }
+
+    /// Replaces this comparison of column `lhs` with constant `rhs` by a `RangeMask` if the codec of the column supports
+    /// it. `bounds` maps the encoded constant to the range of encoded values that pass the comparison.
+    fn with_range_mask(self, lhs: &QueryPlan<'a>, rhs: &QueryPlan<'a>, bounds: fn(i64) -> (i64, i64)) -> QueryPlan<'a> {
+        match (lhs, rhs) {
+            (&QueryPlan::ReadColumn(codec), &QueryPlan::Constant(RawVal::Int(value))) if codec.supports_range_mask() =>
+                match codec.encode_int(value) {
+                    RawVal::Int(encoded) => {
+                        let (min, max) = bounds(encoded);
+                        QueryPlan::RangeMask(codec, min, max, Box::new(self))
+                    }
+                    _ => self,
+                },
+            _ => self,
+        }
+    }
+
+    /// Comparisons involving null are never true.
+    fn null_comparison<'b>(lhs: &QueryPlan<'b>, rhs: &QueryPlan<'b>) -> Result<(QueryPlan<'b>, Type<'b>), QueryError> {
+        match lhs.len().or_else(|| rhs.len()) {
+            Some(len) => Ok((QueryPlan::BitVecConstant(BitVec::from_elem(len, false)), Type::bit_vec())),
+            None => bail!(QueryError::NotImplemented, "Comparison of null with {:?}", rhs),
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        use self::QueryPlan::*;
+        match *self {
+            ReadColumn(codec) => Some(codec.len()),
+            DecodeColumn(col) => Some(col.len()),
+            _ => None,
         }
     }
 
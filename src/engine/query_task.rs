@@ -1,19 +1,33 @@
 use std::cmp;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::iter::Iterator;
 use std::mem;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::time::Duration;
+use std::usize;
 
 use ::QueryError;
+use bit_vec::BitVec;
 use QueryResult;
 use engine::aggregator::*;
 use engine::batch_merging::*;
+use engine::join::{DimensionTable, JoinedColumns};
 use engine::query::Query;
+use engine::query_cache::{PartialKey, QueryCache, QueryKey};
+use engine::typed_vec::TypedVec;
+use heapsize::HeapSizeOf;
+use futures::{Async, Future, Never, Poll};
+use futures::executor::{Executor, SpawnError};
+use futures::task::{Context, LocalMap, Wake, Waker};
+use futures_channel::mpsc::{SendError, Sender};
 use ingest::raw_val::RawVal;
 use mem_store::batch::Batch;
 use mem_store::column::Column;
+use mem_store::null_column::NullColumn;
+use mem_store::zone_map;
 use scheduler::*;
 use syntax::expression::*;
 use time::precise_time_ns;
@@ -22,10 +36,21 @@ use time::precise_time_ns;
 pub struct QueryTask {
     query: Query,
     batches: Vec<Batch>,
+    dimension: Option<DimensionTable>,
+    /// Dimension columns of each batch, which are probed when the batch is scanned
+    joined: Vec<OnceLock<JoinedColumns>>,
+    null_cols: Vec<Vec<Column>>,
+    /// Columns that are read through a query reader instead, e.g. because they are block-compressed
+    readers: Vec<Vec<Column>>,
     referenced_cols: HashSet<String>,
     output_colnames: Vec<String>,
     aggregate: Vec<Aggregator>,
     start_time_ns: u64,
+    cancellation: CancellationHandle,
+    timeout: Option<Duration>,
+    memory_limit: Option<usize>,
+    /// Bytes held by partial results, query readers and queued stream chunks of all threads
+    memory_used: AtomicUsize,
 
     // Lifetime is not actually static, but tied to the lifetime of this struct.
     // There is currently no good way to express this constraint in Rust.
@@ -34,7 +59,42 @@ pub struct QueryTask {
     unsafe_state: Mutex<QueryState<'static>>,
     batch_index: AtomicUsize,
     completed: AtomicBool,
-    sender: SharedSender<QueryResult>,
+    sink: ResultSink,
+    /// Set for streaming queries, which are scheduled again through this handle after they were parked
+    resume_handle: OnceLock<(Weak<QueryTask>, Weak<InnerRuba>)>,
+    /// Whether a streaming query waits for its client to receive earlier chunks
+    parked: AtomicBool,
+    /// Cache of results on individual batches and the key of this query
+    partial_cache: Option<(Arc<QueryCache>, QueryKey)>,
+}
+
+/// Destination of query results.
+pub enum ResultSink {
+    /// Sends the full result once the query has completed.
+    Complete(SharedSender<QueryResult>),
+    /// Sends rows in chunks as they become available and closes the channel after the last chunk.
+    /// The last chunk contains the final query stats. While the channel is full, workers stop taking new batches
+    /// and the query is parked until the client has received earlier chunks.
+    Stream(Mutex<ChunkQueue>),
+}
+
+impl ResultSink {
+    pub fn stream(sender: Sender<QueryResult>) -> ResultSink {
+        ResultSink::Stream(Mutex::new(ChunkQueue {
+            sender,
+            chunks: VecDeque::new(),
+            close: false,
+        }))
+    }
+}
+
+/// Chunks of a streaming query that wait for room in the channel to the client.
+pub struct ChunkQueue {
+    sender: Sender<QueryResult>,
+    /// Chunks with the number of bytes they account for in the memory used by the query
+    chunks: VecDeque<(QueryResult, usize)>,
+    /// Whether the channel is closed once all queued chunks have been sent
+    close: bool,
 }
 
 pub struct QueryState<'a> {
@@ -42,19 +102,33 @@ pub struct QueryState<'a> {
     partial_results: Vec<BatchResult<'a>>,
     rows_scanned: usize,
     rows_collected: usize,
+    batches_skipped: usize,
 }
 
+#[derive(Clone)]
 pub struct QueryOutput {
     pub colnames: Vec<String>,
     pub rows: Vec<Vec<RawVal>>,
     pub stats: QueryStats,
 }
 
+impl QueryOutput {
+    /// Result of statements that don't return any rows, e.g. `DROP TABLE`.
+    pub fn empty() -> QueryOutput {
+        QueryOutput {
+            colnames: Vec::new(),
+            rows: Vec::new(),
+            stats: QueryStats::default(),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct QueryStats {
     pub runtime_ns: u64,
     pub rows_scanned: usize,
+    /// Batches that were not scanned because their zone maps show that they cannot contribute to the result
+    pub batches_skipped: usize,
 }
 
 impl Default for QueryStats {
@@ -62,16 +136,35 @@ impl Default for QueryStats {
         QueryStats {
             runtime_ns: 0,
             rows_scanned: 0,
+            batches_skipped: 0,
         }
     }
 }
 
 
 impl QueryTask {
-    pub fn new(mut query: Query, source: Vec<Batch>, sender: SharedSender<QueryResult>) -> QueryTask {
+    pub fn new(mut query: Query,
+               source: Vec<Batch>,
+               dimension: Option<Vec<Batch>>,
+               sink: ResultSink,
+               cancellation: CancellationHandle,
+               timeout: Option<Duration>,
+               memory_limit: Option<usize>) -> Result<QueryTask, QueryError> {
         let start_time_ns = precise_time_ns();
+        let dimension = match (query.join.as_ref(), dimension) {
+            (Some(join), Some(dimension)) => Some(DimensionTable::build(&dimension, &join.dim_key)?),
+            _ => None,
+        };
+        let dimension_cols = dimension.as_ref()
+            .map_or_else(Vec::new, |d| d.colnames().map(|name| name.to_string()).collect());
+        let mut all_cols = find_all_cols(&source);
+        for col in &dimension_cols {
+            if !all_cols.contains(col) {
+                all_cols.push(col.clone());
+            }
+        }
         if query.is_select_star() {
-            query.select = find_all_cols(&source).into_iter().map(Expr::ColName).collect();
+            query.select = all_cols.iter().cloned().map(Expr::ColName).collect();
         }
 
         let output_colnames = query.result_column_names();
@@ -84,54 +177,169 @@ impl QueryTask {
             }
         }
         query.order_by_index = order_by_index;
-        let referenced_cols = query.find_referenced_cols();
+        let mut referenced_cols = query.find_referenced_cols();
+        if let Some(ref join) = query.join {
+            referenced_cols.insert(join.fact_key.clone());
+        }
         let aggregate = query.aggregate.iter().map(|&(aggregate, _)| aggregate).collect();
 
-        QueryTask {
+        // Batches that lack a column which exists in other batches (e.g. when querying multiple tables) read it as null
+        let null_cols = source.iter()
+            .map(|batch| {
+                let len = batch.cols().get(0).map_or(0, |c| c.len());
+                all_cols.iter()
+                    .filter(|&col| referenced_cols.contains(col))
+                    .filter(|&col| !batch.cols().iter().any(|c| c.name() == col))
+                    .filter(|&col| !dimension_cols.contains(col))
+                    .map(|col| Column::new(col.clone(), Box::new(NullColumn::new(len))))
+                    .collect()
+            })
+            .collect();
+
+        let readers = source.iter()
+            .map(|batch| query_readers(batch, &referenced_cols))
+            .collect::<Vec<_>>();
+        let readers_size = readers.iter().flat_map(|cols| cols).map(|col| col.heap_size_of_children()).sum();
+
+        let joined = if dimension.is_some() { source.iter().map(|_| OnceLock::new()).collect() } else { Vec::new() };
+
+        Ok(QueryTask {
             query,
             batches: source,
+            dimension,
+            joined,
+            null_cols,
+            readers,
             referenced_cols,
             output_colnames,
             aggregate,
             start_time_ns,
+            cancellation,
+            timeout,
+            memory_limit,
+            memory_used: AtomicUsize::new(readers_size),
 
             unsafe_state: Mutex::new(QueryState {
                 partial_results: Vec::new(),
                 completed_batches: 0,
                 rows_scanned: 0,
                 rows_collected: 0,
+                batches_skipped: 0,
             }),
             batch_index: AtomicUsize::new(0),
             completed: AtomicBool::new(false),
-            sender,
+            sink,
+            resume_handle: OnceLock::new(),
+            parked: AtomicBool::new(false),
+            partial_cache: None,
+        })
+    }
+
+    /// Prepares a streaming query for scheduling. Instead of occupying workers while the client falls behind,
+    /// the query is parked and scheduled on `ruba` again once the client has received earlier chunks.
+    pub fn into_stream_task(self, ruba: &Arc<InnerRuba>) -> Arc<QueryTask> {
+        let task = Arc::new(self);
+        let _ = task.resume_handle.set((Arc::downgrade(&task), Arc::downgrade(ruba)));
+        task
+    }
+
+    /// Reuses the partial results that earlier runs of the same query computed on any of the batches, and caches
+    /// the partial results of all other batches.
+    pub fn with_partial_cache(mut self, cache: &Arc<QueryCache>, query: &QueryKey) -> QueryTask {
+        if cache.is_enabled() {
+            self.partial_cache = Some((cache.clone(), query.clone()));
         }
+        self
     }
 
     pub fn run(&self) {
+        self.scan_batches();
+        self.flush_chunks();
+    }
+
+    fn scan_batches(&self) {
+        if self.batches.is_empty() {
+            self.push_empty_result();
+            return;
+        }
         let mut rows_scanned = 0;
         let mut rows_collected = 0;
+        let mut batches_skipped = 0;
         let mut batch_results = Vec::<BatchResult>::new();
         while let Some((batch, id)) = self.next_batch() {
             trace_start!("Batch {}", id);
-            rows_scanned += batch.cols().get(0).map_or(0, |c| c.len());
-            let batch = QueryTask::prepare_batch(&self.referenced_cols, batch);
-            let mut batch_result = match if self.aggregate.is_empty() {
-                self.query.run(&batch)
-            } else {
-                self.query.run_aggregate(&batch)
-            } {
-                Ok(result) => result,
-                Err(error) => {
+            if let Some(error) = self.interruption() {
+                self.fail_with(error);
+                return;
+            }
+            if !self.may_match(batch) || self.can_prune(batch, &batch_results) {
+                if self.streams_rows() {
+                    if let Err(error) = self.push_chunk(None, 0, 1) {
+                        self.fail_with(error);
+                        return;
+                    }
+                    if self.completed.load(Ordering::SeqCst) {
+                        return;
+                    }
+                } else {
+                    batches_skipped += 1;
+                }
+                continue;
+            }
+            let batch_rows = batch.cols().get(0).map_or(0, |c| c.len());
+            let partial_key = self.partial_cache.as_ref()
+                .map(|&(_, ref query)| PartialKey::new(query, &self.query.select, batch.id()));
+            let cached = match (self.partial_cache.as_ref(), partial_key.as_ref()) {
+                (Some(&(ref cache, _)), Some(key)) => cache.get_partial(key),
+                _ => None,
+            };
+            let scanned = cached.is_none();
+            let mut batch_result = match cached {
+                Some(cached) => cached,
+                None => match self.run_batch(batch, id, partial_key) {
+                    Ok(result) => result,
+                    Err(error) => {
+                        self.fail_with(error);
+                        return;
+                    }
+                },
+            };
+            if self.streams_rows() {
+                if let Err(error) = self.push_chunk(Some(&batch_result), batch_rows, 0) {
                     self.fail_with(error);
                     return;
                 }
-            };
+                if self.completed.load(Ordering::SeqCst) {
+                    return;
+                }
+                continue;
+            }
+            if scanned {
+                rows_scanned += batch_rows;
+            }
             rows_collected += batch_result.len();
+            let mut batch_result_size = batch_result.heap_size_of_children();
+            if let Err(error) = self.track_memory(batch_result_size, 0) {
+                self.fail_with(error);
+                return;
+            }
 
             // Merge only with previous batch results of same level to get O(n log n) complexity
             while let Some(br) = batch_results.pop() {
                 if br.level == batch_result.level {
-                    batch_result = combine(br, batch_result, self.combined_limit());
+                    let freed = br.heap_size_of_children() + batch_result_size;
+                    batch_result = match combine(br, batch_result, self.combined_limit()) {
+                        Ok(combined) => combined,
+                        Err(error) => {
+                            self.fail_with(error);
+                            return;
+                        }
+                    };
+                    batch_result_size = batch_result.heap_size_of_children();
+                    if let Err(error) = self.track_memory(batch_result_size, freed) {
+                        self.fail_with(error);
+                        return;
+                    }
                 } else {
                     batch_results.push(br);
                     break;
@@ -142,74 +350,402 @@ impl QueryTask {
             if self.completed.load(Ordering::SeqCst) {
                 return;
             }
-            if self.sufficient_rows(rows_collected) {
+            if self.sufficient_rows(rows_collected) || self.sufficient_distinct_rows(&batch_results) {
                 break;
             }
         }
 
-        if let Some(result) = QueryTask::combine_results(batch_results, self.combined_limit()) {
-            self.push_result(result, rows_scanned, rows_collected);
+        let result = match self.combine_results(batch_results) {
+            Ok(result) => result,
+            Err(error) => {
+                self.fail_with(error);
+                return;
+            }
+        };
+        if result.is_some() || batches_skipped > 0 {
+            self.push_result(result, rows_scanned, rows_collected, batches_skipped);
+        }
+    }
+
+    /// Runs the query on a single batch. If `partial_key` is set, the result is decoded and cached under that key.
+    fn run_batch<'a>(&'a self, batch: &'a Batch, id: usize, partial_key: Option<PartialKey>) -> Result<BatchResult<'a>, QueryError> {
+        let mut columns = QueryTask::prepare_batch(&self.referenced_cols, batch);
+        let joined = self.joined(batch, id)?;
+        if let Some(joined) = joined {
+            for col in &joined.cols {
+                columns.insert(col.name(), col);
+            }
+        }
+        let filter_mask = filter_mask(batch, joined);
+        let filter_mask = filter_mask.as_ref();
+        for col in self.null_cols[id].iter().chain(&self.readers[id]) {
+            columns.insert(col.name(), col);
+        }
+        let readers_size = self.readers_size(id);
+        let result = if self.aggregate.is_empty() && !self.query.distinct {
+            self.query.run(&columns, filter_mask, self.memory_budget())?
+        } else {
+            self.query.run_aggregate(&columns, filter_mask, self.memory_budget())?
+        };
+        // Blocks decompressed by readers are held until the query completes
+        self.track_memory(self.readers_size(id) - readers_size, 0)?;
+        match (self.partial_cache.as_ref(), partial_key) {
+            (Some(&(ref cache, _)), Some(key)) => {
+                let owned = OwnedBatchResult::from_result(result)?;
+                let result = owned.to_result();
+                cache.insert_partial(key, owned);
+                Ok(result)
+            }
+            _ => Ok(result),
         }
     }
 
-    fn combine_results(batch_results: Vec<BatchResult>, limit: usize) -> Option<BatchResult> {
-        let mut full_result = None;
+    /// Combines partial results into one, accounting for the memory held by the combined results.
+    fn combine_results<'a>(&self, batch_results: Vec<BatchResult<'a>>) -> Result<Option<BatchResult<'a>>, QueryError> {
+        let mut full_result: Option<BatchResult> = None;
         for batch_result in batch_results {
             if let Some(partial) = full_result {
-                full_result = Some(combine(partial, batch_result, limit));
+                let freed = partial.heap_size_of_children() + batch_result.heap_size_of_children();
+                let combined = combine(partial, batch_result, self.combined_limit())?;
+                self.track_memory(combined.heap_size_of_children(), freed)?;
+                full_result = Some(combined);
             } else {
                 full_result = Some(batch_result);
             }
         }
-        full_result
+        Ok(full_result)
     }
 
-    fn push_result(&self, result: BatchResult, rows_scanned: usize, rows_collected: usize) {
+    fn push_result(&self,
+                   result: Option<BatchResult>,
+                   rows_scanned: usize,
+                   rows_collected: usize,
+                   batches_skipped: usize) {
         let mut state = self.unsafe_state.lock().unwrap();
         if self.completed.load(Ordering::SeqCst) { return; }
-        state.completed_batches += result.batch_count;
+        state.completed_batches += result.as_ref().map_or(0, |r| r.batch_count) + batches_skipped;
+        state.batches_skipped += batches_skipped;
         state.rows_scanned += rows_scanned;
         state.rows_collected += rows_collected;
-        unsafe {
-            let result = mem::transmute::<_, BatchResult<'static>>(result);
-            state.partial_results.push(result);
+        if let Some(result) = result {
+            unsafe {
+                let result = mem::transmute::<_, BatchResult<'static>>(result);
+                state.partial_results.push(result);
+            }
         }
-        if state.completed_batches == self.batches.len() || self.sufficient_rows(state.rows_collected) {
+        if state.completed_batches == self.batches.len()
+            || self.sufficient_rows(state.rows_collected)
+            || self.sufficient_distinct_rows(&state.partial_results) {
             let mut owned_results = Vec::with_capacity(0);
             mem::swap(&mut owned_results, &mut state.partial_results);
-            // TODO(clemens): Handle empty table
-            let full_result = QueryTask::combine_results(owned_results, self.combined_limit()).unwrap();
-            let final_result = self.convert_to_output_format(&full_result, state.rows_scanned);
-            self.sender.send(Ok(final_result));
+            let final_result = match self.combine_results(owned_results) {
+                Ok(Some(full_result)) => order_by_group_key(full_result)
+                    .map(|full_result| self.convert_to_output_format(&full_result, &state)),
+                // All batches were skipped
+                Ok(None) => Ok(QueryOutput {
+                    colnames: self.output_colnames.clone(),
+                    rows: Vec::new(),
+                    stats: self.stats(&state),
+                }),
+                Err(error) => Err(error),
+            };
+            self.send_final(final_result);
             self.completed.store(true, Ordering::SeqCst);
         }
     }
 
+    /// Sends the rows of a single batch to a streaming client, skipping rows before the offset and after the limit.
+    /// `result` is `None` for skipped batches. Fails if queued chunks exceed the memory limit of the query.
+    fn push_chunk(&self, result: Option<&BatchResult>, rows_scanned: usize, batches_skipped: usize) -> Result<(), QueryError> {
+        let mut state = self.unsafe_state.lock().unwrap();
+        if self.completed.load(Ordering::SeqCst) { return Ok(()); }
+        state.completed_batches += result.map_or(0, |r| r.batch_count) + batches_skipped;
+        state.batches_skipped += batches_skipped;
+        state.rows_scanned += rows_scanned;
+
+        let mut rows = Vec::new();
+        if let Some(result) = result {
+            let offset = self.query.limit.offset as usize;
+            let start = offset.saturating_sub(state.rows_collected);
+            let end = cmp::min(result.len(), self.combined_limit().saturating_sub(state.rows_collected));
+            state.rows_collected += result.len();
+            if start < end {
+                rows = self.collect_rows(result, start, end - start);
+            }
+        }
+
+        let finished = state.completed_batches == self.batches.len() || state.rows_collected >= self.combined_limit();
+        if !rows.is_empty() || finished {
+            let chunk = Ok(QueryOutput {
+                colnames: self.output_colnames.clone(),
+                rows,
+                stats: self.stats(&state),
+            });
+            if finished {
+                self.send_final(chunk);
+                self.completed.store(true, Ordering::SeqCst);
+            } else if let ResultSink::Stream(ref queue) = self.sink {
+                let size = chunk_size(&chunk);
+                // Queued while holding the state lock so that the final chunk is always sent last
+                queue.lock().unwrap().chunks.push_back((chunk, size));
+                self.track_memory(size, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the full result, or queues the last chunk of a streaming query.
+    fn send_final(&self, result: QueryResult) {
+        match self.sink {
+            ResultSink::Complete(ref sender) => sender.send(result),
+            ResultSink::Stream(ref queue) => {
+                let mut queue = queue.lock().unwrap();
+                let size = chunk_size(&result);
+                self.memory_used.fetch_add(size, Ordering::SeqCst);
+                queue.chunks.push_back((result, size));
+                queue.close = true;
+            }
+        }
+    }
+
+    /// Sends queued chunks of a streaming query without waiting for the client.
+    /// Returns false if chunks remain queued because the channel is full, in which case the query is parked until
+    /// the client has received earlier chunks, or if the client is gone.
+    fn flush_chunks(&self) -> bool {
+        let mut queue = match self.sink {
+            ResultSink::Stream(ref queue) => queue.lock().unwrap(),
+            ResultSink::Complete(_) => return true,
+        };
+        while !queue.chunks.is_empty() {
+            // Set before polling since the client may wake the query before `poll_client` returns
+            self.parked.store(true, Ordering::SeqCst);
+            match self.poll_client(&mut queue.sender) {
+                Ok(Async::Ready(())) => {
+                    self.parked.store(false, Ordering::SeqCst);
+                    let (chunk, size) = queue.chunks.pop_front().unwrap();
+                    let _ = queue.sender.start_send(chunk);
+                    self.memory_used.fetch_sub(size, Ordering::SeqCst);
+                }
+                Ok(Async::Pending) => return false,
+                Err(_) => {
+                    self.parked.store(false, Ordering::SeqCst);
+                    let queued = queue.chunks.drain(..).map(|(_, size)| size).sum();
+                    self.memory_used.fetch_sub(queued, Ordering::SeqCst);
+                    self.completed.store(true, Ordering::SeqCst);
+                    self.batch_index.store(self.batches.len(), Ordering::SeqCst);
+                    return false;
+                }
+            }
+        }
+        if queue.close {
+            queue.sender.close_channel();
+            queue.close = false;
+        }
+        true
+    }
+
+    /// Checks whether the channel to a streaming client has room for another chunk.
+    /// If it does not, the query is scheduled again once the client has received a chunk.
+    fn poll_client(&self, sender: &mut Sender<QueryResult>) -> Poll<(), SendError> {
+        let (task, ruba) = self.resume_handle.get()
+            .and_then(|(task, ruba)| task.upgrade().map(|task| (task, ruba.clone())))
+            .expect("streaming queries must be scheduled through QueryTask::into_stream_task");
+        let waker = Waker::from(Arc::new(Resume { task, ruba }));
+        let mut local_map = LocalMap::new();
+        let mut executor = NoSpawn;
+        let mut cx = Context::new(&mut local_map, &waker, &mut executor);
+        sender.poll_ready(&mut cx)
+    }
+
+    fn has_queued_chunks(&self) -> bool {
+        match self.sink {
+            ResultSink::Stream(ref queue) => !queue.lock().unwrap().chunks.is_empty(),
+            ResultSink::Complete(_) => false,
+        }
+    }
+
+    fn push_empty_result(&self) {
+        let _state = self.unsafe_state.lock().unwrap();
+        if self.completed.load(Ordering::SeqCst) { return; }
+        self.send_final(Ok(QueryOutput {
+            colnames: self.output_colnames.clone(),
+            rows: Vec::new(),
+            stats: QueryStats {
+                runtime_ns: precise_time_ns() - self.start_time_ns,
+                ..QueryStats::default()
+            },
+        }));
+        self.completed.store(true, Ordering::SeqCst);
+    }
+
     fn fail_with(&self, error: QueryError) {
         let mut _state = self.unsafe_state.lock().unwrap();
         if self.completed.load(Ordering::SeqCst) { return; }
         self.completed.store(true, Ordering::SeqCst);
         self.batch_index.store(self.batches.len(), Ordering::SeqCst);
-        self.sender.send(Err(error));
+        self.send_final(Err(error));
+    }
+
+    /// Error to abort the query with if it was cancelled or ran past its timeout.
+    fn interruption(&self) -> Option<QueryError> {
+        if self.cancellation.is_cancelled() {
+            return Some(QueryError::Cancelled);
+        }
+        match self.timeout {
+            Some(timeout) => {
+                let timeout_ns = timeout.as_secs() * 1_000_000_000 + u64::from(timeout.subsec_nanos());
+                if precise_time_ns() - self.start_time_ns > timeout_ns {
+                    Some(QueryError::Timeout(timeout_ns / 1_000_000))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    /// Memory that is still available for the buffers used to process a single batch.
+    fn memory_budget(&self) -> usize {
+        match self.memory_limit {
+            Some(limit) => limit.saturating_sub(self.memory_used.load(Ordering::SeqCst)),
+            None => usize::MAX,
+        }
+    }
+
+    /// Accounts for memory held by partial results, query readers and queued stream chunks.
+    /// Fails if the query uses more than its memory limit.
+    fn track_memory(&self, allocated: usize, freed: usize) -> Result<(), QueryError> {
+        let used = self.memory_used.fetch_add(allocated, Ordering::SeqCst) + allocated - freed;
+        self.memory_used.fetch_sub(freed, Ordering::SeqCst);
+        match self.memory_limit {
+            Some(limit) if used > limit => bail!(QueryError::MemoryLimitExceeded,
+                                                 "query requires {} bytes, limit is {} bytes", used, limit),
+            _ => Ok(()),
+        }
+    }
+
+    fn readers_size(&self, id: usize) -> usize {
+        self.readers[id].iter().map(|col| col.heap_size_of_children()).sum()
     }
 
     fn sufficient_rows(&self, rows_collected: usize) -> bool {
-        let unordered_select = self.query.aggregate.is_empty() && self.query.order_by.is_none();
-        unordered_select && self.combined_limit() < rows_collected
+        self.unordered_select() && self.combined_limit() < rows_collected
+    }
+
+    fn unordered_select(&self) -> bool {
+        self.query.aggregate.is_empty() && !self.query.distinct && self.query.order_by.is_none()
+    }
+
+    /// Rows of unordered selects are sent to streaming clients batch by batch without merging.
+    fn streams_rows(&self) -> bool {
+        match self.sink {
+            ResultSink::Stream(_) => self.unordered_select(),
+            ResultSink::Complete(_) => false,
+        }
+    }
+
+    /// Unordered distinct queries can stop as soon as a single partial result holds enough distinct rows.
+    fn sufficient_distinct_rows(&self, partial_results: &[BatchResult]) -> bool {
+        let unordered_distinct = self.query.distinct && self.query.aggregate.is_empty() && self.query.order_by.is_none();
+        unordered_distinct && partial_results.iter().any(|result| result.len() >= self.combined_limit())
+    }
+
+    /// Returns false if the zone maps or string indexes of `batch` show that none of its rows pass the filter.
+    fn may_match(&self, batch: &Batch) -> bool {
+        if let Some((colname, value)) = self.query.string_equality() {
+            let rows = batch.cols().iter()
+                .find(|col| col.name() == colname)
+                .and_then(|col| col.data().lookup_str(value));
+            if rows.map_or(false, |rows| rows.is_empty()) {
+                return false;
+            }
+        }
+        let zone_maps = batch.cols().iter()
+            .filter(|col| self.referenced_cols.contains(col.name()))
+            .filter_map(|col| col.zone_map().map(|zone_map| (col.name(), zone_map)))
+            .collect();
+        zone_map::may_match(&self.query.filter, &zone_maps)
+    }
+
+    /// Returns true if no row of `batch` can be part of the result of an `ORDER BY ... LIMIT` query
+    /// because a partial result already holds enough rows that sort before or equal to all of them.
+    fn can_prune(&self, batch: &Batch, partial_results: &[BatchResult]) -> bool {
+        let limit = self.combined_limit();
+        if limit == 0 || !self.query.aggregate.is_empty() || self.query.distinct {
+            return false;
+        }
+        let index = match self.query.order_by_index {
+            Some(index) => index,
+            None => return false,
+        };
+        let range = match self.query.select[index] {
+            Expr::ColName(ref name) => batch.cols().iter()
+                .find(|col| col.name() == name)
+                .and_then(|col| col.data().range()),
+            _ => None,
+        };
+        let (min, max) = match range {
+            Some(range) => range,
+            None => return false,
+        };
+        partial_results.iter()
+            .filter(|result| result.len() >= limit)
+            .any(|result| match result.select[index] {
+                TypedVec::Integer(ref sorted) if self.query.order_desc => max <= sorted[limit - 1],
+                TypedVec::Integer(ref sorted) => min >= sorted[limit - 1],
+                _ => false,
+            })
+    }
+
+    /// Probes the dimension table with batch `id` the first time it is scanned. The join key is read like any other
+    /// column of the query.
+    fn joined(&self, batch: &Batch, id: usize) -> Result<Option<&JoinedColumns>, QueryError> {
+        let (dimension, join) = match (self.dimension.as_ref(), self.query.join.as_ref()) {
+            (Some(dimension), Some(join)) => (dimension, join),
+            _ => return Ok(None),
+        };
+        if self.joined[id].get().is_none() {
+            let key = self.readers[id].iter()
+                .chain(&self.null_cols[id])
+                .chain(batch.cols())
+                .find(|col| col.name() == join.fact_key);
+            let _ = self.joined[id].set(dimension.probe(batch, key, &self.referenced_cols)?);
+        }
+        Ok(self.joined[id].get())
     }
 
     fn next_batch(&self) -> Option<(&Batch, usize)> {
+        // Streaming queries don't take new batches while the client has not received earlier chunks
+        if !self.flush_chunks() {
+            return None;
+        }
         let index = self.batch_index.fetch_add(1, Ordering::SeqCst);
         self.batches.get(index).map(|b| (b, index))
     }
 
     fn convert_to_output_format(&self,
                                 full_result: &BatchResult,
-                                rows_scanned: usize) -> QueryOutput {
-        let limit = self.query.limit.limit as usize;
+                                state: &QueryState) -> QueryOutput {
+        let limit = self.query.limit.limit.map_or(usize::MAX, |limit| limit as usize);
         let offset = self.query.limit.offset as usize;
-        let mut result_rows = Vec::new();
-        let count = cmp::min(limit, full_result.len() - offset);
+        let count = cmp::min(limit, full_result.len().saturating_sub(offset));
+        QueryOutput {
+            colnames: self.output_colnames.clone(),
+            rows: self.collect_rows(full_result, offset, count),
+            stats: self.stats(state),
+        }
+    }
+
+    fn stats(&self, state: &QueryState) -> QueryStats {
+        QueryStats {
+            runtime_ns: precise_time_ns() - self.start_time_ns,
+            rows_scanned: state.rows_scanned,
+            batches_skipped: state.batches_skipped,
+        }
+    }
+
+    fn collect_rows(&self, full_result: &BatchResult, offset: usize, count: usize) -> Vec<Vec<RawVal>> {
+        let mut result_rows = Vec::with_capacity(count);
         for i in offset..(count + offset) {
             let mut record = Vec::with_capacity(self.output_colnames.len());
             if let Some(ref gs) = full_result.group_by {
@@ -222,15 +758,7 @@ impl QueryTask {
             }
             result_rows.push(record);
         }
-
-        QueryOutput {
-            colnames: self.output_colnames.clone(),
-            rows: result_rows,
-            stats: QueryStats {
-                runtime_ns: precise_time_ns() - self.start_time_ns,
-                rows_scanned,
-            }
-        }
+        result_rows
     }
 
     fn prepare_batch<'a>(referenced_cols: &'a HashSet<String>, source: &'a Batch) -> HashMap<&'a str, &'a Column> {
@@ -242,19 +770,81 @@ impl QueryTask {
     }
 
     fn combined_limit(&self) -> usize {
-        (self.query.limit.limit + self.query.limit.offset) as usize
+        self.query.limit.combined().unwrap_or(usize::MAX)
     }
 }
 
 impl Task for QueryTask {
     fn execute(&self) { self.run(); }
     fn completed(&self) -> bool {
+        // Parked queries are scheduled again once the client has received earlier chunks
+        if self.parked.load(Ordering::SeqCst) {
+            return true;
+        }
+        if self.has_queued_chunks() {
+            return false;
+        }
         let batch_index = self.batch_index.load(Ordering::SeqCst);
-        self.completed.load(Ordering::SeqCst) || batch_index >= self.batches.len()
+        // Queries over empty tables still need to be executed once to send an (empty) result
+        self.completed.load(Ordering::SeqCst) || (batch_index >= self.batches.len() && !self.batches.is_empty())
     }
     fn multithreaded(&self) -> bool { true }
 }
 
+impl Task for Arc<QueryTask> {
+    fn execute(&self) { self.run(); }
+    fn completed(&self) -> bool { Task::completed(&**self) }
+    fn multithreaded(&self) -> bool { true }
+}
+
+/// Schedules a parked streaming query again when the client has received a chunk or is gone.
+struct Resume {
+    task: Arc<QueryTask>,
+    ruba: Weak<InnerRuba>,
+}
+
+impl Wake for Resume {
+    fn wake(arc_self: &Arc<Self>) {
+        if arc_self.task.parked.swap(false, Ordering::SeqCst) {
+            if let Some(ruba) = arc_self.ruba.upgrade() {
+                ruba.schedule(arc_self.task.clone());
+            }
+        }
+    }
+}
+
+/// Executor for the context used to poll the channel to a streaming client, which never spawns tasks.
+struct NoSpawn;
+
+impl Executor for NoSpawn {
+    fn spawn(&mut self, _: Box<Future<Item=(), Error=Never> + Send>) -> Result<(), SpawnError> {
+        Err(SpawnError::shutdown())
+    }
+}
+
+fn chunk_size(chunk: &QueryResult) -> usize {
+    chunk.as_ref().map_or(0, |output| output.rows.heap_size_of_children())
+}
+
+/// Rows of `batch` that are eligible independently of the query: not deleted and with a join partner.
+fn filter_mask(batch: &Batch, joined: Option<&JoinedColumns>) -> Option<BitVec> {
+    match (batch.live_rows(), joined.and_then(|j| j.matches.clone())) {
+        (Some(mut live), Some(matches)) => {
+            live.intersect(&matches);
+            Some(live)
+        }
+        (live, matches) => live.or(matches),
+    }
+}
+
+/// Readers for all columns of `batch` in `referenced_cols` that have one. Decoded values must not outlive them.
+pub fn query_readers(batch: &Batch, referenced_cols: &HashSet<String>) -> Vec<Column> {
+    batch.cols().iter()
+        .filter(|col| referenced_cols.contains(col.name()))
+        .filter_map(|col| col.data().query_reader().map(|reader| Column::new(col.name().to_string(), reader)))
+        .collect()
+}
+
 fn find_all_cols(source: &[Batch]) -> Vec<String> {
     let mut cols = HashSet::new();
     for batch in source {
@@ -1,8 +1,12 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::mem;
 use std::string;
 
 use bit_vec::BitVec;
 use engine::types::*;
 use engine::vector_op::types::IdentityCodec;
+use heapsize::HeapSizeOf;
 use ingest::raw_val::RawVal;
 use mem_store::point_codec::PointCodec;
 use mem_store::value::Val;
@@ -111,6 +115,25 @@ impl<'a> TypedVec<'a> {
         }
     }
 
+    /// Selects the elements at `indices` without decoding them.
+    pub fn gather(&self, indices: &[usize]) -> TypedVec<'a> {
+        match *self {
+            TypedVec::String(ref data) => TypedVec::String(indices.iter().map(|&i| data[i]).collect()),
+            TypedVec::Integer(ref data) => TypedVec::Integer(indices.iter().map(|&i| data[i]).collect()),
+            TypedVec::Mixed(ref data) => TypedVec::Mixed(indices.iter().map(|&i| data[i]).collect()),
+            TypedVec::Raw(ref data) => TypedVec::Raw(indices.iter().map(|&i| data[i].clone()).collect()),
+            TypedVec::EncodedU8(ref data, codec) => TypedVec::EncodedU8(indices.iter().map(|&i| data[i]).collect(), codec),
+            TypedVec::EncodedU16(ref data, codec) => TypedVec::EncodedU16(indices.iter().map(|&i| data[i]).collect(), codec),
+            TypedVec::EncodedU32(ref data, codec) => TypedVec::EncodedU32(indices.iter().map(|&i| data[i]).collect(), codec),
+            TypedVec::BorrowedEncodedU8(data, codec) => TypedVec::EncodedU8(indices.iter().map(|&i| data[i]).collect(), codec),
+            TypedVec::BorrowedEncodedU16(data, codec) => TypedVec::EncodedU16(indices.iter().map(|&i| data[i]).collect(), codec),
+            TypedVec::BorrowedEncodedU32(data, codec) => TypedVec::EncodedU32(indices.iter().map(|&i| data[i]).collect(), codec),
+            TypedVec::Empty(_) => TypedVec::Empty(indices.len()),
+            TypedVec::Constant(ref c) => TypedVec::Constant(c.clone()),
+            TypedVec::Boolean(_) | TypedVec::USize(_) => panic!("gather not supported for type {:?}", self.get_type()),
+        }
+    }
+
     pub fn extend(self, other: TypedVec<'a>, count: usize) -> TypedVec<'a> {
         match (self, other) {
             (Integer(mut data), Integer(other_data)) => {
@@ -155,39 +178,41 @@ impl<'a> TypedVec<'a> {
         }
     }
 
-    pub fn sort_indices_desc(&self, indices: &mut Vec<usize>) {
+    /// Sorts `indices` in descending order of the values they point to and truncates them to at most `limit` elements.
+    pub fn sort_indices_desc(&self, indices: &mut Vec<usize>, limit: usize) {
         match *self {
-            TypedVec::String(ref data) => indices.sort_unstable_by(|i, j| data[*i].cmp(data[*j]).reverse()),
-            TypedVec::Integer(ref data) => indices.sort_unstable_by_key(|i| -data[*i]),
-            TypedVec::USize(ref data) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::Mixed(ref data) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::Raw(ref data) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
+            TypedVec::String(ref data) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::Integer(ref data) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::USize(ref data) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::Mixed(ref data) => sort_limited(indices, limit, |i| Reverse(&data[i])),
+            TypedVec::Raw(ref data) => sort_limited(indices, limit, |i| Reverse(&data[i])),
             TypedVec::Boolean(_) => panic!("cannot sort by boolean column"),
-            TypedVec::EncodedU8(ref data, _) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::EncodedU16(ref data, _) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::EncodedU32(ref data, _) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::BorrowedEncodedU8(data, _) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::BorrowedEncodedU16(data, _) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::BorrowedEncodedU32(data, _) => indices.sort_unstable_by(|i, j| data[*i].cmp(&data[*j]).reverse()),
-            TypedVec::Empty(_) | TypedVec::Constant(_) => {}
+            TypedVec::EncodedU8(ref data, _) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::EncodedU16(ref data, _) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::EncodedU32(ref data, _) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::BorrowedEncodedU8(data, _) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::BorrowedEncodedU16(data, _) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::BorrowedEncodedU32(data, _) => sort_limited(indices, limit, |i| Reverse(data[i])),
+            TypedVec::Empty(_) | TypedVec::Constant(_) => indices.truncate(limit),
         }
     }
 
-    pub fn sort_indices_asc(&self, indices: &mut Vec<usize>) {
+    /// Sorts `indices` in ascending order of the values they point to and truncates them to at most `limit` elements.
+    pub fn sort_indices_asc(&self, indices: &mut Vec<usize>, limit: usize) {
         match *self {
-            TypedVec::String(ref data) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::Integer(ref data) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::USize(ref data) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::Mixed(ref data) => indices.sort_unstable_by_key(|i| &data[*i]),
-            TypedVec::Raw(ref data) => indices.sort_unstable_by_key(|i| &data[*i]),
+            TypedVec::String(ref data) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::Integer(ref data) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::USize(ref data) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::Mixed(ref data) => sort_limited(indices, limit, |i| &data[i]),
+            TypedVec::Raw(ref data) => sort_limited(indices, limit, |i| &data[i]),
             TypedVec::Boolean(_) => panic!("cannot sort by boolean column"),
-            TypedVec::EncodedU8(ref data, _) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::EncodedU16(ref data, _) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::EncodedU32(ref data, _) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::BorrowedEncodedU8(data, _) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::BorrowedEncodedU16(data, _) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::BorrowedEncodedU32(data, _) => indices.sort_unstable_by_key(|i| data[*i]),
-            TypedVec::Empty(_) | TypedVec::Constant(_) => {}
+            TypedVec::EncodedU8(ref data, _) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::EncodedU16(ref data, _) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::EncodedU32(ref data, _) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::BorrowedEncodedU8(data, _) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::BorrowedEncodedU16(data, _) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::BorrowedEncodedU32(data, _) => sort_limited(indices, limit, |i| data[i]),
+            TypedVec::Empty(_) | TypedVec::Constant(_) => indices.truncate(limit),
         }
     }
 
@@ -199,6 +224,23 @@ impl<'a> TypedVec<'a> {
     }
 
 
+    pub fn cast_ref_mixed<'b>(&'b self) -> &'b [Val<'a>] {
+        match *self {
+            TypedVec::Mixed(ref x) => x,
+            _ => panic!("type error: {:?}", self.get_type()),
+        }
+    }
+
+    pub fn into_mixed(self) -> TypedVec<'a> {
+        match self {
+            TypedVec::String(data) => TypedVec::Mixed(data.into_iter().map(Val::Str).collect()),
+            TypedVec::Integer(data) => TypedVec::Mixed(data.into_iter().map(Val::Integer).collect()),
+            TypedVec::Empty(len) => TypedVec::Mixed(vec![Val::Null; len]),
+            mixed @ TypedVec::Mixed(_) => mixed,
+            _ => panic!("cannot convert {:?} to mixed", self.get_type()),
+        }
+    }
+
     pub fn cast_ref_usize(&self) -> &[usize] {
         match *self {
             TypedVec::USize(ref x) => x,
@@ -291,6 +333,56 @@ impl<'a> From<(Vec<u8>, &'a PointCodec<u8>)> for TypedVec<'a> {
     }
 }
 
+impl<'a> HeapSizeOf for TypedVec<'a> {
+    /// Memory allocated by the vector itself, values borrowed from columns are not included.
+    fn heap_size_of_children(&self) -> usize {
+        match *self {
+            TypedVec::String(ref data) => allocated_bytes(data),
+            TypedVec::Integer(ref data) => allocated_bytes(data),
+            TypedVec::Mixed(ref data) => allocated_bytes(data),
+            TypedVec::Raw(ref data) => data.heap_size_of_children(),
+            TypedVec::Boolean(ref data) => data.capacity() / 8,
+            TypedVec::USize(ref data) => allocated_bytes(data),
+            TypedVec::EncodedU8(ref data, _) => allocated_bytes(data),
+            TypedVec::EncodedU16(ref data, _) => allocated_bytes(data),
+            TypedVec::EncodedU32(ref data, _) => allocated_bytes(data),
+            TypedVec::Constant(ref value) => value.heap_size_of_children(),
+            TypedVec::BorrowedEncodedU8(..) | TypedVec::BorrowedEncodedU16(..) |
+            TypedVec::BorrowedEncodedU32(..) | TypedVec::Empty(_) => 0,
+        }
+    }
+}
+
+fn allocated_bytes<T>(data: &Vec<T>) -> usize {
+    data.capacity() * mem::size_of::<T>()
+}
+
+/// Sorts `indices` by `key` and keeps the first `limit` elements.
+/// If only a small prefix is required, selects it with a bounded heap instead of sorting all elements.
+fn sort_limited<K: Ord, F: Fn(usize) -> K>(indices: &mut Vec<usize>, limit: usize, key: F) {
+    if limit >= indices.len() {
+        indices.sort_unstable_by_key(|&i| key(i));
+        return;
+    }
+    if limit == 0 {
+        indices.clear();
+        return;
+    }
+    let mut heap = BinaryHeap::with_capacity(limit + 1);
+    for &i in indices.iter() {
+        if heap.len() < limit {
+            heap.push((key(i), i));
+        } else {
+            let k = key(i);
+            if k < heap.peek().unwrap().0 {
+                heap.pop();
+                heap.push((k, i));
+            }
+        }
+    }
+    *indices = heap.into_sorted_vec().into_iter().map(|(_, i)| i).collect();
+}
+
 impl<'a> From<(Vec<u16>, &'a PointCodec<u16>)> for TypedVec<'a> {
     fn from(encoded: (Vec<u16>, &'a PointCodec<u16>)) -> Self {
         TypedVec::EncodedU16(encoded.0, encoded.1)
@@ -309,6 +401,12 @@ impl<'a> From<Vec<u8>> for TypedVec<'a> {
     }
 }
 
+impl<'a> From<Vec<u32>> for TypedVec<'a> {
+    fn from(encoded: Vec<u32>) -> Self {
+        TypedVec::EncodedU32(encoded, &IdentityCodec)
+    }
+}
+
 impl<'a> From<(Vec<i64>)> for TypedVec<'a> {
     fn from(data: Vec<i64>) -> Self {
         TypedVec::Integer(data)
@@ -320,3 +418,15 @@ impl<'a> From<(Vec<&'a str>)> for TypedVec<'a> {
         TypedVec::String(data)
     }
 }
+
+impl<'a> From<Vec<Val<'a>>> for TypedVec<'a> {
+    fn from(data: Vec<Val<'a>>) -> Self {
+        TypedVec::Mixed(data)
+    }
+}
+
+impl<'a> From<Vec<RawVal>> for TypedVec<'a> {
+    fn from(data: Vec<RawVal>) -> Self {
+        TypedVec::Raw(data)
+    }
+}
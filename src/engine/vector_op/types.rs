@@ -1,11 +1,14 @@
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::mem;
+use std::usize;
 
 use engine::typed_vec::TypedVec;
 use ingest::raw_val::RawVal;
 use mem_store::point_codec::PointCodec;
+use mem_store::value::Val;
 use num::PrimInt;
+use num::traits::NumCast;
 
 
 pub trait VecType<T>: PartialEq + PartialOrd + Copy + Debug {
@@ -48,6 +51,19 @@ impl<'c> VecType<&'c str> for &'c str {
     }
 }
 
+impl<'c> VecType<Val<'c>> for Val<'c> {
+    fn unwrap<'a, 'b>(vec: &'b TypedVec<'a>) -> &'b [Val<'c>] where Val<'c>: 'a {
+        unsafe {
+            mem::transmute::<_, &'b [Val<'c>]>(vec.cast_ref_mixed())
+        }
+    }
+    fn wrap<'a>(data: Vec<Val<'c>>) -> TypedVec<'a> {
+        unsafe {
+            mem::transmute::<_, TypedVec<'a>>(TypedVec::Mixed(data))
+        }
+    }
+}
+
 
 pub trait IntVecType<T>: VecType<T> + Into<i64> + PrimInt + Copy + Hash + 'static {}
 
@@ -87,11 +103,19 @@ impl IntoUsize for i64 {
 }
 
 
+/// Codec of vectors whose codes are the values themselves.
 pub struct IdentityCodec;
 
-impl<T: Send + Sync> PointCodec<T> for IdentityCodec {
-    fn decode(&self, _data: &[T]) -> TypedVec { unimplemented!() }
-    fn index_decode(&self, _data: &[T], _indices: &[usize]) -> TypedVec { unimplemented!() }
-    fn to_raw(&self, _elem: T) -> RawVal { unimplemented!() }
-    fn max_cardinality(&self) -> usize { unimplemented!() }
+impl<T: NumCast + Copy + Send + Sync> PointCodec<T> for IdentityCodec {
+    fn decode(&self, data: &[T]) -> TypedVec {
+        TypedVec::Integer(data.iter().map(|x| x.to_i64().unwrap()).collect())
+    }
+    fn index_decode(&self, data: &[T], indices: &[usize]) -> TypedVec {
+        TypedVec::Integer(indices.iter().map(|&i| data[i].to_i64().unwrap()).collect())
+    }
+    fn to_raw(&self, elem: T) -> RawVal { RawVal::Int(elem.to_i64().unwrap()) }
+    fn max_cardinality(&self) -> usize {
+        // Every value of `T` is its own code
+        1usize.checked_shl(8 * mem::size_of::<T>() as u32).unwrap_or(usize::MAX)
+    }
 }
@@ -8,6 +8,7 @@ use engine::aggregation_operator::*;
 use engine::typed_vec::TypedVec;
 use engine::types::EncodingType;
 use engine::vector_op::types::*;
+use heapsize::HeapSizeOf;
 use ingest::raw_val::RawVal;
 use mem_store::column::{ColumnData, ColumnCodec};
 
@@ -23,6 +24,10 @@ pub trait VecOperator<'a>: fmt::Debug {
 
 pub struct Scratchpad<'a> {
     buffers: Vec<RefCell<TypedVec<'a>>>,
+    /// Memory allocated by all buffers that were stored with `set`
+    heap_size: usize,
+    /// Memory that the last operator allocated internally and freed before returning, e.g. for hash maps
+    temporary_heap_size: usize,
 }
 
 impl<'a> Scratchpad<'a> {
@@ -31,7 +36,7 @@ impl<'a> Scratchpad<'a> {
         for _ in 0..count {
             buffers.push(RefCell::new(TypedVec::Empty(0)));
         }
-        Scratchpad { buffers }
+        Scratchpad { buffers, heap_size: 0, temporary_heap_size: 0 }
     }
 
     pub fn get(&self, index: BufferRef) -> Ref<TypedVec<'a>> {
@@ -43,13 +48,28 @@ impl<'a> Scratchpad<'a> {
     }
 
     pub fn collect(&mut self, index: BufferRef) -> TypedVec<'a> {
-        let owned = mem::replace(&mut self.buffers[index.0], RefCell::new(TypedVec::Empty(0)));
-        owned.into_inner()
+        let owned = mem::replace(&mut self.buffers[index.0], RefCell::new(TypedVec::Empty(0))).into_inner();
+        self.heap_size = self.heap_size.saturating_sub(owned.heap_size_of_children());
+        owned
     }
 
     pub fn set(&mut self, index: BufferRef, vec: TypedVec<'a>) {
+        let previous = self.buffers[index.0].borrow().heap_size_of_children();
+        self.heap_size = self.heap_size.saturating_sub(previous) + vec.heap_size_of_children();
         self.buffers[index.0] = RefCell::new(vec);
     }
+
+    pub fn heap_size(&self) -> usize { self.heap_size }
+
+    /// Records memory used internally by an operator, which counts towards the memory budget of its execution.
+    pub fn track_temporary(&mut self, bytes: usize) {
+        self.temporary_heap_size += bytes;
+    }
+
+    /// Peak memory used during the execution of the last operator, which resets the temporary memory.
+    pub fn take_peak_heap_size(&mut self) -> usize {
+        self.heap_size + mem::replace(&mut self.temporary_heap_size, 0)
+    }
 }
 
 
@@ -198,6 +218,26 @@ impl<'a> VecOperator<'a> for IndexEncoded<'a> {
     }
 }
 
+#[derive(Debug)]
+pub struct RangeMask<'a> {
+    col: &'a ColumnCodec,
+    min: i64,
+    max: i64,
+    output: BufferRef,
+}
+
+impl<'a> RangeMask<'a> {
+    pub fn new(col: &'a ColumnCodec, min: i64, max: i64, output: BufferRef) -> RangeMask<'a> {
+        RangeMask { col, min, max, output }
+    }
+}
+
+impl<'a> VecOperator<'a> for RangeMask<'a> {
+    fn execute(&mut self, scratchpad: &mut Scratchpad<'a>) {
+        scratchpad.set(self.output, TypedVec::Boolean(self.col.range_mask(self.min, self.max)));
+    }
+}
+
 #[derive(Debug)]
 pub struct DecodeWith<'a> {
     input: BufferRef,
@@ -221,11 +261,35 @@ impl<'a> VecOperator<'a> for DecodeWith<'a> {
     }
 }
 
+#[derive(Debug)]
+pub struct WrapEncoded<'a> {
+    input: BufferRef,
+    output: BufferRef,
+    codec: &'a ColumnCodec,
+}
+
+impl<'a> WrapEncoded<'a> {
+    pub fn new(input: BufferRef, output: BufferRef, codec: &'a ColumnCodec) -> WrapEncoded<'a> {
+        WrapEncoded { input, output, codec }
+    }
+}
+
+impl<'a> VecOperator<'a> for WrapEncoded<'a> {
+    fn execute(&mut self, scratchpad: &mut Scratchpad<'a>) {
+        let result = {
+            let encoded = scratchpad.get(self.input);
+            self.codec.wrap_encoded(&encoded)
+        };
+        scratchpad.set(self.output, result);
+    }
+}
+
 #[derive(Debug)]
 pub struct SortIndices {
     input: BufferRef,
     output: BufferRef,
     descending: bool,
+    limit: usize,
 }
 
 impl<'a> VecOperator<'a> for SortIndices {
@@ -234,9 +298,9 @@ impl<'a> VecOperator<'a> for SortIndices {
             let input = scratchpad.get(self.input);
             let mut result = (0..input.len()).collect();
             if self.descending {
-                input.sort_indices_desc(&mut result);
+                input.sort_indices_desc(&mut result, self.limit);
             } else {
-                input.sort_indices_asc(&mut result);
+                input.sort_indices_asc(&mut result, self.limit);
             }
             TypedVec::USize(result)
         };
@@ -291,6 +355,16 @@ impl<'a> VecOperator<'a> {
         }
     }
 
+    pub fn greater_than_vs(t: EncodingType, lhs: BufferRef, rhs: BufferRef, output: BufferRef) -> BoxedOperator<'a> {
+        match t {
+            EncodingType::U8 => Box::new(VecConstBoolOperator::<u8, i64, GreaterThanInt<u8>>::new(lhs, rhs, output)),
+            EncodingType::U16 => Box::new(VecConstBoolOperator::<u16, i64, GreaterThanInt<u16>>::new(lhs, rhs, output)),
+            EncodingType::U32 => Box::new(VecConstBoolOperator::<u32, i64, GreaterThanInt<u32>>::new(lhs, rhs, output)),
+            EncodingType::I64 => Box::new(VecConstBoolOperator::<i64, i64, GreaterThanInt<i64>>::new(lhs, rhs, output)),
+            _ => panic!("greater_than_vs not supported for type {:?}", t),
+        }
+    }
+
     pub fn equals_vs(t: EncodingType, lhs: BufferRef, rhs: BufferRef, output: BufferRef) -> BoxedOperator<'a> {
         match t {
             EncodingType::Str => Box::new(VecConstBoolOperator::<_, _, EqualsString>::new(lhs, rhs, output)),
@@ -310,6 +384,14 @@ impl<'a> VecOperator<'a> {
         Box::new(BitUnpackOperator::new(inner, output, shift, width))
     }
 
+    pub fn add_constant(inner: BufferRef, output: BufferRef, constant: i64) -> BoxedOperator<'a> {
+        Box::new(AddConstantOperator { input: inner, output, constant })
+    }
+
+    pub fn gather(input: BufferRef, indices: BufferRef, output: BufferRef) -> BoxedOperator<'a> {
+        Box::new(Gather { input, indices, output })
+    }
+
     pub fn type_conversion(inner: BufferRef, output: BufferRef, initial_type: EncodingType, target_type: EncodingType) -> BoxedOperator<'a> {
         use self::EncodingType::*;
         match (initial_type, target_type) {
@@ -389,20 +471,28 @@ impl<'a> VecOperator<'a> {
     pub fn hash_map_grouping(raw_grouping_key: BufferRef,
                              unique_out: BufferRef,
                              grouping_key_out: BufferRef,
-                             cardinality_out: BufferRef,
-                             grouping_key_type: EncodingType,
-                             max_cardinality: usize) -> BoxedOperator<'a> {
+                             grouping_key_type: EncodingType) -> BoxedOperator<'a> {
         match grouping_key_type {
-            EncodingType::U8 => HashMapGrouping::<u8>::boxed(raw_grouping_key, unique_out, grouping_key_out, cardinality_out, max_cardinality),
-            EncodingType::U16 => HashMapGrouping::<u16>::boxed(raw_grouping_key, unique_out, grouping_key_out, cardinality_out, max_cardinality),
-            EncodingType::U32 => HashMapGrouping::<u32>::boxed(raw_grouping_key, unique_out, grouping_key_out, cardinality_out, max_cardinality),
-            EncodingType::I64 => HashMapGrouping::<i64>::boxed(raw_grouping_key, unique_out, grouping_key_out, cardinality_out, max_cardinality),
+            EncodingType::U8 => HashMapGrouping::<u8>::boxed(raw_grouping_key, unique_out, grouping_key_out),
+            EncodingType::U16 => HashMapGrouping::<u16>::boxed(raw_grouping_key, unique_out, grouping_key_out),
+            EncodingType::U32 => HashMapGrouping::<u32>::boxed(raw_grouping_key, unique_out, grouping_key_out),
+            EncodingType::I64 => HashMapGrouping::<i64>::boxed(raw_grouping_key, unique_out, grouping_key_out),
             t => panic!("unsupported type {:?} for grouping key", t),
         }
     }
 
-    pub fn sort_indices(input: BufferRef, output: BufferRef, descending: bool) -> BoxedOperator<'a> {
-        Box::new(SortIndices { input, output, descending })
+    pub fn row_grouping(columns: Vec<BufferRef>,
+                        packing: Option<Vec<(i64, u32)>>,
+                        grouping_key_out: BufferRef,
+                        representative_rows_out: BufferRef) -> BoxedOperator<'a> {
+        match packing {
+            Some(packing) => RowGrouping::<u128>::boxed(columns, packing, grouping_key_out, representative_rows_out),
+            None => RowGrouping::<Vec<u8>>::boxed(columns, Vec::new(), grouping_key_out, representative_rows_out),
+        }
+    }
+
+    pub fn sort_indices(input: BufferRef, output: BufferRef, descending: bool, limit: usize) -> BoxedOperator<'a> {
+        Box::new(SortIndices { input, output, descending, limit })
     }
 }
 
@@ -461,6 +551,14 @@ impl<T: Into<i64> + Copy> BoolOperation<T, i64> for LessThanInt<T> {
     fn perform(l: &T, r: &i64) -> bool { Into::<i64>::into(*l) < *r }
 }
 
+#[derive(Debug)]
+struct GreaterThanInt<T> { t: PhantomData<T> }
+
+impl<T: Into<i64> + Copy> BoolOperation<T, i64> for GreaterThanInt<T> {
+    #[inline]
+    fn perform(l: &T, r: &i64) -> bool { Into::<i64>::into(*l) > *r }
+}
+
 #[derive(Debug)]
 struct Equals<T> { t: PhantomData<T> }
 
@@ -676,6 +774,42 @@ impl<'a> VecOperator<'a> for BitUnpackOperator {
     }
 }
 
+#[derive(Debug)]
+struct AddConstantOperator {
+    input: BufferRef,
+    output: BufferRef,
+    constant: i64,
+}
+
+impl<'a> VecOperator<'a> for AddConstantOperator {
+    fn execute(&mut self, scratchpad: &mut Scratchpad<'a>) {
+        let result = {
+            let data = scratchpad.get(self.input);
+            let output = data.cast_ref_i64().iter().map(|d| d + self.constant).collect();
+            TypedVec::Integer(output)
+        };
+        scratchpad.set(self.output, result);
+    }
+}
+
+#[derive(Debug)]
+struct Gather {
+    input: BufferRef,
+    indices: BufferRef,
+    output: BufferRef,
+}
+
+impl<'a> VecOperator<'a> for Gather {
+    fn execute(&mut self, scratchpad: &mut Scratchpad<'a>) {
+        let result = {
+            let data = scratchpad.get(self.input);
+            let indices = scratchpad.get(self.indices);
+            data.gather(indices.cast_ref_usize())
+        };
+        scratchpad.set(self.output, result);
+    }
+}
+
 trait ParameterizedIntegerOperation {
     fn perform(lhs: i64, rhs: i64, param: i64) -> i64;
 }
@@ -1,17 +1,87 @@
+use std::fmt;
+
 #[derive(Fail, Debug)]
 pub enum QueryError {
-    #[fail(display = "Failed to parser query. Chars remaining: {}", _0)]
-    SytaxErrorCharsRemaining(String),
-    #[fail(display = "Failed to parser query. Bytes remaining: {:?}", _0)]
-    SyntaxErrorBytesRemaining(Vec<u8>),
-    #[fail(display = "Failed to parser query: {}", _0)]
-    ParseError(String),
+    #[fail(display = "{}", _0)]
+    SyntaxError(SyntaxError),
     #[fail(display = "Some assumption was violated. This is a bug: {}", _0)]
     FatalError(String),
     #[fail(display = "Not implemented: {}", _0)]
     NotImplemented(String),
     #[fail(display = "Type error: {}", _0)]
     TypeError(String),
+    #[fail(display = "Table {} does not exist", _0)]
+    TableNotFound(String),
+    #[fail(display = "Table {} already exists", _0)]
+    TableAlreadyExists(String),
+    #[fail(display = "Schema violation: {}", _0)]
+    SchemaViolation(String),
+    #[fail(display = "Query was cancelled")]
+    Cancelled,
+    /// Timeout of the query in milliseconds
+    #[fail(display = "Query exceeded timeout of {}ms", _0)]
+    Timeout(u64),
+    #[fail(display = "Memory limit exceeded: {}", _0)]
+    MemoryLimitExceeded(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    /// Byte offset of the error within the query
+    pub offset: usize,
+    /// Line and column of the error, starting at 1
+    pub line: usize,
+    pub column: usize,
+    /// Line of the query that contains the error
+    pub snippet: String,
+    /// Tokens that would have been valid at `offset`
+    pub expected: Vec<String>,
+}
+
+impl SyntaxError {
+    pub fn new(query: &str, mut offset: usize, expected: Vec<String>) -> SyntaxError {
+        while !query.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line_start = query[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = query[offset..].find('\n').map_or(query.len(), |i| offset + i);
+        SyntaxError {
+            offset,
+            line: query[..offset].matches('\n').count() + 1,
+            column: query[line_start..offset].chars().count() + 1,
+            snippet: query[line_start..line_end].trim_right().to_string(),
+            expected,
+        }
+    }
+
+    /// Describes the error without showing its position.
+    pub fn message(&self) -> String {
+        let found = match self.snippet.chars().nth(self.column - 1) {
+            Some(c) if c.is_alphanumeric() || c == '_' => format!("`{}`", self.snippet.chars()
+                .skip(self.column - 1)
+                .take_while(|&c| c.is_alphanumeric() || c == '_')
+                .collect::<String>()),
+            Some(c) => format!("`{}`", c),
+            None => "end of input".to_string(),
+        };
+        match self.expected.len() {
+            0 => format!("unexpected {}", found),
+            1 => format!("expected {}, found {}", self.expected[0], found),
+            _ => format!("expected one of {}, found {}", self.expected.join(", "), found),
+        }
+    }
+
+    /// The line containing the error followed by a caret pointing at the error position.
+    pub fn caret_snippet(&self) -> String {
+        format!("{}\n{}^", self.snippet, " ".repeat(self.column - 1))
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Syntax error at line {}, column {}: {}\n{}",
+               self.line, self.column, self.message(), self.caret_snippet())
+    }
 }
 
 #[macro_export]
@@ -5,6 +5,10 @@ use ingest::input_column::InputColumn;
 use heapsize::HeapSizeOf;
 use std::cmp;
 use mem_store::batch::Batch;
+use mem_store::column::Column;
+use mem_store::shared_dictionary::SharedDictionaries;
+use mem_store::table::ColumnSchema;
+use QueryError;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Buffer {
@@ -78,6 +82,20 @@ impl Buffer {
     pub fn len(&self) -> usize {
         self.length
     }
+
+    /// Encodes all buffered columns. Columns declared in `schema` are converted to the declared type and codec.
+    pub fn into_batch(self, schema: Option<&[ColumnSchema]>, dictionaries: &SharedDictionaries) -> Result<Batch, QueryError> {
+        let mut cols = Vec::with_capacity(self.buffer.len());
+        for (name, raw_col) in self.buffer {
+            let null_count = raw_col.null_count();
+            let data = match schema.and_then(|s| s.iter().find(|c| c.name == name)) {
+                Some(declared) => raw_col.finalize_as(declared, dictionaries)?,
+                None => raw_col.finalize(),
+            };
+            cols.push(Column::with_zone_map(name, data, null_count));
+        }
+        Ok(Batch::from(cols))
+    }
 }
 
 impl HeapSizeOf for Buffer {
@@ -88,8 +106,7 @@ impl HeapSizeOf for Buffer {
 
 impl From<Buffer> for Batch {
     fn from(buffer: Buffer) -> Self {
-        Batch::new(buffer.buffer.into_iter()
-            .map(|(name, raw_col)| (name, raw_col.finalize()))
-            .collect())
+        // Columns are only converted to declared types, which is the only way encoding can fail
+        buffer.into_batch(None, &SharedDictionaries::new()).unwrap()
     }
 }
@@ -9,24 +9,33 @@ use mem_store::batch::Batch;
 use mem_store::column::*;
 use mem_store::column_builder::*;
 use mem_store::null_column::NullColumn;
+use mem_store::shared_dictionary::{SharedDictColumn, SharedDictionaries};
+use mem_store::table::{ColumnSchema, ColumnType};
 use scheduler::*;
+use QueryError;
 use super::extractor;
 
 type IngestionTransform = HashMap<String, extractor::Extractor>;
 
-pub fn ingest_file(filename: &str, chunk_size: usize, extractors: &IngestionTransform) -> Vec<Batch> {
+pub fn ingest_file(filename: &str,
+                   chunk_size: usize,
+                   extractors: &IngestionTransform,
+                   schema: Option<&[ColumnSchema]>,
+                   dictionaries: &SharedDictionaries) -> Result<Vec<Batch>, QueryError> {
     let mut reader = csv::Reader::from_file(filename)
         .unwrap()
         .has_headers(true);
     let headers = reader.headers().unwrap();
-    auto_ingest(reader.records().map(|r| r.unwrap()), &headers, chunk_size, extractors)
+    auto_ingest(reader.records().map(|r| r.unwrap()), &headers, chunk_size, extractors, schema, dictionaries)
 }
 
 fn auto_ingest<T: Iterator<Item=Vec<String>>>(records: T,
                                               colnames: &[String],
                                               batch_size: usize,
-                                              extractors: &IngestionTransform)
-                                              -> Vec<Batch> {
+                                              extractors: &IngestionTransform,
+                                              schema: Option<&[ColumnSchema]>,
+                                              dictionaries: &SharedDictionaries)
+                                              -> Result<Vec<Batch>, QueryError> {
     let num_columns = colnames.len();
     let mut batches = Vec::new();
 
@@ -38,29 +47,36 @@ fn auto_ingest<T: Iterator<Item=Vec<String>>>(records: T,
         }
 
         if row_num % batch_size == batch_size - 1 {
-            batches.push(create_batch(raw_cols, colnames, extractors));
+            batches.push(create_batch(raw_cols, colnames, extractors, schema, dictionaries)?);
             raw_cols = (0..num_columns).map(|_| RawCol::new()).collect::<Vec<_>>();
         }
         row_num += 1;
     }
 
     if row_num % batch_size != 0 {
-        batches.push(create_batch(raw_cols, colnames, extractors));
+        batches.push(create_batch(raw_cols, colnames, extractors, schema, dictionaries)?);
     }
 
-    batches
+    Ok(batches)
 }
 
-fn create_batch(cols: Vec<RawCol>, colnames: &[String], extractors: &IngestionTransform) -> Batch {
+fn create_batch(cols: Vec<RawCol>,
+                colnames: &[String],
+                extractors: &IngestionTransform,
+                schema: Option<&[ColumnSchema]>,
+                dictionaries: &SharedDictionaries) -> Result<Batch, QueryError> {
     let mut mem_store = Vec::new();
     for (i, col) in cols.into_iter().enumerate() {
-        let new_column = match extractors.get(&colnames[i]) {
-            Some(extractor) => Column::new(colnames[i].clone(), col.extract(extractor)),
-            None => Column::new(colnames[i].clone(), col.finalize()),
+        let declared = schema.and_then(|s| s.iter().find(|c| c.name == colnames[i]));
+        let null_count = col.null_count();
+        let data = match (extractors.get(&colnames[i]), declared) {
+            (Some(extractor), _) => col.extract(extractor),
+            (None, Some(declared)) => col.finalize_as(declared, dictionaries)?,
+            (None, None) => col.finalize(),
         };
-        mem_store.push(new_column);
+        mem_store.push(Column::with_zone_map(colnames[i].clone(), data, null_count));
     }
-    Batch::from(mem_store)
+    Ok(Batch::from(mem_store))
 }
 
 pub struct CSVIngestionTask {
@@ -69,7 +85,7 @@ pub struct CSVIngestionTask {
     chunk_size: usize,
     extractors: IngestionTransform,
     ruba: Arc<InnerRuba>,
-    sender: SharedSender<()>,
+    sender: SharedSender<Result<(), QueryError>>,
 }
 
 impl CSVIngestionTask {
@@ -78,7 +94,7 @@ impl CSVIngestionTask {
                chunk_size: usize,
                extractors: IngestionTransform,
                ruba: Arc<InnerRuba>,
-               sender: SharedSender<()>) -> CSVIngestionTask {
+               sender: SharedSender<Result<(), QueryError>>) -> CSVIngestionTask {
         CSVIngestionTask {
             filename,
             table,
@@ -92,9 +108,14 @@ impl CSVIngestionTask {
 
 impl Task for CSVIngestionTask {
     fn execute(&self) {
-        let batches = ingest_file(&self.filename, self.chunk_size, &self.extractors);
-        self.ruba.load_batches(&self.table, batches);
-        self.sender.send(());
+        let schema = self.ruba.metadata(&self.table).and_then(|md| md.schema);
+        let dictionaries = self.ruba.shared_dictionaries(&self.table);
+        let batches = ingest_file(&self.filename,
+                                  self.chunk_size,
+                                  &self.extractors,
+                                  schema.as_ref().map(|s| &s[..]),
+                                  &dictionaries);
+        self.sender.send(batches.and_then(|batches| self.ruba.load_batches(&self.table, batches)));
     }
     fn completed(&self) -> bool { false }
     fn multithreaded(&self) -> bool { false }
@@ -119,6 +140,14 @@ impl RawCol {
         self.data.push(elem);
     }
 
+    fn null_count(&self) -> usize {
+        if self.types.contains_null {
+            self.data.iter().filter(|s| s.is_empty()).count()
+        } else {
+            0
+        }
+    }
+
     fn finalize(self) -> Box<ColumnData> {
         if self.types.contains_string {
             let mut builder = StringColBuilder::new();
@@ -129,16 +158,7 @@ impl RawCol {
         } else if self.types.contains_int {
             let mut builder = IntColBuilder::new();
             for s in self.data {
-                let int = if s.is_empty() {
-                    0
-                } else if let Ok(int) = s.parse::<i64>() {
-                    int
-                } else if let Ok(float) = s.parse::<f64>() {
-                    float as i64
-                } else {
-                    unreachable!("{} should be parseable as int or float", s)
-                };
-                builder.push(&int);
+                builder.push(&parse_int(&s));
             }
             builder.finalize()
         } else {
@@ -146,6 +166,41 @@ impl RawCol {
         }
     }
 
+    /// Encodes values as the declared type. Empty values are null.
+    /// Fails if the values cannot be converted, or contain nulls and the column is declared as `NOT NULL`.
+    fn finalize_as(self, declared: &ColumnSchema, dictionaries: &SharedDictionaries) -> Result<Box<ColumnData>, QueryError> {
+        if !declared.nullable && self.types.contains_null {
+            bail!(QueryError::SchemaViolation, "Column {} is declared as NOT NULL but got null", declared.name)
+        }
+        let codec = declared.codec;
+        Ok(match (declared.column_type, dictionaries.get(&declared.name)) {
+            (ColumnType::String, Some(dictionary)) =>
+                Box::new(SharedDictColumn::new(dictionary, self.data.iter().map(|s| s.as_str()))),
+            (ColumnType::String, None) => {
+                let mut builder = StringColBuilder::with_codec(codec);
+                for s in self.data {
+                    if s.is_empty() {
+                        builder.push_null();
+                    } else {
+                        builder.push(&s);
+                    }
+                }
+                builder.finalize()
+            }
+            (ColumnType::Integer, _) => {
+                if self.types.contains_string {
+                    let value = self.data.iter().find(|s| ColType::determine(s).contains_string).unwrap();
+                    bail!(QueryError::SchemaViolation, "Column {} is declared as Integer but got {:?}", declared.name, value)
+                }
+                let mut builder = IntColBuilder::with_codec(codec);
+                for s in self.data {
+                    builder.push(&parse_int(&s));
+                }
+                builder.finalize()
+            }
+        })
+    }
+
     fn extract(self, extractor: &extractor::Extractor) -> Box<ColumnData> {
         let mut builder = IntColBuilder::new();
         for s in self.data {
@@ -155,6 +210,18 @@ impl RawCol {
     }
 }
 
+fn parse_int(s: &str) -> i64 {
+    if s.is_empty() {
+        0
+    } else if let Ok(int) = s.parse::<i64>() {
+        int
+    } else if let Ok(float) = s.parse::<f64>() {
+        float as i64
+    } else {
+        unreachable!("{} should be parseable as int or float", s)
+    }
+}
+
 
 #[derive(Copy, Clone)]
 struct ColType {
@@ -4,3 +4,13 @@ pub enum InputColumn {
     Null(usize),
 }
 
+
+impl InputColumn {
+    pub fn len(&self) -> usize {
+        match *self {
+            InputColumn::Int(ref ints) => ints.len(),
+            InputColumn::Str(ref strs) => strs.len(),
+            InputColumn::Null(count) => count,
+        }
+    }
+}
@@ -6,6 +6,7 @@ extern crate serde_derive;
 #[macro_use]
 extern crate failure_derive;
 extern crate log;
+extern crate lz4_flex;
 extern crate bincode;
 extern crate bit_vec;
 extern crate failure;
@@ -36,9 +37,12 @@ mod disk_store;
 pub use ingest::raw_val::RawVal as Value;
 pub use ruba::Ruba as Ruba;
 pub use engine::query_task::QueryOutput;
+pub use engine::query_cache::CacheStats;
 pub use mem_store::table::TableStats;
 pub use ingest::extractor;
-pub use errors::QueryError;
+pub use errors::{QueryError, SyntaxError};
+pub use scheduler::CancellationHandle;
+pub use ruba::{QueryFuture, QueryStream};
 
 pub type QueryResult = Result<QueryOutput, QueryError>;
 
@@ -1,37 +1,69 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use bit_vec::BitVec;
 use heapsize::HeapSizeOf;
-use mem_store::column::{Column, ColumnData};
+use mem_store::column::Column;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Clone)]
 pub struct Batch {
+    /// Unique among all batches, changes whenever rows are deleted
+    id: usize,
     cols: Arc<Vec<Column>>,
+    /// Rows removed by `DELETE`. `None` if no rows have been deleted.
+    deleted: Option<Arc<BitVec>>,
 }
 
 
 impl Batch {
-    pub fn new(cols: Vec<(String, Box<ColumnData>)>) -> Batch {
-        let mut mem_store = Vec::new();
-        for (name, col) in cols {
-            mem_store.push(Column::new(name, col));
-        }
-        Batch { cols: Arc::new(mem_store) }
-    }
+    pub fn id(&self) -> usize { self.id }
 
     pub fn cols(&self) -> &Vec<Column> {
         self.cols.as_ref()
     }
+
+    pub fn len(&self) -> usize {
+        self.cols.get(0).map_or(0, |c| c.len())
+    }
+
+    pub fn deleted(&self) -> Option<&BitVec> {
+        self.deleted.as_ref().map(|d| d.as_ref())
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.deleted.as_ref().map_or(0, |d| d.iter().filter(|&deleted| deleted).count())
+    }
+
+    /// Rows that have not been deleted. `None` if all rows are live.
+    pub fn live_rows(&self) -> Option<BitVec> {
+        self.deleted.as_ref().map(|deleted| {
+            let mut live = deleted.as_ref().clone();
+            live.negate();
+            live
+        })
+    }
+
+    /// Returns a batch that shares all columns with `self` and additionally has `rows` marked as deleted.
+    pub fn with_deletions(&self, rows: &BitVec) -> Batch {
+        let mut deleted = rows.clone();
+        if let Some(ref previous) = self.deleted {
+            deleted.union(previous);
+        }
+        Batch { id: NEXT_ID.fetch_add(1, Ordering::SeqCst), cols: self.cols.clone(), deleted: Some(Arc::new(deleted)) }
+    }
 }
 
 impl From<Vec<Column>> for Batch {
     fn from(cols: Vec<Column>) -> Batch {
-        Batch { cols: Arc::new(cols) }
+        Batch { id: NEXT_ID.fetch_add(1, Ordering::SeqCst), cols: Arc::new(cols), deleted: None }
     }
 }
 
 
 impl HeapSizeOf for Batch {
     fn heap_size_of_children(&self) -> usize {
-        self.cols.heap_size_of_children()
+        self.cols.heap_size_of_children() + self.deleted.as_ref().map_or(0, |d| d.capacity() / 8)
     }
 }
@@ -0,0 +1,533 @@
+use std::cmp;
+use std::marker::PhantomData;
+
+use bit_vec::BitVec;
+use engine::typed_vec::TypedVec;
+use engine::types::*;
+use engine::vector_op::types::IntVecType;
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
+use mem_store::column::{ColumnData, ColumnCodec};
+use mem_store::integers::IntLike;
+use mem_store::point_codec::PointCodec;
+
+
+/// Unsigned integers of 1 to 63 bits packed into 64-bit words.
+/// Each value occupies a lane of `width + 1` bits whose top (guard) bit is always zero, and lanes never
+/// straddle words. The guard bits absorb borrows, which lets comparisons run on all lanes of a word at once.
+pub struct PackedWords {
+    words: Vec<u64>,
+    width: u8,
+    len: usize,
+}
+
+impl PackedWords {
+    pub fn new<I: Iterator<Item=u64>>(values: I, width: u8, len: usize) -> PackedWords {
+        assert!(width >= 1 && width <= 63, "bit width {} out of range", width);
+        let lanes = lanes(width);
+        let mut words = vec![0u64; words_for(len, width)];
+        for (i, value) in values.enumerate() {
+            debug_assert!(value >> width == 0);
+            words[i / lanes] |= value << (i % lanes * (width as usize + 1));
+        }
+        PackedWords { words, width, len }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    fn lanes(&self) -> usize { lanes(self.width) }
+
+    fn lane_bits(&self) -> usize { self.width as usize + 1 }
+
+    fn mask(&self) -> u64 { (1 << self.width) - 1 }
+
+    pub fn get(&self, index: usize) -> u64 {
+        let lanes = self.lanes();
+        (self.words[index / lanes] >> (index % lanes * self.lane_bits())) & self.mask()
+    }
+
+    /// Appends the values packed into word `index` to `output`, converted with `convert`.
+    fn unpack_word<T, F: Fn(u64) -> T>(&self, index: usize, convert: &F, output: &mut Vec<T>) {
+        let (lanes, lane_bits, mask) = (self.lanes(), self.lane_bits(), self.mask());
+        let word = self.words[index];
+        let count = cmp::min(lanes, self.len - index * lanes);
+        for lane in 0..count {
+            output.push(convert((word >> (lane * lane_bits)) & mask));
+        }
+    }
+
+    /// Unpacks all values, one word at a time.
+    pub fn unpack<T, F: Fn(u64) -> T>(&self, convert: F) -> Vec<T> {
+        let mut output = Vec::with_capacity(self.len);
+        for index in 0..self.words.len() {
+            self.unpack_word(index, &convert, &mut output);
+        }
+        output
+    }
+
+    /// Unpacks the values selected by `filter`, skipping words without selected values.
+    pub fn filter<T, F: Fn(u64) -> T>(&self, filter: &BitVec, convert: F) -> Vec<T> {
+        let mut output = Vec::new();
+        let mut unpacked = Vec::with_capacity(self.lanes());
+        let mut selected = filter.iter();
+        for index in 0..self.words.len() {
+            let selection = selected.by_ref().take(self.lanes()).collect::<Vec<_>>();
+            if selection.iter().any(|&s| s) {
+                unpacked.clear();
+                self.unpack_word(index, &|value| value, &mut unpacked);
+                output.extend(unpacked.iter().zip(selection).filter(|&(_, s)| s).map(|(&value, _)| convert(value)));
+            }
+        }
+        output
+    }
+
+    /// Indices of all values in `min..=max`, compared directly on the packed words.
+    pub fn lookup_range(&self, min: u64, max: u64) -> Vec<usize> {
+        let mut rows = Vec::new();
+        self.for_each_in_range(min, max, |row| rows.push(row));
+        rows
+    }
+
+    /// Marks all values in `min..=max`, compared directly on the packed words.
+    pub fn range_mask(&self, min: u64, max: u64) -> BitVec {
+        let mut mask = BitVec::from_elem(self.len, false);
+        self.for_each_in_range(min, max, |row| mask.set(row, true));
+        mask
+    }
+
+    /// Calls `f` with the index of each value in `min..=max` in ascending order, one word at a time.
+    fn for_each_in_range<F: FnMut(usize)>(&self, min: u64, max: u64, mut f: F) {
+        if min > max || min > self.mask() {
+            return;
+        }
+        let max = cmp::min(max, self.mask());
+        let (lanes, lane_bits) = (self.lanes(), self.lane_bits());
+        let guards = self.broadcast(1 << self.width);
+        let (min, max) = (self.broadcast(min), self.broadcast(max));
+        for (index, &word) in self.words.iter().enumerate() {
+            // The guard bit of a lane remains set iff the subtraction did not borrow from it
+            let at_most_max = (max | guards) - word;
+            let at_least_min = (word | guards) - min;
+            let mut matches = at_most_max & at_least_min & guards;
+            while matches != 0 {
+                let row = index * lanes + matches.trailing_zeros() as usize / lane_bits;
+                if row < self.len {
+                    f(row);
+                }
+                matches &= matches - 1;
+            }
+        }
+    }
+
+    /// Word with `value` in every lane.
+    fn broadcast(&self, value: u64) -> u64 {
+        (0..self.lanes()).fold(0, |word, lane| word | value << (lane * self.lane_bits()))
+    }
+}
+
+impl HeapSizeOf for PackedWords {
+    fn heap_size_of_children(&self) -> usize {
+        self.words.heap_size_of_children()
+    }
+}
+
+fn lanes(width: u8) -> usize { 64 / (width as usize + 1) }
+
+fn words_for(len: usize, width: u8) -> usize { (len + lanes(width) - 1) / lanes(width) }
+
+
+/// Number of values that `DenseWords` unpacks at once.
+const BLOCK_LEN: usize = 64;
+
+/// Unsigned integers of 1 to 63 bits stored back to back, so values may straddle two words.
+/// Denser than `PackedWords` for values of more than 31 bits, of which `PackedWords` only fits one per word.
+/// Values are unpacked in blocks of `BLOCK_LEN` before they are compared.
+pub struct DenseWords {
+    words: Vec<u64>,
+    width: u8,
+    len: usize,
+}
+
+impl DenseWords {
+    pub fn new<I: Iterator<Item=u64>>(values: I, width: u8, len: usize) -> DenseWords {
+        assert!(width >= 1 && width <= 63, "bit width {} out of range", width);
+        let mut words = vec![0u64; dense_words_for(len, width)];
+        for (i, value) in values.enumerate() {
+            debug_assert!(value >> width == 0);
+            let (word, shift) = (i * width as usize / 64, i * width as usize % 64);
+            words[word] |= value << shift;
+            if shift + width as usize > 64 {
+                words[word + 1] |= value >> (64 - shift);
+            }
+        }
+        DenseWords { words, width, len }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    fn mask(&self) -> u64 { (1 << self.width) - 1 }
+
+    pub fn get(&self, index: usize) -> u64 {
+        let (word, shift) = (index * self.width as usize / 64, index * self.width as usize % 64);
+        let mut value = self.words[word] >> shift;
+        if shift + self.width as usize > 64 {
+            value |= self.words[word + 1] << (64 - shift);
+        }
+        value & self.mask()
+    }
+
+    fn blocks(&self) -> usize { (self.len + BLOCK_LEN - 1) / BLOCK_LEN }
+
+    /// Unpacks the values of block `index` into `block` and returns their number.
+    fn unpack_block(&self, index: usize, block: &mut [u64; BLOCK_LEN]) -> usize {
+        let start = index * BLOCK_LEN;
+        let count = cmp::min(BLOCK_LEN, self.len - start);
+        for (i, value) in block.iter_mut().take(count).enumerate() {
+            *value = self.get(start + i);
+        }
+        count
+    }
+
+    /// Unpacks all values, one block at a time.
+    pub fn unpack<T, F: Fn(u64) -> T>(&self, convert: F) -> Vec<T> {
+        let mut output = Vec::with_capacity(self.len);
+        let mut block = [0; BLOCK_LEN];
+        for index in 0..self.blocks() {
+            let count = self.unpack_block(index, &mut block);
+            output.extend(block[..count].iter().map(|&value| convert(value)));
+        }
+        output
+    }
+
+    /// Unpacks the values selected by `filter`, skipping blocks without selected values.
+    pub fn filter<T, F: Fn(u64) -> T>(&self, filter: &BitVec, convert: F) -> Vec<T> {
+        let mut output = Vec::new();
+        let mut block = [0; BLOCK_LEN];
+        let mut selected = filter.iter();
+        for index in 0..self.blocks() {
+            let selection = selected.by_ref().take(BLOCK_LEN).collect::<Vec<_>>();
+            if selection.iter().any(|&s| s) {
+                let count = self.unpack_block(index, &mut block);
+                output.extend(block[..count].iter().zip(selection).filter(|&(_, s)| s).map(|(&value, _)| convert(value)));
+            }
+        }
+        output
+    }
+
+    /// Indices of all values in `min..=max`, unpacking one block at a time.
+    pub fn lookup_range(&self, min: u64, max: u64) -> Vec<usize> {
+        let mut rows = Vec::new();
+        let mut block = [0; BLOCK_LEN];
+        for index in 0..self.blocks() {
+            let count = self.unpack_block(index, &mut block);
+            rows.extend(block[..count].iter().enumerate()
+                .filter(|&(_, &value)| min <= value && value <= max)
+                .map(|(i, _)| index * BLOCK_LEN + i));
+        }
+        rows
+    }
+}
+
+impl HeapSizeOf for DenseWords {
+    fn heap_size_of_children(&self) -> usize {
+        self.words.heap_size_of_children()
+    }
+}
+
+fn dense_words_for(len: usize, width: u8) -> usize { (len * width as usize + 63) / 64 }
+
+/// Values of more than 31 bits are stored in `DenseWords`, narrower values in `PackedWords`.
+pub fn is_dense(width: u8) -> bool { width > 31 }
+
+/// Number of bits required to represent `maximum`, at least 1.
+pub fn bit_width(maximum: u64) -> u8 {
+    cmp::max(64 - maximum.leading_zeros(), 1) as u8
+}
+
+/// Number of bytes used to bit-pack `len` values between 0 and `maximum`.
+pub fn packed_size(len: usize, maximum: u64) -> usize {
+    let width = bit_width(maximum);
+    let words = if is_dense(width) { dense_words_for(len, width) } else { words_for(len, width) };
+    words * 8
+}
+
+/// Whether bit-packing saves at least a quarter of the memory used with `value_size` bytes per value.
+pub fn is_worthwhile(len: usize, maximum: u64, value_size: usize) -> bool {
+    packed_size(len, maximum) * 4 <= len * value_size * 3
+}
+
+
+/// Integer column that subtracts an offset from all values and bit-packs the result.
+/// Exposes its values as `T` codes to operators on encoded data, unpacking them on access.
+/// Comparisons with constants run directly on the packed words, see `range_mask`.
+pub struct BitPackedColumn<T: IntLike> {
+    packed: PackedWords,
+    offset: i64,
+    maximum: usize,
+    encoding: PhantomData<T>,
+}
+
+impl<T: IntLike> BitPackedColumn<T> {
+    pub fn new(values: &[i64], offset: i64, maximum: usize) -> BitPackedColumn<T> {
+        let width = bit_width(maximum as u64);
+        let encoded = values.iter().map(|&value| (value - offset) as u64);
+        BitPackedColumn {
+            packed: PackedWords::new(encoded, width, values.len()),
+            offset,
+            maximum,
+            encoding: PhantomData,
+        }
+    }
+
+    fn encode(value: u64) -> T { T::from(value).unwrap() }
+
+    /// Clamps the encoded range `min..=max` to the values that occur in the column.
+    fn clamp(&self, min: i64, max: i64) -> Option<(u64, u64)> {
+        if max < 0 || min > self.maximum as i64 || min > max {
+            None
+        } else {
+            Some((cmp::max(min, 0) as u64, cmp::min(max, self.maximum as i64) as u64))
+        }
+    }
+}
+
+impl<T: IntLike + IntVecType<T>> ColumnData for BitPackedColumn<T> {
+    fn collect_decoded(&self) -> TypedVec {
+        let offset = self.offset;
+        TypedVec::Integer(self.packed.unpack(|value| value as i64 + offset))
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        let offset = self.offset;
+        TypedVec::Integer(self.packed.filter(filter, |value| value as i64 + offset))
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        TypedVec::Integer(filter.iter().map(|&i| self.packed.get(i) as i64 + self.offset).collect())
+    }
+
+    fn basic_type(&self) -> BasicType { BasicType::Integer }
+
+    fn to_codec(&self) -> Option<&ColumnCodec> { Some(self as &ColumnCodec) }
+
+    fn len(&self) -> usize { self.packed.len() }
+
+    fn codec_name(&self) -> &'static str { "bit packed" }
+
+    fn range(&self) -> Option<(i64, i64)> { Some((self.offset, self.offset + self.maximum as i64)) }
+
+    fn lookup_range(&self, min: i64, max: i64) -> Option<Vec<usize>> {
+        let upper = self.offset + self.maximum as i64;
+        if min > upper || max < self.offset {
+            return Some(Vec::new());
+        }
+        let min = (cmp::max(min, self.offset) - self.offset) as u64;
+        let max = (cmp::min(max, upper) - self.offset) as u64;
+        Some(self.packed.lookup_range(min, max))
+    }
+}
+
+impl<'a, T: IntLike + IntVecType<T> + 'a> PointCodec<T> for BitPackedColumn<T> {
+    fn decode(&self, data: &[T]) -> TypedVec {
+        TypedVec::Integer(data.iter().map(|value| value.to_i64().unwrap() + self.offset).collect())
+    }
+
+    fn index_decode(&self, data: &[T], filter: &[usize]) -> TypedVec {
+        TypedVec::Integer(filter.iter().map(|&i| data[i].to_i64().unwrap() + self.offset).collect())
+    }
+
+    fn to_raw(&self, elem: T) -> RawVal {
+        RawVal::Int(elem.to_i64().unwrap() + self.offset)
+    }
+
+    fn max_cardinality(&self) -> usize { self.maximum }
+}
+
+impl<'a, T: IntLike + IntVecType<T> + 'a> ColumnCodec for BitPackedColumn<T> {
+    fn get_encoded(&self) -> TypedVec {
+        T::typed_vec(self.packed.unpack(Self::encode), self as &PointCodec<T>)
+    }
+
+    fn unwrap_decode<'b>(&'b self, data: &TypedVec<'b>) -> TypedVec<'b> {
+        self.decode(T::unwrap(data))
+    }
+
+    fn filter_encoded(&self, filter: &BitVec) -> TypedVec {
+        T::typed_vec(self.packed.filter(filter, Self::encode), self as &PointCodec<T>)
+    }
+
+    fn index_encoded(&self, filter: &[usize]) -> TypedVec {
+        let encoded = filter.iter().map(|&i| Self::encode(self.packed.get(i))).collect();
+        T::typed_vec(encoded, self as &PointCodec<T>)
+    }
+
+    fn encode_int(&self, val: i64) -> RawVal {
+        RawVal::Int(val - self.offset)
+    }
+
+    fn is_summation_preserving(&self) -> bool { self.offset == 0 }
+    fn is_order_preserving(&self) -> bool { true }
+    fn is_positive_integer(&self) -> bool { true }
+    fn encoding_type(&self) -> EncodingType { T::t() }
+    fn encoding_range(&self) -> Option<(i64, i64)> { Some((0, self.maximum as i64)) }
+
+    fn supports_range_mask(&self) -> bool { true }
+
+    fn range_mask(&self, min: i64, max: i64) -> BitVec {
+        match self.clamp(min, max) {
+            Some((min, max)) => self.packed.range_mask(min, max),
+            None => BitVec::from_elem(self.len(), false),
+        }
+    }
+}
+
+impl<T: IntLike> HeapSizeOf for BitPackedColumn<T> {
+    fn heap_size_of_children(&self) -> usize {
+        self.packed.heap_size_of_children()
+    }
+}
+
+
+/// Integer column that subtracts an offset from all values and stores the result in `DenseWords`.
+/// Used for ranges of more than 31 bits, which don't fit any code type of operators on encoded data.
+pub struct DenseBitPackedColumn {
+    packed: DenseWords,
+    offset: i64,
+    maximum: u64,
+}
+
+impl DenseBitPackedColumn {
+    pub fn new(values: &[i64], offset: i64, maximum: u64) -> DenseBitPackedColumn {
+        let encoded = values.iter().map(|&value| value.wrapping_sub(offset) as u64);
+        DenseBitPackedColumn {
+            packed: DenseWords::new(encoded, bit_width(maximum), values.len()),
+            offset,
+            maximum,
+        }
+    }
+
+    fn decode(&self, value: u64) -> i64 { self.offset.wrapping_add(value as i64) }
+}
+
+impl ColumnData for DenseBitPackedColumn {
+    fn collect_decoded(&self) -> TypedVec {
+        TypedVec::Integer(self.packed.unpack(|value| self.decode(value)))
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        TypedVec::Integer(self.packed.filter(filter, |value| self.decode(value)))
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        TypedVec::Integer(filter.iter().map(|&i| self.decode(self.packed.get(i))).collect())
+    }
+
+    fn basic_type(&self) -> BasicType { BasicType::Integer }
+
+    fn len(&self) -> usize { self.packed.len() }
+
+    fn codec_name(&self) -> &'static str { "bit packed" }
+
+    fn range(&self) -> Option<(i64, i64)> { Some((self.offset, self.decode(self.maximum))) }
+
+    fn lookup_range(&self, min: i64, max: i64) -> Option<Vec<usize>> {
+        let upper = self.decode(self.maximum);
+        if min > upper || max < self.offset || min > max {
+            return Some(Vec::new());
+        }
+        let min = cmp::max(min, self.offset).wrapping_sub(self.offset) as u64;
+        let max = cmp::min(max, upper).wrapping_sub(self.offset) as u64;
+        Some(self.packed.lookup_range(min, max))
+    }
+}
+
+impl HeapSizeOf for DenseBitPackedColumn {
+    fn heap_size_of_children(&self) -> usize {
+        self.packed.heap_size_of_children()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::i64;
+    use mem_store::column_builder::*;
+    use mem_store::table::CodecHint;
+
+    fn brute_force(values: &[u64], min: u64, max: u64) -> Vec<usize> {
+        values.iter().enumerate().filter(|&(_, &v)| min <= v && v <= max).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn test_packed_words_all_widths() {
+        for width in 1..64u8 {
+            let mask = (1u64 << width) - 1;
+            let values = (0..200u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15) & mask).collect::<Vec<_>>();
+            let packed = PackedWords::new(values.iter().cloned(), width, values.len());
+            assert_eq!(packed.unpack(|v| v), values, "width {}", width);
+            assert_eq!(packed.get(137), values[137]);
+            for &(min, max) in &[(0, mask), (mask / 3, mask / 2), (0, 0), (mask, mask), (values[5], values[5])] {
+                assert_eq!(packed.lookup_range(min, max), brute_force(&values, min, max), "width {}", width);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bit_packed_column() {
+        let values = (0..1000).map(|i| 1000 + i * 7 % 300).collect::<Vec<i64>>();
+        let mut builder = IntColBuilder::with_codec(Some(CodecHint::Packed));
+        for value in &values {
+            builder.push(value);
+        }
+        let column = builder.finalize();
+        assert_eq!(column.codec_name(), "bit packed");
+        assert!(column.heap_size_of_children() <= values.len() * 12 / 8);
+        assert_eq!(column.collect_decoded().cast_ref_i64(), &values[..]);
+        assert_eq!(column.index_decode(&[999, 0, 7]).cast_ref_i64(), &[values[999], values[0], values[7]]);
+        let filter = (0..1000).map(|i| i % 100 == 3).collect::<BitVec>();
+        assert_eq!(column.filter_decode(&filter).cast_ref_i64(),
+                   &(0..10).map(|i| values[i * 100 + 3]).collect::<Vec<_>>()[..]);
+        let codec = column.to_codec().unwrap();
+        assert!(match codec.encoding_type() { EncodingType::U16 => true, _ => false });
+        assert_eq!(codec.unwrap_decode(&codec.get_encoded()).cast_ref_i64(), &values[..]);
+        let expected = values.iter().enumerate().filter(|&(_, &v)| 1100 <= v && v <= 1150).map(|(i, _)| i).collect();
+        assert_eq!(column.lookup_range(1100, 1150), Some(expected));
+        assert_eq!(column.lookup_range(0, 999), Some(vec![]));
+        let mask = codec.range_mask(0, 99);
+        assert_eq!(mask.len(), 1000);
+        assert_eq!(mask.iter().filter(|&m| m).count(), values.iter().filter(|&&v| v < 1100).count());
+        assert!(codec.range_mask(-5, -1).none());
+    }
+
+    #[test]
+    fn test_dense_words_all_widths() {
+        for width in 1..64u8 {
+            let mask = (1u64 << width) - 1;
+            let values = (0..200u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15) & mask).collect::<Vec<_>>();
+            let packed = DenseWords::new(values.iter().cloned(), width, values.len());
+            assert_eq!(packed.unpack(|v| v), values, "width {}", width);
+            assert_eq!(packed.get(137), values[137]);
+            for &(min, max) in &[(0, mask), (mask / 3, mask / 2), (mask, mask), (values[5], values[5])] {
+                assert_eq!(packed.lookup_range(min, max), brute_force(&values, min, max), "width {}", width);
+            }
+            let filter = (0..200).map(|i| i % 70 == 1).collect::<BitVec>();
+            assert_eq!(packed.filter(&filter, |v| v), vec![values[1], values[71], values[141]]);
+        }
+    }
+
+    #[test]
+    fn test_dense_bit_packed_column() {
+        let values = (0..1000).map(|i| -(1 << 40) + i * 0x3_0000_0007 % (1 << 41)).collect::<Vec<i64>>();
+        let mut builder = IntColBuilder::with_codec(Some(CodecHint::Packed));
+        for value in &values {
+            builder.push(value);
+        }
+        let column = builder.finalize();
+        assert_eq!(column.codec_name(), "bit packed");
+        assert!(column.heap_size_of_children() <= values.len() * 6);
+        assert_eq!(column.collect_decoded().cast_ref_i64(), &values[..]);
+        assert_eq!(column.index_decode(&[999, 0]).cast_ref_i64(), &[values[999], values[0]]);
+        let expected = values.iter().enumerate().filter(|&(_, &v)| v < 0).map(|(i, _)| i).collect();
+        assert_eq!(column.lookup_range(i64::MIN, -1), Some(expected));
+    }
+}
@@ -4,24 +4,44 @@ use engine::types::*;
 use std::fmt;
 use engine::typed_vec::TypedVec;
 use ingest::raw_val::RawVal;
+use mem_store::zone_map::ZoneMap;
 
 
 pub struct Column {
     name: String,
     data: Box<ColumnData>,
+    zone_map: Option<ZoneMap>,
 }
 
 impl Column {
+    /// Column without zone map, used for temporary columns that are only needed for a single query.
     pub fn new(name: String, data: Box<ColumnData>) -> Column {
         Column {
             name,
             data,
+            zone_map: None,
+        }
+    }
+
+    /// Column of a finalized batch. `null_count` is the number of nulls in the values `data` was built from.
+    pub fn with_zone_map(name: String, data: Box<ColumnData>, null_count: usize) -> Column {
+        let zone_map = ZoneMap::compute(data.as_ref(), null_count);
+        Column {
+            name,
+            data,
+            zone_map: Some(zone_map),
         }
     }
 
     pub fn name(&self) -> &str { &self.name }
     pub fn len(&self) -> usize { self.data().len() }
     pub fn data(&self) -> &ColumnData { self.data.as_ref() }
+    pub fn zone_map(&self) -> Option<&ZoneMap> { self.zone_map.as_ref() }
+
+    /// Heap size of the column without block compression.
+    pub fn uncompressed_size(&self) -> usize {
+        self.heap_size_of_children() - self.data().heap_size_of_children() + self.data().uncompressed_size()
+    }
 }
 
 
@@ -38,6 +58,23 @@ pub trait ColumnData: HeapSizeOf + Send + Sync {
     fn basic_type(&self) -> BasicType;
     fn to_codec(&self) -> Option<&ColumnCodec> { None }
     fn len(&self) -> usize;
+    /// Short human readable description of the encoding, e.g. `offset u16`.
+    fn codec_name(&self) -> &'static str;
+    /// Minimum and maximum value of integer columns, if known.
+    fn range(&self) -> Option<(i64, i64)> { None }
+    /// Smallest and largest value of string columns, if known without decoding the column.
+    fn string_range(&self) -> Option<(&str, &str)> { None }
+    /// Rows equal to `value` in ascending order, if the column maintains an index on its values.
+    fn lookup_str(&self, _value: &str) -> Option<Vec<usize>> { None }
+    /// Rows with values in `min..=max` in ascending order, if they can be found without decoding the whole column.
+    fn lookup_range(&self, _min: i64, _max: i64) -> Option<Vec<usize>> { None }
+    /// Values and lengths of runs of equal values, if the column is run-length encoded.
+    fn runs(&self) -> Option<(TypedVec, Vec<usize>)> { None }
+    /// Column that queries read instead of this one, for columns that cannot lend out decoded values themselves.
+    /// Block-compressed columns decompress into memory owned by the reader, which is dropped with the query.
+    fn query_reader(&self) -> Option<Box<ColumnData>> { None }
+    /// Heap size of the column without block compression.
+    fn uncompressed_size(&self) -> usize { self.heap_size_of_children() }
 
     fn full_type(&self) -> Type {
         Type::new(self.basic_type(), self.to_codec())
@@ -69,6 +106,22 @@ pub trait ColumnCodec: ColumnData {
     fn encode_int(&self, _: i64) -> RawVal {
         panic!("encode_str not supported")
     }
+
+    /// Identifies the table-wide dictionary whose codes mean the same in every batch, if any.
+    fn shared_dictionary_id(&self) -> Option<usize> { None }
+
+    /// Attaches this codec to encoded values so they are only decoded for the final output.
+    fn wrap_encoded<'a>(&'a self, _: &TypedVec<'a>) -> TypedVec<'a> {
+        panic!("wrap_encoded not supported")
+    }
+
+    /// Whether `range_mask` can compare encoded values with constants without materializing them.
+    fn supports_range_mask(&self) -> bool { false }
+
+    /// Marks all rows whose encoded value is in `min..=max`.
+    fn range_mask(&self, _min: i64, _max: i64) -> BitVec {
+        panic!("range_mask not supported")
+    }
 }
 
 impl<'a> fmt::Debug for &'a ColumnCodec {
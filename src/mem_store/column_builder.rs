@@ -1,6 +1,10 @@
+use heapsize::HeapSizeOf;
+use mem_store::delta::{self, DeltaColumn};
 use mem_store::integers::*;
+use mem_store::run_length::{self, RunLengthColumn};
 use mem_store::column::*;
 use mem_store::strings::*;
+use mem_store::table::CodecHint;
 use std::cmp;
 use std::i64;
 use std::hash::Hash;
@@ -17,13 +21,28 @@ pub trait ColumnBuilder<T: ?Sized> {
 pub struct StringColBuilder {
     data: Vec<Option<Rc<String>>>,
     uniques: UniqueValues<Option<Rc<String>>>,
+    codec: Option<CodecHint>,
 }
 
 impl StringColBuilder {
     pub fn new() -> StringColBuilder {
+        StringColBuilder::with_codec(None)
+    }
+
+    pub fn with_codec(codec: Option<CodecHint>) -> StringColBuilder {
         StringColBuilder {
             data: Vec::new(),
-            uniques: UniqueValues::new(MAX_UNIQUE_STRINGS)
+            uniques: UniqueValues::new(MAX_UNIQUE_STRINGS),
+            codec,
+        }
+    }
+
+    /// Only block-compressed columns keep track of nulls, all other string columns store them as empty strings.
+    pub fn push_null(&mut self) {
+        if self.codec == Some(CodecHint::Compressed) {
+            self.data.push(None);
+        } else {
+            self.push("");
         }
     }
 }
@@ -36,7 +55,7 @@ impl ColumnBuilder<str> for StringColBuilder {
     }
 
     fn finalize(self) -> Box<ColumnData> {
-        build_string_column(&self.data, self.uniques)
+        build_string_column(&self.data, self.uniques, self.codec)
     }
 }
 
@@ -45,14 +64,20 @@ pub struct IntColBuilder {
     data: Vec<i64>,
     min: i64,
     max: i64,
+    codec: Option<CodecHint>,
 }
 
 impl IntColBuilder {
     pub fn new() -> IntColBuilder {
+        IntColBuilder::with_codec(None)
+    }
+
+    pub fn with_codec(codec: Option<CodecHint>) -> IntColBuilder {
         IntColBuilder {
             data: Vec::new(),
             min: i64::MAX,
             max: i64::MIN,
+            codec,
         }
     }
 }
@@ -66,6 +91,22 @@ impl ColumnBuilder<i64> for IntColBuilder {
     }
 
     fn finalize(self) -> Box<ColumnData> {
+        if self.codec == Some(CodecHint::Plain) {
+            return IntegerColumn::new_plain(self.data);
+        }
+        if self.codec == Some(CodecHint::Packed) {
+            return IntegerColumn::new_bit_packed(self.data, self.min, self.max);
+        }
+        if let Some(run_ends) = run_length::clustered_runs(&self.data) {
+            let values = run_ends.iter().map(|&end| self.data[end as usize - 1]).collect();
+            return Box::new(RunLengthColumn::new(IntegerColumn::new_boxed(values, self.min, self.max), run_ends));
+        }
+        if delta::is_nearly_sorted(&self.data) {
+            let delta = DeltaColumn::new(&self.data);
+            if delta.heap_size_of_children() < IntegerColumn::encoded_size(self.data.len(), self.min, self.max) {
+                return Box::new(delta);
+            }
+        }
         IntegerColumn::new_boxed(self.data, self.min, self.max)
     }
 }
@@ -0,0 +1,310 @@
+use std::cmp;
+use std::mem;
+use std::rc::Rc;
+use std::str;
+use std::sync::{Arc, OnceLock};
+
+use bit_vec::BitVec;
+use engine::typed_vec::TypedVec;
+use engine::types::*;
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
+use lz4_flex::block::{compress_prepend_size, decompress_size_prepended};
+use mem_store::column::ColumnData;
+
+
+/// Number of strings compressed together. Queries decompress only the blocks that contain rows they touch.
+const BLOCK_ROWS: usize = 1024;
+
+/// String column whose blocks are compressed with LZ4.
+/// Decoding the column yields nulls, queries read null rows as empty strings like from any other string column.
+/// The column never holds on to decompressed blocks. Queries read it through a `CompressedStringsReader`, which
+/// owns the blocks it decompresses until the query completes. Decoding the column directly yields owned copies.
+pub struct CompressedStrings {
+    blocks: Arc<CompressedBlocks>,
+}
+
+/// Reads a `CompressedStrings` column for a single query, decompressing each block on first access.
+pub struct CompressedStringsReader {
+    blocks: Arc<CompressedBlocks>,
+    decompressed: Vec<OnceLock<Block>>,
+}
+
+struct CompressedBlocks {
+    blocks: Vec<Vec<u8>>,
+    len: usize,
+    /// Heap size of the same strings stored uncompressed
+    uncompressed_size: usize,
+    /// Smallest and largest non-null string
+    range: Option<(String, String)>,
+    /// Rows that are null, `None` if there are no nulls. Null rows are stored as empty strings in the blocks.
+    nulls: Option<BitVec>,
+}
+
+/// Concatenated strings of a block, and the end of each string.
+struct Block {
+    data: String,
+    ends: Vec<u32>,
+}
+
+impl CompressedStrings {
+    pub fn from_strings(strings: &[Option<Rc<String>>]) -> CompressedStrings {
+        let mut blocks = Vec::with_capacity(strings.len() / BLOCK_ROWS + 1);
+        let mut uncompressed_size = 0;
+        for chunk in strings.chunks(BLOCK_ROWS) {
+            // Payload is the end offset of each string, followed by the concatenated strings
+            let mut payload = Vec::with_capacity(chunk.len() * mem::size_of::<u32>());
+            let mut end = 0;
+            for string in chunk {
+                end += string.as_ref().map_or(0, |s| s.len());
+                assert!(end <= u32::MAX as usize, "block of compressed strings too large");
+                payload.extend_from_slice(&(end as u32).to_le_bytes());
+            }
+            for string in chunk.iter().flat_map(|s| s.as_ref()) {
+                payload.extend_from_slice(string.as_bytes());
+            }
+            uncompressed_size += payload.len();
+            let mut compressed = compress_prepend_size(&payload);
+            compressed.shrink_to_fit();
+            blocks.push(compressed);
+        }
+        let strs = strings.iter().flat_map(|s| s.as_ref().map(|s| s.as_str()));
+        let range = strs.clone().min().and_then(|min| Some((min.to_string(), strs.max()?.to_string())));
+        let nulls = if strings.iter().any(|s| s.is_none()) {
+            Some(strings.iter().map(|s| s.is_none()).collect::<BitVec>())
+        } else {
+            None
+        };
+        CompressedStrings {
+            blocks: Arc::new(CompressedBlocks {
+                blocks,
+                len: strings.len(),
+                uncompressed_size,
+                range,
+                nulls,
+            }),
+        }
+    }
+}
+
+impl CompressedBlocks {
+    fn decompress(&self, index: usize) -> Block {
+        let rows = cmp::min(BLOCK_ROWS, self.len - index * BLOCK_ROWS);
+        let mut payload = decompress_size_prepended(&self.blocks[index]).expect("corrupt compressed block");
+        let data = payload.split_off(rows * mem::size_of::<u32>());
+        let ends = payload.chunks(mem::size_of::<u32>())
+            .map(|end| u32::from_le_bytes([end[0], end[1], end[2], end[3]]))
+            .collect();
+        Block { data: String::from_utf8(data).expect("compressed block is not utf8"), ends }
+    }
+
+    fn string_range(&self) -> Option<(&str, &str)> {
+        self.range.as_ref().map(|&(ref min, ref max)| (min.as_str(), max.as_str()))
+    }
+
+    fn to_raw(&self, block: &Block, row: usize) -> RawVal {
+        if self.nulls.as_ref().map_or(false, |nulls| nulls[row]) {
+            RawVal::Null
+        } else {
+            RawVal::Str(block.get(row % BLOCK_ROWS).to_string())
+        }
+    }
+}
+
+impl Block {
+    fn get(&self, i: usize) -> &str {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] as usize };
+        &self.data[start..self.ends[i] as usize]
+    }
+
+    fn len(&self) -> usize { self.ends.len() }
+}
+
+impl ColumnData for CompressedStrings {
+    fn collect_decoded(&self) -> TypedVec {
+        let mut result = Vec::with_capacity(self.blocks.len);
+        for index in 0..self.blocks.blocks.len() {
+            let block = self.blocks.decompress(index);
+            result.extend((0..block.len()).map(|i| self.blocks.to_raw(&block, index * BLOCK_ROWS + i)));
+        }
+        TypedVec::Raw(result)
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        let mut result = Vec::new();
+        let mut selected = filter.iter();
+        for index in 0..self.blocks.blocks.len() {
+            let selection = selected.by_ref().take(BLOCK_ROWS).collect::<Vec<_>>();
+            if selection.iter().any(|&s| s) {
+                let block = self.blocks.decompress(index);
+                result.extend(selection.iter().enumerate()
+                    .filter(|&(_, &s)| s)
+                    .map(|(i, _)| self.blocks.to_raw(&block, index * BLOCK_ROWS + i)));
+            }
+        }
+        TypedVec::Raw(result)
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        let mut result = Vec::with_capacity(filter.len());
+        let mut current: Option<(usize, Block)> = None;
+        for &row in filter {
+            let index = row / BLOCK_ROWS;
+            if current.as_ref().map_or(true, |&(i, _)| i != index) {
+                current = Some((index, self.blocks.decompress(index)));
+            }
+            let block = &current.as_ref().unwrap().1;
+            result.push(self.blocks.to_raw(block, row));
+        }
+        TypedVec::Raw(result)
+    }
+
+    fn basic_type(&self) -> BasicType { BasicType::String }
+
+    fn len(&self) -> usize { self.blocks.len }
+
+    fn codec_name(&self) -> &'static str { "lz4" }
+
+    fn string_range(&self) -> Option<(&str, &str)> { self.blocks.string_range() }
+
+    fn uncompressed_size(&self) -> usize { self.blocks.uncompressed_size }
+
+    fn query_reader(&self) -> Option<Box<ColumnData>> {
+        Some(Box::new(CompressedStringsReader {
+            decompressed: (0..self.blocks.blocks.len()).map(|_| OnceLock::new()).collect(),
+            blocks: self.blocks.clone(),
+        }))
+    }
+}
+
+impl HeapSizeOf for CompressedStrings {
+    fn heap_size_of_children(&self) -> usize {
+        let blocks = &self.blocks.blocks;
+        blocks.iter().map(|block| block.heap_size_of_children()).sum::<usize>()
+            + blocks.capacity() * mem::size_of::<Vec<u8>>()
+            + self.blocks.range.heap_size_of_children()
+            + self.blocks.nulls.as_ref().map_or(0, |nulls| nulls.capacity() / 8)
+    }
+}
+
+impl CompressedStringsReader {
+    fn block(&self, index: usize) -> &Block {
+        self.decompressed[index].get_or_init(|| self.blocks.decompress(index))
+    }
+}
+
+impl ColumnData for CompressedStringsReader {
+    fn collect_decoded(&self) -> TypedVec {
+        let mut result = Vec::with_capacity(self.blocks.len);
+        for index in 0..self.decompressed.len() {
+            let block = self.block(index);
+            result.extend((0..block.len()).map(|i| block.get(i)));
+        }
+        TypedVec::String(result)
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        let mut result = Vec::new();
+        let mut selected = filter.iter();
+        for index in 0..self.decompressed.len() {
+            let selection = selected.by_ref().take(BLOCK_ROWS).collect::<Vec<_>>();
+            if selection.iter().any(|&s| s) {
+                let block = self.block(index);
+                result.extend(selection.iter().enumerate().filter(|&(_, &s)| s).map(|(i, _)| block.get(i)));
+            }
+        }
+        TypedVec::String(result)
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        TypedVec::String(filter.iter().map(|&row| self.block(row / BLOCK_ROWS).get(row % BLOCK_ROWS)).collect())
+    }
+
+    fn basic_type(&self) -> BasicType { BasicType::String }
+
+    fn len(&self) -> usize { self.blocks.len }
+
+    fn codec_name(&self) -> &'static str { "lz4" }
+
+    fn string_range(&self) -> Option<(&str, &str)> { self.blocks.string_range() }
+}
+
+impl HeapSizeOf for CompressedStringsReader {
+    /// Only counts decompressed blocks, the compressed blocks belong to the column.
+    fn heap_size_of_children(&self) -> usize {
+        self.decompressed.iter()
+            .filter_map(|block| block.get())
+            .map(|block| block.data.heap_size_of_children() + block.ends.heap_size_of_children())
+            .sum::<usize>()
+            + self.decompressed.capacity() * mem::size_of::<OnceLock<Block>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings() -> Vec<Option<Rc<String>>> {
+        (0..3000).map(|i| Some(Rc::new(format!("{{\"id\":{},\"name\":\"user{}\",\"active\":true}}", i, i % 10)))).collect()
+    }
+
+    fn raw_strings(strings: &[&str]) -> Vec<RawVal> {
+        strings.iter().map(|s| RawVal::Str(s.to_string())).collect()
+    }
+
+    #[test]
+    fn test_compressed_strings_round_trip() {
+        let strings = strings();
+        let column = CompressedStrings::from_strings(&strings);
+        let expected = strings.iter().map(|s| s.as_ref().unwrap().as_str()).collect::<Vec<_>>();
+        let reader = column.query_reader().unwrap();
+        assert_eq!(reader.collect_decoded().cast_ref_str(), &expected[..]);
+        let decoded = column.collect_decoded();
+        assert_eq!((0..decoded.len()).map(|i| decoded.get_raw(i)).collect::<Vec<_>>(), raw_strings(&expected));
+        assert_eq!(column.len(), 3000);
+        assert_eq!(reader.len(), 3000);
+    }
+
+    #[test]
+    fn test_compressed_strings_decompress_touched_blocks() {
+        let strings = strings();
+        let column = CompressedStrings::from_strings(&strings);
+        let compressed_size = column.heap_size_of_children();
+        assert!(compressed_size * 2 < column.uncompressed_size());
+
+        let reader = column.query_reader().unwrap();
+        let nothing_decompressed = reader.heap_size_of_children();
+        assert_eq!(reader.index_decode(&[2999, 5]).cast_ref_str(),
+                   &[strings[2999].as_ref().unwrap().as_str(), strings[5].as_ref().unwrap().as_str()]);
+        let two_blocks = reader.heap_size_of_children();
+        assert!(two_blocks > nothing_decompressed);
+        let filter = (0..3000).map(|i| i == 1500 || i == 1501).collect::<BitVec>();
+        assert_eq!(reader.filter_decode(&filter).cast_ref_str().len(), 2);
+        assert!(reader.heap_size_of_children() > two_blocks);
+        // Reading never changes the size of the column itself
+        assert_eq!(column.heap_size_of_children(), compressed_size);
+
+        let decoded = column.index_decode(&[1500, 0, 1]);
+        assert_eq!(decoded.get_raw(0), RawVal::Str(strings[1500].as_ref().unwrap().to_string()));
+        assert_eq!(column.filter_decode(&filter).len(), 2);
+    }
+
+    #[test]
+    fn test_compressed_strings_nulls_and_empty() {
+        let strings = vec![None, Some(Rc::new("a".to_string())), Some(Rc::new(String::new()))];
+        let column = CompressedStrings::from_strings(&strings);
+        assert_eq!(column.query_reader().unwrap().collect_decoded().cast_ref_str(), &["", "a", ""]);
+        assert_eq!(column.string_range(), Some(("", "a")));
+        let decoded = column.collect_decoded();
+        assert_eq!((0..decoded.len()).map(|i| decoded.get_raw(i)).collect::<Vec<_>>(),
+                   vec![RawVal::Null, RawVal::Str("a".to_string()), RawVal::Str(String::new())]);
+        assert_eq!(column.index_decode(&[2, 0]).get_raw(1), RawVal::Null);
+        let filter = [true, false, true].iter().cloned().collect::<BitVec>();
+        assert_eq!(column.filter_decode(&filter).get_raw(0), RawVal::Null);
+
+        let only_nulls = CompressedStrings::from_strings(&[None, None]);
+        assert_eq!(only_nulls.string_range(), None);
+        assert_eq!(only_nulls.collect_decoded().get_raw(1), RawVal::Null);
+        assert_eq!(CompressedStrings::from_strings(&[]).collect_decoded().len(), 0);
+    }
+}
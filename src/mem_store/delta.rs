@@ -0,0 +1,400 @@
+use std::cmp::{max, min, Ordering};
+use std::{mem, u32};
+
+use bit_vec::BitVec;
+use engine::typed_vec::TypedVec;
+use engine::types::*;
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
+use mem_store::column::{ColumnData, ColumnCodec};
+use mem_store::point_codec::PointCodec;
+
+
+/// Number of values per block. Blocks are the unit of decoding, and range filters skip whole blocks.
+const BLOCK_SIZE: usize = 128;
+
+/// Integer column that stores the differences between consecutive values, bit-packed per block.
+/// Blocks whose deltas vary little store the differences between consecutive deltas instead.
+/// If the values span at most `u32::MAX`, operators on encoded data receive them as u32 offsets from the minimum.
+pub struct DeltaColumn {
+    blocks: Vec<Block>,
+    data: Vec<u64>,
+    len: usize,
+    range: Option<(i64, i64)>,
+    /// Whether values are in ascending order, which allows range lookups to binary search for blocks.
+    sorted: bool,
+}
+
+struct Block {
+    first: i64,
+    /// Difference between the first two values, only used for delta-of-deltas
+    first_delta: i64,
+    min: i64,
+    max: i64,
+    /// Bit offset of the packed differences in `data`
+    offset: usize,
+    width: u8,
+    /// 1 for deltas, 2 for delta-of-deltas
+    order: u8,
+}
+
+impl DeltaColumn {
+    pub fn new(values: &[i64]) -> DeltaColumn {
+        let mut blocks = Vec::with_capacity(values.len() / BLOCK_SIZE + 1);
+        let mut data = Vec::new();
+        let mut bit_len = 0;
+        for chunk in values.chunks(BLOCK_SIZE) {
+            let deltas = differences(chunk);
+            let first_delta = deltas.first().cloned().unwrap_or(0);
+            let delta_deltas = differences(&deltas);
+            let (order, encoded) = if delta_deltas.len() * packed_width(&delta_deltas) < deltas.len() * packed_width(&deltas) {
+                (2, delta_deltas)
+            } else {
+                (1, deltas)
+            };
+            let width = packed_width(&encoded) as u8;
+            blocks.push(Block {
+                first: chunk[0],
+                first_delta,
+                min: *chunk.iter().min().unwrap(),
+                max: *chunk.iter().max().unwrap(),
+                offset: bit_len,
+                width,
+                order,
+            });
+            for &value in &encoded {
+                pack(&mut data, bit_len, width, zigzag(value));
+                bit_len += width as usize;
+            }
+        }
+        data.shrink_to_fit();
+        let range = match (values.iter().min(), values.iter().max()) {
+            (Some(&min), Some(&max)) => Some((min, max)),
+            _ => None,
+        };
+        DeltaColumn {
+            blocks,
+            data,
+            len: values.len(),
+            range,
+            sorted: values.windows(2).all(|w| w[0] <= w[1]),
+        }
+    }
+
+    fn block_len(&self, block: usize) -> usize {
+        min(BLOCK_SIZE, self.len - block * BLOCK_SIZE)
+    }
+
+    /// Appends the values of `block` to `output`.
+    fn decode_block(&self, index: usize, output: &mut Vec<i64>) {
+        let block = &self.blocks[index];
+        let mut value = block.first;
+        let mut delta = 0i64;
+        let mut offset = block.offset;
+        output.push(value);
+        for i in 1..self.block_len(index) {
+            delta = if block.order == 2 && i == 1 {
+                block.first_delta
+            } else {
+                let encoded = unzigzag(unpack(&self.data, offset, block.width));
+                offset += block.width as usize;
+                if block.order == 1 { encoded } else { delta.wrapping_add(encoded) }
+            };
+            value = value.wrapping_add(delta);
+            output.push(value);
+        }
+    }
+
+    /// Decodes all values and converts them with `convert`.
+    fn decode_all<T, F: Fn(i64) -> T>(&self, convert: F) -> Vec<T> {
+        let mut result = Vec::with_capacity(self.len);
+        let mut decoded = Vec::with_capacity(BLOCK_SIZE);
+        for block in 0..self.blocks.len() {
+            decoded.clear();
+            self.decode_block(block, &mut decoded);
+            result.extend(decoded.iter().map(|&value| convert(value)));
+        }
+        result
+    }
+
+    /// Decodes the values selected by `filter`, skipping blocks without selected values.
+    fn decode_filtered<T, F: Fn(i64) -> T>(&self, filter: &BitVec, convert: F) -> Vec<T> {
+        let mut result = Vec::new();
+        let mut decoded = Vec::with_capacity(BLOCK_SIZE);
+        let mut selected = filter.iter();
+        for block in 0..self.blocks.len() {
+            let selection = selected.by_ref().take(self.block_len(block)).collect::<Vec<_>>();
+            if selection.iter().any(|&s| s) {
+                decoded.clear();
+                self.decode_block(block, &mut decoded);
+                result.extend(decoded.iter().zip(selection).filter(|&(_, s)| s).map(|(&value, _)| convert(value)));
+            }
+        }
+        result
+    }
+
+    /// Decodes the values at `indices`. Indices are visited in ascending order so that each block is decoded at most
+    /// once, even if `indices` jump between blocks (e.g. sort indices).
+    fn decode_indices<T, F: Fn(i64) -> T>(&self, indices: &[usize], convert: F) -> Vec<T> {
+        let mut order = (0..indices.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&position| indices[position]);
+        let mut values = vec![0; indices.len()];
+        let mut decoded = Vec::with_capacity(BLOCK_SIZE);
+        let mut current_block = None;
+        for position in order {
+            let i = indices[position];
+            let block = i / BLOCK_SIZE;
+            if current_block != Some(block) {
+                decoded.clear();
+                self.decode_block(block, &mut decoded);
+                current_block = Some(block);
+            }
+            values[position] = decoded[i % BLOCK_SIZE];
+        }
+        values.into_iter().map(convert).collect()
+    }
+
+    fn offset(&self) -> i64 { self.range.map_or(0, |(min, _)| min) }
+
+    fn encode(&self, value: i64) -> u32 { value.wrapping_sub(self.offset()) as u32 }
+
+    /// Indices of the blocks that may contain values in `min..=max`.
+    fn candidate_blocks(&self, min: i64, max: i64) -> (usize, usize) {
+        if !self.sorted {
+            return (0, self.blocks.len());
+        }
+        let first = self.blocks
+            .binary_search_by(|block| if block.max < min { Ordering::Less } else { Ordering::Greater })
+            .unwrap_err();
+        let last = self.blocks
+            .binary_search_by(|block| if block.min <= max { Ordering::Less } else { Ordering::Greater })
+            .unwrap_err();
+        (first, last)
+    }
+}
+
+impl ColumnData for DeltaColumn {
+    fn collect_decoded(&self) -> TypedVec {
+        TypedVec::Integer(self.decode_all(|value| value))
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        TypedVec::Integer(self.decode_filtered(filter, |value| value))
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        TypedVec::Integer(self.decode_indices(filter, |value| value))
+    }
+
+    fn basic_type(&self) -> BasicType { BasicType::Integer }
+
+    fn len(&self) -> usize { self.len }
+
+    fn to_codec(&self) -> Option<&ColumnCodec> {
+        match self.range {
+            Some((min, max)) if max.checked_sub(min).map_or(false, |range| range <= u32::MAX as i64) =>
+                Some(self as &ColumnCodec),
+            _ => None,
+        }
+    }
+
+    fn codec_name(&self) -> &'static str { "delta" }
+
+    fn range(&self) -> Option<(i64, i64)> { self.range }
+
+    fn lookup_range(&self, min: i64, max: i64) -> Option<Vec<usize>> {
+        let (first, last) = self.candidate_blocks(min, max);
+        let mut rows = Vec::new();
+        let mut decoded = Vec::with_capacity(BLOCK_SIZE);
+        for (index, block) in self.blocks.iter().enumerate().take(last).skip(first) {
+            let start = index * BLOCK_SIZE;
+            if block.max < min || block.min > max {
+                continue;
+            } else if min <= block.min && block.max <= max {
+                rows.extend(start..start + self.block_len(index));
+            } else {
+                decoded.clear();
+                self.decode_block(index, &mut decoded);
+                rows.extend(decoded.iter().enumerate()
+                    .filter(|&(_, &value)| min <= value && value <= max)
+                    .map(|(i, _)| start + i));
+            }
+        }
+        Some(rows)
+    }
+}
+
+impl PointCodec<u32> for DeltaColumn {
+    fn decode(&self, data: &[u32]) -> TypedVec {
+        TypedVec::Integer(data.iter().map(|&code| code as i64 + self.offset()).collect())
+    }
+
+    fn index_decode(&self, data: &[u32], indices: &[usize]) -> TypedVec {
+        TypedVec::Integer(indices.iter().map(|&i| data[i] as i64 + self.offset()).collect())
+    }
+
+    fn to_raw(&self, elem: u32) -> RawVal { RawVal::Int(elem as i64 + self.offset()) }
+
+    fn max_cardinality(&self) -> usize {
+        self.range.map_or(0, |(min, max)| (max - min) as usize)
+    }
+}
+
+impl ColumnCodec for DeltaColumn {
+    fn get_encoded(&self) -> TypedVec {
+        TypedVec::EncodedU32(self.decode_all(|value| self.encode(value)), self as &PointCodec<u32>)
+    }
+
+    fn unwrap_decode<'a>(&'a self, data: &TypedVec<'a>) -> TypedVec<'a> {
+        self.decode(data.cast_ref_u32().0)
+    }
+
+    fn filter_encoded(&self, filter: &BitVec) -> TypedVec {
+        TypedVec::EncodedU32(self.decode_filtered(filter, |value| self.encode(value)), self as &PointCodec<u32>)
+    }
+
+    fn index_encoded(&self, filter: &[usize]) -> TypedVec {
+        TypedVec::EncodedU32(self.decode_indices(filter, |value| self.encode(value)), self as &PointCodec<u32>)
+    }
+
+    fn encode_int(&self, val: i64) -> RawVal {
+        RawVal::Int(val.saturating_sub(self.offset()))
+    }
+
+    fn is_summation_preserving(&self) -> bool { self.offset() == 0 }
+    fn is_order_preserving(&self) -> bool { true }
+    fn is_positive_integer(&self) -> bool { true }
+    fn encoding_type(&self) -> EncodingType { EncodingType::U32 }
+    fn encoding_range(&self) -> Option<(i64, i64)> { Some((0, self.max_cardinality() as i64)) }
+}
+
+impl HeapSizeOf for DeltaColumn {
+    fn heap_size_of_children(&self) -> usize {
+        self.data.heap_size_of_children() + self.blocks.capacity() * mem::size_of::<Block>()
+    }
+}
+
+fn differences(values: &[i64]) -> Vec<i64> {
+    values.windows(2).map(|w| w[1].wrapping_sub(w[0])).collect()
+}
+
+fn packed_width(values: &[i64]) -> usize {
+    let max_encoded = values.iter().map(|&value| zigzag(value)).max().unwrap_or(0);
+    (64 - max_encoded.leading_zeros()) as usize
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn pack(data: &mut Vec<u64>, offset: usize, width: u8, value: u64) {
+    if width == 0 {
+        return;
+    }
+    let (word, shift) = (offset / 64, offset % 64);
+    while data.len() <= (offset + width as usize - 1) / 64 {
+        data.push(0);
+    }
+    data[word] |= value << shift;
+    if shift + width as usize > 64 {
+        data[word + 1] |= value >> (64 - shift);
+    }
+}
+
+fn unpack(data: &[u64], offset: usize, width: u8) -> u64 {
+    if width == 0 {
+        return 0;
+    }
+    let (word, shift) = (offset / 64, offset % 64);
+    let mut value = data[word] >> shift;
+    if shift + width as usize > 64 {
+        value |= data[word + 1] << (64 - shift);
+    }
+    if width == 64 { value } else { value & ((1 << width) - 1) }
+}
+
+/// Whether at most one in 16 consecutive pairs of values is out of order.
+pub fn is_nearly_sorted(values: &[i64]) -> bool {
+    let unsorted = values.windows(2).filter(|w| w[0] > w[1]).count();
+    unsorted * 16 <= max(values.len(), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{i64, u16};
+
+    fn decoded(column: &DeltaColumn) -> Vec<i64> {
+        column.collect_decoded().cast_ref_i64().to_vec()
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let timestamps = (0..1000).map(|i| 1_500_000_000 + i * 7 + i % 3).collect::<Vec<_>>();
+        let mut nearly_sorted = timestamps.clone();
+        nearly_sorted.swap(10, 11);
+        nearly_sorted.swap(500, 530);
+        let extremes = vec![i64::MIN, i64::MAX, 0, -1, i64::MAX, i64::MIN + 1];
+        for values in &[timestamps, nearly_sorted, extremes, vec![42], vec![]] {
+            let column = DeltaColumn::new(values);
+            assert_eq!(&decoded(&column), values);
+            assert_eq!(column.len(), values.len());
+        }
+    }
+
+    #[test]
+    fn test_delta_of_delta_compresses_constant_steps() {
+        let values = (0..1024).map(|i| 1_500_000_000 + i * 60).collect::<Vec<_>>();
+        let column = DeltaColumn::new(&values);
+        assert!(column.blocks.iter().all(|block| block.order == 2));
+        assert!(column.data.len() < 64);
+        assert_eq!(decoded(&column), values);
+    }
+
+    #[test]
+    fn test_delta_partial_decode() {
+        let values = (0..1000).map(|i| i * i).collect::<Vec<_>>();
+        let column = DeltaColumn::new(&values);
+        let indices = vec![999, 3, 128, 127, 500];
+        assert_eq!(ColumnData::index_decode(&column, &indices).cast_ref_i64(), &[998001, 9, 16384, 16129, 250000]);
+        let alternating = (0..1000).map(|i| if i % 2 == 0 { i } else { 999 - i }).collect::<Vec<_>>();
+        assert_eq!(ColumnData::index_decode(&column, &alternating).cast_ref_i64(),
+                   &alternating.iter().map(|&i| values[i]).collect::<Vec<_>>()[..]);
+        let filter = (0..1000).map(|i| i % 100 == 0).collect::<BitVec>();
+        assert_eq!(column.filter_decode(&filter).cast_ref_i64(),
+                   &(0..10).map(|i| i * i * 10000).collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn test_delta_lookup_range() {
+        let sorted = (0..1000).map(|i| i * 3).collect::<Vec<_>>();
+        let mut nearly_sorted = sorted.clone();
+        nearly_sorted.swap(0, 999);
+        for values in &[sorted, nearly_sorted] {
+            let column = DeltaColumn::new(values);
+            for &(min, max) in &[(100, 2000), (i64::MIN, 10), (2990, i64::MAX), (5000, 6000), (4, 5), (0, u16::MAX as i64)] {
+                let expected = values.iter().enumerate()
+                    .filter(|&(_, &v)| min <= v && v <= max)
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                assert_eq!(column.lookup_range(min, max), Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_delta_encoded() {
+        let values = (0..1000).map(|i| 1_500_000_000 + i * 7 % 900).collect::<Vec<_>>();
+        let column = DeltaColumn::new(&values);
+        let codec = column.to_codec().unwrap();
+        assert_eq!(codec.encoding_range(), Some((0, 899)));
+        assert_eq!(codec.get_encoded().cast_ref_u32().0[..3], [0, 7, 14]);
+        assert_eq!(codec.unwrap_decode(&codec.get_encoded()).cast_ref_i64(), &values[..]);
+        assert_eq!(codec.unwrap_decode(&codec.index_encoded(&[999, 1])).cast_ref_i64(), &[values[999], values[1]]);
+        assert!(DeltaColumn::new(&[i64::MIN, i64::MAX]).to_codec().is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use std::convert::From;
+use std::mem;
 use std::{u8, u16, u32};
 
 use bit_vec::BitVec;
@@ -7,6 +8,7 @@ use engine::types::*;
 use engine::vector_op::types::IntVecType;
 use heapsize::HeapSizeOf;
 use ingest::raw_val::RawVal;
+use mem_store::bit_packing::{self, BitPackedColumn, DenseBitPackedColumn};
 use mem_store::column::{ColumnData, ColumnCodec};
 use mem_store::point_codec::PointCodec;
 use num::traits::NumCast;
@@ -14,23 +16,67 @@ use num::traits::NumCast;
 
 pub struct IntegerColumn {
     values: Vec<i64>,
+    range: Option<(i64, i64)>,
 }
 
 impl IntegerColumn {
+    /// Bit-packs the values if that saves enough memory over rounding their width up to u8/u16/u32/i64.
     // TODO(clemens): do not subtract offset if it does not change encoding size
-    pub fn new_boxed(mut values: Vec<i64>, min: i64, max: i64) -> Box<ColumnData> {
-        let maximum = (max - min) as usize;
-        if max - min <= From::from(u8::MAX) {
-            Box::new(IntegerOffsetColumn::<u8>::new(values, min, maximum))
-        } else if max - min <= From::from(u16::MAX) {
-            Box::new(IntegerOffsetColumn::<u16>::new(values, min, maximum))
-        } else if max - min <= From::from(u32::MAX) {
-            Box::new(IntegerOffsetColumn::<u32>::new(values, min, maximum))
-        } else {
-            values.shrink_to_fit();
-            Box::new(IntegerColumn { values })
+    pub fn new_boxed(values: Vec<i64>, min: i64, max: i64) -> Box<ColumnData> {
+        if let Some(range) = max.checked_sub(min) {
+            if bit_packing::is_worthwhile(values.len(), range as u64, IntegerColumn::value_size(min, max)) {
+                return IntegerColumn::new_bit_packed(values, min, max);
+            }
+        }
+        match max.checked_sub(min) {
+            Some(range) if range <= From::from(u8::MAX) => Box::new(IntegerOffsetColumn::<u8>::new(values, min, range as usize)),
+            Some(range) if range <= From::from(u16::MAX) => Box::new(IntegerOffsetColumn::<u16>::new(values, min, range as usize)),
+            Some(range) if range <= From::from(u32::MAX) => Box::new(IntegerOffsetColumn::<u32>::new(values, min, range as usize)),
+            _ => IntegerColumn::new_plain(values),
+        }
+    }
+
+    /// Bit-packs the offset values instead of rounding their width up to u8/u16/u32/i64.
+    /// Ranges of more than 31 bits are packed densely and can only be read decoded.
+    pub fn new_bit_packed(values: Vec<i64>, min: i64, max: i64) -> Box<ColumnData> {
+        match max.checked_sub(min) {
+            Some(range) if bit_packing::is_dense(bit_packing::bit_width(range as u64)) =>
+                Box::new(DenseBitPackedColumn::new(&values, min, range as u64)),
+            Some(range) if range <= From::from(u8::MAX) => Box::new(BitPackedColumn::<u8>::new(&values, min, range as usize)),
+            Some(range) if range <= From::from(u16::MAX) => Box::new(BitPackedColumn::<u16>::new(&values, min, range as usize)),
+            Some(range) => Box::new(BitPackedColumn::<u32>::new(&values, min, range as usize)),
+            None => IntegerColumn::new_plain(values),
+        }
+    }
+
+    /// Number of bytes per value used for values between `min` and `max` without bit packing.
+    pub fn value_size(min: i64, max: i64) -> usize {
+        match max.checked_sub(min) {
+            Some(range) if range <= From::from(u8::MAX) => 1,
+            Some(range) if range <= From::from(u16::MAX) => 2,
+            Some(range) if range <= From::from(u32::MAX) => 4,
+            _ => mem::size_of::<i64>(),
+        }
+    }
+
+    /// Number of bytes used by `new_boxed` for `len` values between `min` and `max`.
+    pub fn encoded_size(len: usize, min: i64, max: i64) -> usize {
+        let value_size = IntegerColumn::value_size(min, max);
+        match max.checked_sub(min) {
+            Some(range) if bit_packing::is_worthwhile(len, range as u64, value_size) =>
+                bit_packing::packed_size(len, range as u64),
+            _ => len * value_size,
         }
     }
+
+    pub fn new_plain(mut values: Vec<i64>) -> Box<ColumnData> {
+        values.shrink_to_fit();
+        let range = match (values.iter().min(), values.iter().max()) {
+            (Some(&min), Some(&max)) => Some((min, max)),
+            _ => None,
+        };
+        Box::new(IntegerColumn { values, range })
+    }
 }
 
 impl ColumnData for IntegerColumn {
@@ -59,6 +105,10 @@ impl ColumnData for IntegerColumn {
     fn basic_type(&self) -> BasicType { BasicType::Integer }
 
     fn len(&self) -> usize { self.values.len() }
+
+    fn codec_name(&self) -> &'static str { "i64" }
+
+    fn range(&self) -> Option<(i64, i64)> { self.range }
 }
 
 
@@ -108,6 +158,10 @@ impl<T: IntLike + IntVecType<T>> ColumnData for IntegerOffsetColumn<T> {
     fn to_codec(&self) -> Option<&ColumnCodec> { Some(self as &ColumnCodec) }
 
     fn len(&self) -> usize { self.values.len() }
+
+    fn codec_name(&self) -> &'static str { T::codec_name() }
+
+    fn range(&self) -> Option<(i64, i64)> { Some((self.offset, self.offset + self.maximum as i64)) }
 }
 
 impl<'a, T: IntLike + IntVecType<T> + 'a> PointCodec<T> for IntegerOffsetColumn<T> {
@@ -177,10 +231,11 @@ impl HeapSizeOf for IntegerColumn {
     }
 }
 
-trait IntLike: NumCast + HeapSizeOf + Copy + Send + Sync {
+pub trait IntLike: NumCast + HeapSizeOf + Copy + Send + Sync {
     fn borrowed_typed_vec<'a>(values: &'a [Self], codec: &'a PointCodec<Self>) -> TypedVec<'a>;
     fn typed_vec(values: Vec<Self>, codec: &PointCodec<Self>) -> TypedVec;
     fn t() -> EncodingType;
+    fn codec_name() -> &'static str;
 }
 
 impl IntLike for u8 {
@@ -193,6 +248,8 @@ impl IntLike for u8 {
     }
 
     fn t() -> EncodingType { EncodingType::U8 }
+
+    fn codec_name() -> &'static str { "offset u8" }
 }
 
 impl IntLike for u16 {
@@ -205,6 +262,8 @@ impl IntLike for u16 {
     }
 
     fn t() -> EncodingType { EncodingType::U16 }
+
+    fn codec_name() -> &'static str { "offset u16" }
 }
 
 impl IntLike for u32 {
@@ -217,6 +276,8 @@ impl IntLike for u32 {
     }
 
     fn t() -> EncodingType { EncodingType::U32 }
+
+    fn codec_name() -> &'static str { "offset u32" }
 }
 
 impl<T: IntLike> HeapSizeOf for IntegerOffsetColumn<T> {
@@ -6,6 +6,12 @@ pub mod column_builder;
 pub mod table;
 pub mod null_column;
 pub mod raw_col;
+pub mod zone_map;
+pub mod shared_dictionary;
+mod bit_packing;
+mod compressed_strings;
+mod delta;
 mod integers;
 mod strings;
 mod mixed_column;
+mod run_length;
@@ -31,6 +31,8 @@ impl ColumnData for NullColumn {
     fn basic_type(&self) -> BasicType { BasicType::Null }
 
     fn len(&self) -> usize { self.length }
+
+    fn codec_name(&self) -> &'static str { "null" }
 }
 
 impl HeapSizeOf for NullColumn {
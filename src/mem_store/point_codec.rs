@@ -7,4 +7,7 @@ pub trait PointCodec<T>: Sync + Send {
     fn index_decode(&self, data: &[T], indices: &[usize]) -> TypedVec;
     fn to_raw(&self, elem: T) -> RawVal;
     fn max_cardinality(&self) -> usize;
+
+    /// Identifies the table-wide dictionary whose codes mean the same in every batch, if any.
+    fn shared_dictionary_id(&self) -> Option<usize> { None }
 }
@@ -5,6 +5,9 @@ use std::ops::BitOr;
 use std::iter::repeat;
 use ingest::raw_val::RawVal;
 use mem_store::null_column::NullColumn;
+use mem_store::shared_dictionary::{SharedDictColumn, SharedDictionaries};
+use mem_store::table::{ColumnSchema, ColumnType};
+use QueryError;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct RawCol {
@@ -50,6 +53,14 @@ impl RawCol {
         self.data.len()
     }
 
+    pub fn null_count(&self) -> usize {
+        if self.types.contains_null {
+            self.data.iter().filter(|v| **v == RawVal::Null).count()
+        } else {
+            0
+        }
+    }
+
     pub fn finalize(self) -> Box<ColumnData> {
         if self.types.contains_string {
             let mut builder = StringColBuilder::new();
@@ -57,7 +68,7 @@ impl RawCol {
                 match v {
                     RawVal::Str(s) => builder.push(&s),
                     RawVal::Int(i) => builder.push(&i.to_string()),
-                    RawVal::Null => builder.push(""),
+                    RawVal::Null => builder.push_null(),
                 }
             }
             builder.finalize()
@@ -77,6 +88,55 @@ impl RawCol {
     }
 }
 
+impl RawCol {
+    /// Like `finalize`, but always produces a column of the declared type encoded with the declared codec (if any).
+    /// Fails if any value is not of the declared type, or null if the column is declared as `NOT NULL`.
+    pub fn finalize_as(self,
+                       declared: &ColumnSchema,
+                       dictionaries: &SharedDictionaries) -> Result<Box<ColumnData>, QueryError> {
+        let codec = declared.codec;
+        Ok(match declared.column_type {
+            ColumnType::String => {
+                let mut strings = Vec::with_capacity(self.data.len());
+                for v in self.data {
+                    match v {
+                        RawVal::Str(s) => strings.push(Some(s)),
+                        RawVal::Null if declared.nullable => strings.push(None),
+                        other => bail!(QueryError::SchemaViolation, "Column {} is declared as String but got {:?}",
+                                       declared.name, other),
+                    }
+                }
+                match dictionaries.get(&declared.name) {
+                    Some(dictionary) => Box::new(SharedDictColumn::new(
+                        dictionary, strings.iter().map(|s| s.as_ref().map_or("", |s| s.as_str())))),
+                    None => {
+                        let mut builder = StringColBuilder::with_codec(codec);
+                        for s in strings {
+                            match s {
+                                Some(s) => builder.push(&s),
+                                None => builder.push_null(),
+                            }
+                        }
+                        builder.finalize()
+                    }
+                }
+            }
+            ColumnType::Integer => {
+                let mut builder = IntColBuilder::with_codec(codec);
+                for v in self.data {
+                    match v {
+                        RawVal::Int(i) => builder.push(&i),
+                        RawVal::Null if declared.nullable => builder.push(&0),
+                        other => bail!(QueryError::SchemaViolation, "Column {} is declared as Integer but got {:?}",
+                                       declared.name, other),
+                    }
+                }
+                builder.finalize()
+            }
+        })
+    }
+}
+
 impl HeapSizeOf for RawCol {
     fn heap_size_of_children(&self) -> usize {
         self.data.heap_size_of_children()
@@ -0,0 +1,255 @@
+use bit_vec::BitVec;
+use engine::typed_vec::TypedVec;
+use engine::types::*;
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
+use mem_store::column::{ColumnCodec, ColumnData};
+
+
+/// Minimum average number of rows per run for run-length encoding to be used.
+const MIN_AVERAGE_RUN_LENGTH: usize = 8;
+
+/// Column that stores one value per run of equal values, together with the end of each run.
+/// Run values are encoded by another column, e.g. with offset or dictionary encoding. If that column is a codec,
+/// queries read the encoded values of the runs instead of decoding the column.
+pub struct RunLengthColumn {
+    values: Box<ColumnData>,
+    /// Row after the last row of each run
+    run_ends: Vec<u32>,
+}
+
+impl RunLengthColumn {
+    pub fn new(values: Box<ColumnData>, mut run_ends: Vec<u32>) -> RunLengthColumn {
+        run_ends.shrink_to_fit();
+        RunLengthColumn { values, run_ends }
+    }
+
+    fn run_start(&self, run: usize) -> usize {
+        if run == 0 { 0 } else { self.run_ends[run - 1] as usize }
+    }
+
+    fn run_of(&self, row: usize) -> usize {
+        match self.run_ends.binary_search(&(row as u32)) {
+            Ok(run) => run + 1,
+            Err(run) => run,
+        }
+    }
+
+    /// Run of every row.
+    fn all_runs(&self) -> Vec<usize> {
+        let mut runs = Vec::with_capacity(self.len());
+        for (run, &end) in self.run_ends.iter().enumerate() {
+            for _ in self.run_start(run)..end as usize {
+                runs.push(run);
+            }
+        }
+        runs
+    }
+
+    /// Run of every row selected by `filter`.
+    fn filtered_runs(&self, filter: &BitVec) -> Vec<usize> {
+        let mut runs = Vec::new();
+        let mut run = 0;
+        for (row, selected) in filter.iter().enumerate() {
+            while row >= self.run_ends[run] as usize {
+                run += 1;
+            }
+            if selected {
+                runs.push(run);
+            }
+        }
+        runs
+    }
+
+    fn indexed_runs(&self, filter: &[usize]) -> Vec<usize> {
+        filter.iter().map(|&row| self.run_of(row)).collect()
+    }
+
+    fn codec(&self) -> &ColumnCodec {
+        self.values.to_codec().expect("run values are not encoded")
+    }
+
+    /// Rows of all runs for which `matches` is true.
+    fn matching_rows<F: Fn(usize) -> bool>(&self, matches: F) -> Vec<usize> {
+        let mut rows = Vec::new();
+        for run in 0..self.run_ends.len() {
+            if matches(run) {
+                rows.extend(self.run_start(run)..self.run_ends[run] as usize);
+            }
+        }
+        rows
+    }
+}
+
+impl ColumnData for RunLengthColumn {
+    fn collect_decoded(&self) -> TypedVec {
+        self.values.index_decode(&self.all_runs())
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        self.values.index_decode(&self.filtered_runs(filter))
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        self.values.index_decode(&self.indexed_runs(filter))
+    }
+
+    fn basic_type(&self) -> BasicType { self.values.basic_type() }
+
+    fn to_codec(&self) -> Option<&ColumnCodec> {
+        self.values.to_codec().map(|_| self as &ColumnCodec)
+    }
+
+    fn len(&self) -> usize { self.run_ends.last().map_or(0, |&end| end as usize) }
+
+    fn codec_name(&self) -> &'static str { "run length" }
+
+    fn range(&self) -> Option<(i64, i64)> { self.values.range() }
+
+    fn string_range(&self) -> Option<(&str, &str)> {
+        match self.values.collect_decoded() {
+            TypedVec::String(values) => Some((*values.iter().min()?, *values.iter().max()?)),
+            _ => None,
+        }
+    }
+
+    fn lookup_str(&self, value: &str) -> Option<Vec<usize>> {
+        match self.values.collect_decoded() {
+            TypedVec::String(values) => Some(self.matching_rows(|run| values[run] == value)),
+            _ => None,
+        }
+    }
+
+    fn lookup_range(&self, min: i64, max: i64) -> Option<Vec<usize>> {
+        match self.values.collect_decoded() {
+            TypedVec::Integer(values) => Some(self.matching_rows(|run| min <= values[run] && values[run] <= max)),
+            _ => None,
+        }
+    }
+
+    fn runs(&self) -> Option<(TypedVec, Vec<usize>)> {
+        let lengths = (0..self.run_ends.len()).map(|run| self.run_ends[run] as usize - self.run_start(run)).collect();
+        Some((self.values.collect_decoded(), lengths))
+    }
+}
+
+impl ColumnCodec for RunLengthColumn {
+    fn get_encoded(&self) -> TypedVec {
+        self.codec().index_encoded(&self.all_runs())
+    }
+
+    fn filter_encoded(&self, filter: &BitVec) -> TypedVec {
+        self.codec().index_encoded(&self.filtered_runs(filter))
+    }
+
+    fn index_encoded(&self, filter: &[usize]) -> TypedVec {
+        self.codec().index_encoded(&self.indexed_runs(filter))
+    }
+
+    fn encoding_type(&self) -> EncodingType { self.codec().encoding_type() }
+    fn is_summation_preserving(&self) -> bool { self.codec().is_summation_preserving() }
+    fn is_order_preserving(&self) -> bool { self.codec().is_order_preserving() }
+    fn is_positive_integer(&self) -> bool { self.codec().is_positive_integer() }
+    fn encoding_range(&self) -> Option<(i64, i64)> { self.codec().encoding_range() }
+
+    fn unwrap_decode<'a>(&'a self, data: &TypedVec<'a>) -> TypedVec<'a> {
+        self.codec().unwrap_decode(data)
+    }
+
+    fn encode_str(&self, value: &str) -> RawVal { self.codec().encode_str(value) }
+    fn encode_int(&self, value: i64) -> RawVal { self.codec().encode_int(value) }
+    fn shared_dictionary_id(&self) -> Option<usize> { self.codec().shared_dictionary_id() }
+
+    fn wrap_encoded<'a>(&'a self, data: &TypedVec<'a>) -> TypedVec<'a> {
+        self.codec().wrap_encoded(data)
+    }
+}
+
+impl HeapSizeOf for RunLengthColumn {
+    fn heap_size_of_children(&self) -> usize {
+        self.values.heap_size_of_children() + self.run_ends.heap_size_of_children()
+    }
+}
+
+/// End of each run of equal values, if runs are long enough on average to make run-length encoding worthwhile.
+pub fn clustered_runs<T: PartialEq>(values: &[T]) -> Option<Vec<u32>> {
+    let max_runs = values.len() / MIN_AVERAGE_RUN_LENGTH;
+    let mut run_ends = Vec::new();
+    for i in 1..values.len() {
+        if values[i] != values[i - 1] {
+            if run_ends.len() >= max_runs {
+                return None;
+            }
+            run_ends.push(i as u32);
+        }
+    }
+    if run_ends.len() >= max_runs {
+        return None;
+    }
+    run_ends.push(values.len() as u32);
+    Some(run_ends)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mem_store::column_builder::*;
+
+    fn tlds() -> Vec<&'static str> {
+        let mut tlds = vec!["com"; 40];
+        tlds.extend(vec!["org"; 30]);
+        tlds.extend(vec!["com"; 20]);
+        tlds.extend(vec!["net"; 10]);
+        tlds
+    }
+
+    #[test]
+    fn test_run_length_strings() {
+        let mut builder = StringColBuilder::new();
+        for tld in tlds() {
+            builder.push(tld);
+        }
+        let column = builder.finalize();
+        assert_eq!(column.codec_name(), "run length");
+        assert_eq!(column.collect_decoded().cast_ref_str(), &tlds()[..]);
+        assert_eq!(column.index_decode(&[99, 0, 40, 70, 69]).cast_ref_str(), &["net", "com", "org", "com", "org"]);
+        let filter = (0..100).map(|i| i % 25 == 0).collect::<BitVec>();
+        assert_eq!(column.filter_decode(&filter).cast_ref_str(), &["com", "com", "org", "com"]);
+        assert_eq!(column.lookup_str("net"), Some((90..100).collect()));
+        assert_eq!(column.string_range(), Some(("com", "org")));
+        let (values, lengths) = column.runs().unwrap();
+        assert_eq!(values.cast_ref_str(), &["com", "org", "com", "net"]);
+        assert_eq!(lengths, vec![40, 30, 20, 10]);
+
+        // Runs keep the dictionary codes of their values
+        let codec = column.to_codec().unwrap();
+        assert_eq!(format!("{:?}", codec.encoding_type()), "U8");
+        assert_eq!(codec.get_encoded().decode().cast_ref_str(), &tlds()[..]);
+        assert_eq!(codec.index_encoded(&[99, 0]).decode().cast_ref_str(), &["net", "com"]);
+        assert_eq!(codec.filter_encoded(&filter).decode().cast_ref_str(), &["com", "com", "org", "com"]);
+        assert_eq!(codec.encode_str("org"), RawVal::Int(2));
+    }
+
+    #[test]
+    fn test_run_length_integers() {
+        let values = (0..100).map(|i| i / 25 * 1000).collect::<Vec<i64>>();
+        let mut builder = IntColBuilder::new();
+        for value in &values {
+            builder.push(value);
+        }
+        let column = builder.finalize();
+        assert_eq!(column.codec_name(), "run length");
+        assert_eq!(column.collect_decoded().cast_ref_i64(), &values[..]);
+        assert_eq!(column.lookup_range(1000, 2000), Some((25..75).collect()));
+        assert_eq!(column.range(), Some((0, 3000)));
+    }
+
+    #[test]
+    fn test_clustered_runs() {
+        assert_eq!(clustered_runs(&[1; 16]), Some(vec![16]));
+        assert_eq!(clustered_runs(&[1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2]), Some(vec![4, 16]));
+        assert_eq!(clustered_runs(&[1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3]), None);
+        assert_eq!(clustered_runs::<i64>(&[]), None);
+        assert_eq!(clustered_runs(&(0..100).collect::<Vec<_>>()), None);
+    }
+}
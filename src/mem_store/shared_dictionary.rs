@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::u32;
+
+use bit_vec::BitVec;
+use engine::typed_vec::TypedVec;
+use engine::types::*;
+use heapsize::HeapSizeOf;
+use ingest::raw_val::RawVal;
+use mem_store::column::{ColumnData, ColumnCodec};
+use mem_store::point_codec::PointCodec;
+
+
+/// Number of strings per chunk of a `SharedDictionary`. Snapshots share all chunks with the dictionary.
+const CHUNK_SIZE: usize = 4096;
+/// Number of strings after which a `SharedDictionary` starts a new generation.
+const MAX_GENERATION_LEN: usize = 1 << 20;
+/// Number of batches after which a `SharedDictionary` starts a new generation. Batches are dropped roughly in the order
+/// they were created, so this bounds the strings that are kept alive only by batches that no longer exist.
+const MAX_GENERATION_BATCHES: usize = 256;
+
+static NEXT_DICTIONARY_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Shared dictionaries of a table, by column name.
+pub type SharedDictionaries = HashMap<String, Arc<SharedDictionary>>;
+
+/// Dictionary shared by all batches of a string column.
+/// Codes are assigned in order of first occurrence and mean the same in every batch encoded with the same generation
+/// of the dictionary. Generations are append-only and each covers a range of consecutive batches. A new one is started
+/// when the table is truncated or when the current one reaches `MAX_GENERATION_LEN` strings or
+/// `MAX_GENERATION_BATCHES` batches, and earlier generations are freed once no batch refers to them.
+pub struct SharedDictionary {
+    max_len: usize,
+    max_batches: usize,
+    current: Mutex<Arc<Generation>>,
+    /// Earlier generations, which may still be referenced by batches
+    retired: Mutex<Vec<Weak<Generation>>>,
+}
+
+struct Generation {
+    id: usize,
+    state: Mutex<DictionaryState>,
+}
+
+#[derive(Default)]
+struct DictionaryState {
+    chunks: Vec<Arc<Vec<Arc<str>>>>,
+    codes: HashMap<Arc<str>, u32>,
+    /// Number of batches encoded with this generation
+    batches: usize,
+}
+
+/// Strings of a generation of a `SharedDictionary` at the time a batch was encoded.
+#[derive(Clone)]
+struct DictionarySnapshot {
+    chunks: Vec<Arc<Vec<Arc<str>>>>,
+}
+
+impl SharedDictionary {
+    pub fn new() -> SharedDictionary {
+        SharedDictionary::with_limits(MAX_GENERATION_LEN, MAX_GENERATION_BATCHES)
+    }
+
+    fn with_limits(max_len: usize, max_batches: usize) -> SharedDictionary {
+        SharedDictionary {
+            max_len,
+            max_batches,
+            current: Mutex::new(Arc::new(Generation::new())),
+            retired: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Code of `string`, if it occurs in any batch encoded with the current generation of this dictionary.
+    pub fn code(&self, string: &str) -> Option<u32> {
+        self.current.lock().unwrap().code(string)
+    }
+
+    /// Number of strings in the current generation.
+    pub fn len(&self) -> usize {
+        self.current.lock().unwrap().state.lock().unwrap().codes.len()
+    }
+
+    /// Starts a new generation. Called when the table no longer contains any batches.
+    pub fn reset(&self) {
+        let mut current = self.current.lock().unwrap();
+        self.retire(&mut current);
+    }
+
+    /// Generation that new batches are encoded with.
+    fn generation(&self) -> Arc<Generation> {
+        let mut current = self.current.lock().unwrap();
+        let full = {
+            let state = current.state.lock().unwrap();
+            state.codes.len() >= self.max_len || state.batches >= self.max_batches
+        };
+        if full {
+            self.retire(&mut current);
+        }
+        current.clone()
+    }
+
+    fn retire(&self, current: &mut Arc<Generation>) {
+        let previous = mem::replace(current, Arc::new(Generation::new()));
+        let mut retired = self.retired.lock().unwrap();
+        retired.retain(|generation| generation.upgrade().is_some());
+        retired.push(Arc::downgrade(&previous));
+    }
+}
+
+impl Default for SharedDictionary {
+    fn default() -> SharedDictionary { SharedDictionary::new() }
+}
+
+impl Generation {
+    fn new() -> Generation {
+        Generation {
+            id: NEXT_DICTIONARY_ID.fetch_add(1, Ordering::SeqCst),
+            state: Mutex::new(DictionaryState::default()),
+        }
+    }
+
+    fn code(&self, string: &str) -> Option<u32> {
+        self.state.lock().unwrap().codes.get(string).cloned()
+    }
+}
+
+impl DictionaryState {
+    fn code_or_insert(&mut self, string: &str) -> u32 {
+        if let Some(&code) = self.codes.get(string) {
+            return code;
+        }
+        let len = self.codes.len();
+        assert!(len < u32::MAX as usize, "shared dictionary is full");
+        if len % CHUNK_SIZE == 0 {
+            self.chunks.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+        }
+        let string: Arc<str> = Arc::from(string);
+        // Only copies the last chunk if it is still referenced by a snapshot
+        Arc::make_mut(self.chunks.last_mut().unwrap()).push(string.clone());
+        self.codes.insert(string, len as u32);
+        len as u32
+    }
+}
+
+impl DictionarySnapshot {
+    fn get(&self, code: u32) -> &str {
+        let code = code as usize;
+        &self.chunks[code / CHUNK_SIZE][code % CHUNK_SIZE]
+    }
+
+    fn len(&self) -> usize {
+        self.chunks.last().map_or(0, |last| (self.chunks.len() - 1) * CHUNK_SIZE + last.len())
+    }
+}
+
+impl HeapSizeOf for SharedDictionary {
+    /// Includes earlier generations that are still referenced by batches.
+    fn heap_size_of_children(&self) -> usize {
+        let retired = self.retired.lock().unwrap().iter()
+            .filter_map(|generation| generation.upgrade())
+            .map(|generation| generation.heap_size_of_children())
+            .sum::<usize>();
+        self.current.lock().unwrap().heap_size_of_children() + retired
+    }
+}
+
+impl HeapSizeOf for Generation {
+    /// Strings are shared between chunks and codes and only counted once.
+    fn heap_size_of_children(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        let strings = state.chunks.iter()
+            .map(|chunk| chunk.capacity() * mem::size_of::<Arc<str>>()
+                + chunk.iter().map(|s| s.len() + 2 * mem::size_of::<usize>()).sum::<usize>())
+            .sum::<usize>();
+        strings + state.codes.capacity() * mem::size_of::<(Arc<str>, u32)>()
+    }
+}
+
+
+/// String column encoded with the codes of a `SharedDictionary`.
+pub struct SharedDictColumn {
+    generation: Arc<Generation>,
+    snapshot: DictionarySnapshot,
+    codes: Vec<u32>,
+}
+
+impl SharedDictColumn {
+    pub fn new<'a, I: Iterator<Item=&'a str>>(dictionary: &Arc<SharedDictionary>, strings: I) -> SharedDictColumn {
+        let generation = dictionary.generation();
+        let (codes, snapshot) = {
+            let mut state = generation.state.lock().unwrap();
+            state.batches += 1;
+            let mut codes = strings.map(|string| state.code_or_insert(string)).collect::<Vec<_>>();
+            codes.shrink_to_fit();
+            (codes, DictionarySnapshot { chunks: state.chunks.clone() })
+        };
+        SharedDictColumn {
+            generation,
+            snapshot,
+            codes,
+        }
+    }
+}
+
+impl ColumnData for SharedDictColumn {
+    fn collect_decoded(&self) -> TypedVec {
+        self.decode(&self.codes)
+    }
+
+    fn filter_decode(&self, filter: &BitVec) -> TypedVec {
+        let mut result = Vec::with_capacity(self.codes.len());
+        for (&code, selected) in self.codes.iter().zip(filter) {
+            if selected {
+                result.push(self.snapshot.get(code));
+            }
+        }
+        TypedVec::String(result)
+    }
+
+    fn index_decode(&self, filter: &[usize]) -> TypedVec {
+        PointCodec::index_decode(self, &self.codes, filter)
+    }
+
+    fn basic_type(&self) -> BasicType { BasicType::String }
+    fn to_codec(&self) -> Option<&ColumnCodec> { Some(self as &ColumnCodec) }
+    fn len(&self) -> usize { self.codes.len() }
+    fn codec_name(&self) -> &'static str { "shared dictionary" }
+}
+
+impl PointCodec<u32> for SharedDictColumn {
+    fn decode(&self, data: &[u32]) -> TypedVec {
+        TypedVec::String(data.iter().map(|&code| self.snapshot.get(code)).collect())
+    }
+
+    fn index_decode(&self, data: &[u32], filter: &[usize]) -> TypedVec {
+        TypedVec::String(filter.iter().map(|&i| self.snapshot.get(data[i])).collect())
+    }
+
+    fn to_raw(&self, elem: u32) -> RawVal {
+        RawVal::Str(self.snapshot.get(elem).to_string())
+    }
+
+    fn max_cardinality(&self) -> usize { self.snapshot.len() }
+
+    fn shared_dictionary_id(&self) -> Option<usize> { Some(self.generation.id) }
+}
+
+impl ColumnCodec for SharedDictColumn {
+    fn get_encoded(&self) -> TypedVec {
+        TypedVec::BorrowedEncodedU32(&self.codes, self as &PointCodec<u32>)
+    }
+
+    fn filter_encoded(&self, filter: &BitVec) -> TypedVec {
+        let mut result = Vec::with_capacity(self.codes.len());
+        for (&code, selected) in self.codes.iter().zip(filter) {
+            if selected {
+                result.push(code);
+            }
+        }
+        TypedVec::EncodedU32(result, self as &PointCodec<u32>)
+    }
+
+    fn index_encoded(&self, filter: &[usize]) -> TypedVec {
+        TypedVec::EncodedU32(filter.iter().map(|&i| self.codes[i]).collect(), self as &PointCodec<u32>)
+    }
+
+    fn encoding_type(&self) -> EncodingType { EncodingType::U32 }
+
+    fn unwrap_decode<'a>(&'a self, data: &TypedVec<'a>) -> TypedVec<'a> {
+        self.decode(data.cast_ref_u32().0)
+    }
+
+    fn wrap_encoded<'a>(&'a self, data: &TypedVec<'a>) -> TypedVec<'a> {
+        let codes = match *data {
+            TypedVec::Integer(ref codes) => codes.iter().map(|&code| code as u32).collect(),
+            TypedVec::USize(ref codes) => codes.iter().map(|&code| code as u32).collect(),
+            _ => data.cast_ref_u32().0.to_vec(),
+        };
+        TypedVec::EncodedU32(codes, self as &PointCodec<u32>)
+    }
+
+    fn encode_str(&self, s: &str) -> RawVal {
+        RawVal::Int(self.generation.code(s).map_or(-1, i64::from))
+    }
+
+    fn is_summation_preserving(&self) -> bool { false }
+    fn is_order_preserving(&self) -> bool { false }
+    fn is_positive_integer(&self) -> bool { true }
+    fn encoding_range(&self) -> Option<(i64, i64)> { Some((0, self.snapshot.len() as i64)) }
+    fn shared_dictionary_id(&self) -> Option<usize> { Some(self.generation.id) }
+}
+
+impl HeapSizeOf for SharedDictColumn {
+    /// Strings are accounted for by the table that owns the dictionary.
+    fn heap_size_of_children(&self) -> usize {
+        self.codes.heap_size_of_children()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_dictionary_generations() {
+        let dictionary = Arc::new(SharedDictionary::with_limits(3, MAX_GENERATION_BATCHES));
+        let first = SharedDictColumn::new(&dictionary, ["a", "b", "a", "c"].iter().cloned());
+        assert_eq!(first.codes, vec![0, 1, 0, 2]);
+        assert_eq!(dictionary.code("c"), Some(2));
+
+        // The current generation is full, so the next batch starts a new one with its own codes
+        let second = SharedDictColumn::new(&dictionary, ["c", "d"].iter().cloned());
+        assert_eq!(second.codes, vec![0, 1]);
+        assert_ne!(first.generation.id, second.generation.id);
+        assert_eq!(first.collect_decoded().cast_ref_str(), &["a", "b", "a", "c"]);
+        assert_eq!(first.encode_str("c"), RawVal::Int(2));
+        assert_eq!(dictionary.code("a"), None);
+        assert_eq!(dictionary.len(), 2);
+    }
+
+    #[test]
+    fn test_shared_dictionary_batch_ranges() {
+        let dictionary = Arc::new(SharedDictionary::with_limits(MAX_GENERATION_LEN, 2));
+        let strings = (0..1000).map(|i| format!("string{}", i)).collect::<Vec<_>>();
+        let first = SharedDictColumn::new(&dictionary, strings.iter().map(|s| s.as_str()));
+        let second = SharedDictColumn::new(&dictionary, strings.iter().map(|s| s.as_str()));
+        assert_eq!(first.generation.id, second.generation.id);
+        let full = dictionary.heap_size_of_children();
+
+        // The third batch starts a new generation, the strings of the first two are freed once both are dropped
+        let third = SharedDictColumn::new(&dictionary, ["a"].iter().cloned());
+        assert_ne!(second.generation.id, third.generation.id);
+        assert_eq!(dictionary.code("string0"), None);
+        drop(first);
+        assert!(dictionary.heap_size_of_children() >= full);
+        drop(second);
+        assert_eq!(dictionary.heap_size_of_children(), third.generation.heap_size_of_children());
+        assert_eq!(third.collect_decoded().cast_ref_str(), &["a"]);
+    }
+
+    #[test]
+    fn test_shared_dictionary_reset_frees_strings() {
+        let dictionary = Arc::new(SharedDictionary::new());
+        let strings = (0..1000).map(|i| format!("string{}", i)).collect::<Vec<_>>();
+        let column = SharedDictColumn::new(&dictionary, strings.iter().map(|s| s.as_str()));
+        let full = dictionary.heap_size_of_children();
+
+        dictionary.reset();
+        assert_eq!(dictionary.len(), 0);
+        // Still referenced by `column`
+        assert_eq!(dictionary.heap_size_of_children(), full);
+        assert_eq!(ColumnData::index_decode(&column, &[999]).cast_ref_str(), &["string999"]);
+        drop(column);
+        assert!(dictionary.heap_size_of_children() < full / 10);
+    }
+}
@@ -2,66 +2,161 @@ use bit_vec::BitVec;
 use ingest::raw_val::RawVal;
 use mem_store::column::{ColumnData, ColumnCodec};
 use mem_store::column_builder::UniqueValues;
+use mem_store::compressed_strings::CompressedStrings;
+use mem_store::integers::IntLike;
 use mem_store::point_codec::PointCodec;
+use mem_store::run_length::{self, RunLengthColumn};
+use mem_store::table::CodecHint;
 use heapsize::HeapSizeOf;
 use std::collections::hash_set::HashSet;
 use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::mem;
 use std::rc::Rc;
 use std::str;
-use std::{u8, u16};
+use std::{u16, u32};
 use engine::types::*;
 use engine::typed_vec::TypedVec;
+use engine::vector_op::types::IntVecType;
 
 
-pub const MAX_UNIQUE_STRINGS: usize = 10000;
+/// Columns with more distinct values are never dictionary encoded.
+pub const MAX_UNIQUE_STRINGS: usize = 1 << 22;
 
 pub fn build_string_column(values: &[Option<Rc<String>>],
-                           unique_values: UniqueValues<Option<Rc<String>>>)
+                           unique_values: UniqueValues<Option<Rc<String>>>,
+                           codec: Option<CodecHint>)
                            -> Box<ColumnData> {
-    if let Some(u) = unique_values.get_values() {
-        Box::new(DictEncodedStrings::from_strings(values, u))
-    } else {
-        Box::new(StringPacker::from_strings(values))
+    if codec == Some(CodecHint::Packed) {
+        let indexed = worth_indexing(values.len(), unique_values.get_values().as_ref());
+        return Box::new(StringPacker::from_strings(values, indexed));
+    }
+    if codec == Some(CodecHint::Compressed) {
+        return Box::new(CompressedStrings::from_strings(values));
+    }
+    if let Some(run_ends) = run_length::clustered_runs(values) {
+        let run_values = run_ends.iter().map(|&end| values[end as usize - 1].clone()).collect::<Vec<_>>();
+        // Run values are dictionary encoded so that queries can read the codes of runs
+        let run_codec = codec.or(Some(CodecHint::Dictionary));
+        return Box::new(RunLengthColumn::new(build_string_column(&run_values, unique_values, run_codec), run_ends));
+    }
+    match unique_values.get_values() {
+        Some(u) => {
+            // Small dictionaries are always worthwhile since encoded columns are faster to group and filter
+            let prefer_dictionary = codec == Some(CodecHint::Dictionary) || u.len() <= 1 << 8;
+            let indexed = worth_indexing(values.len(), Some(&u));
+            if !prefer_dictionary && packed_size(values, indexed) <= dictionary_size(values.len(), &u) {
+                Box::new(StringPacker::from_strings(values, indexed))
+            } else if u.len() <= 1 << 8 {
+                Box::new(DictEncodedStrings::<u8>::from_strings(values, u))
+            } else if u.len() <= 1 << 16 {
+                Box::new(DictEncodedStrings::<u16>::from_strings(values, u))
+            } else {
+                Box::new(DictEncodedStrings::<u32>::from_strings(values, u))
+            }
+        }
+        None => Box::new(StringPacker::from_strings(values, true)),
     }
 }
 
+/// Whether a `StringPacker` column should keep its rows sorted by value to look up rows equal to a constant.
+/// This pays off for columns with mostly distinct values such as ids, which are typically filtered by a single value.
+/// `unique_values` is `None` if there are too many distinct values to count.
+fn worth_indexing(rows: usize, unique_values: Option<&HashSet<Option<Rc<String>>>>) -> bool {
+    unique_values.map_or(true, |u| u.len() * 2 >= rows)
+}
+
+/// Estimated heap size of a `DictEncodedStrings` column.
+fn dictionary_size(rows: usize, unique_values: &HashSet<Option<Rc<String>>>) -> usize {
+    let code_size = if unique_values.len() <= 1 << 8 {
+        1
+    } else if unique_values.len() <= 1 << 16 {
+        2
+    } else {
+        4
+    };
+    let dictionary = unique_values.iter()
+        .map(|s| mem::size_of::<Option<String>>() + s.as_ref().map_or(0, |s| s.len()))
+        .sum::<usize>();
+    dictionary + rows * code_size
+}
+
+/// Estimated heap size of a `StringPacker` column, assuming u16 offsets.
+fn packed_size(values: &[Option<Rc<String>>], indexed: bool) -> usize {
+    let data = values.iter().map(|s| s.as_ref().map_or(0, |s| s.len())).sum::<usize>();
+    let block_starts = (values.len() / BLOCK_ROWS + 1) * mem::size_of::<usize>();
+    let offsets = values.len() * mem::size_of::<u16>();
+    let index = if indexed { values.len() * mem::size_of::<u32>() } else { 0 };
+    data + block_starts + offsets + index
+}
+
+/// Number of consecutive strings in a `StringPacker` whose offsets are stored relative to the same block start.
+const BLOCK_ROWS: usize = 64;
+
 struct StringPacker {
     data: Vec<u8>,
+    /// Offset in `data` of the first string of each block of `BLOCK_ROWS` strings
+    block_starts: Vec<usize>,
+    /// Offset of each string relative to the start of its block
+    offsets: BlockOffsets,
+    /// Rows sorted by value, used to look up rows equal to a constant without decoding the column.
+    /// Only built for columns with mostly distinct values.
+    sorted_rows: Option<Vec<u32>>,
 }
 
-// TODO(clemens): encode using variable size length + special value to represent null
-impl StringPacker {
-    pub fn new() -> StringPacker {
-        StringPacker { data: Vec::new() }
-    }
+enum BlockOffsets {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
 
-    pub fn from_strings(strings: &[Option<Rc<String>>]) -> StringPacker {
-        let mut sp = StringPacker::new();
-        for string in strings {
-            match *string {
-                Some(ref string) => sp.push(string),
-                None => sp.push(""),
+// TODO(clemens): special value to represent null
+impl StringPacker {
+    pub fn from_strings(strings: &[Option<Rc<String>>], indexed: bool) -> StringPacker {
+        let mut data = Vec::new();
+        let mut block_starts = Vec::with_capacity(strings.len() / BLOCK_ROWS + 1);
+        let mut offsets = Vec::with_capacity(strings.len());
+        for (i, string) in strings.iter().enumerate() {
+            if i % BLOCK_ROWS == 0 {
+                block_starts.push(data.len());
             }
+            offsets.push(data.len() - block_starts[i / BLOCK_ROWS]);
+            if let Some(ref string) = *string {
+                data.extend_from_slice(string.as_bytes());
+            }
+        }
+        data.shrink_to_fit();
+        let offsets = if offsets.iter().all(|&offset| offset <= u16::MAX as usize) {
+            BlockOffsets::U16(offsets.into_iter().map(|offset| offset as u16).collect())
+        } else {
+            assert!(offsets.iter().all(|&offset| offset <= u32::MAX as usize));
+            BlockOffsets::U32(offsets.into_iter().map(|offset| offset as u32).collect())
+        };
+        let mut sp = StringPacker { data, block_starts, offsets, sorted_rows: None };
+        if indexed && strings.len() <= u32::MAX as usize {
+            let mut rows = (0..strings.len() as u32).collect::<Vec<_>>();
+            rows.sort_by_key(|&row| sp.get(row as usize));
+            sp.sorted_rows = Some(rows);
         }
-        sp.shrink_to_fit();
         sp
     }
 
-    pub fn push(&mut self, string: &str) {
-        for &byte in string.as_bytes().iter() {
-            self.data.push(byte);
-        }
-        self.data.push(0);
+    fn start(&self, row: usize) -> usize {
+        let offset = match self.offsets {
+            BlockOffsets::U16(ref offsets) => offsets[row] as usize,
+            BlockOffsets::U32(ref offsets) => offsets[row] as usize,
+        };
+        self.block_starts[row / BLOCK_ROWS] + offset
     }
 
-    pub fn shrink_to_fit(&mut self) {
-        self.data.shrink_to_fit();
+    fn get(&self, row: usize) -> &str {
+        let end = if row + 1 < self.len() { self.start(row + 1) } else { self.data.len() };
+        unsafe { str::from_utf8_unchecked(&self.data[self.start(row)..end]) }
     }
 
     pub fn iter(&self) -> StringPackerIterator {
         StringPackerIterator {
-            data: &self.data,
-            curr_index: 0,
+            packer: self,
+            row: 0,
         }
     }
 }
@@ -73,74 +168,98 @@ impl ColumnData for StringPacker {
 
     fn filter_decode<'a>(&'a self, filter: &BitVec) -> TypedVec {
         let mut result = Vec::new();
-        for (s, select) in self.iter().zip(filter.iter()) {
+        for (row, select) in filter.iter().enumerate() {
             if select {
-                result.push(s);
+                result.push(self.get(row));
             }
         }
         TypedVec::String(result)
     }
 
     fn index_decode<'a>(&'a self, filter: &[usize]) -> TypedVec {
-        let decoded = self.iter().collect::<Vec<_>>();
-        let mut result = Vec::with_capacity(filter.len());
-        for &i in filter {
-            result.push(decoded[i]);
-        }
-        TypedVec::String(result)
+        TypedVec::String(filter.iter().map(|&row| self.get(row)).collect())
     }
 
     fn basic_type(&self) -> BasicType { BasicType::String }
 
-    fn len(&self) -> usize { self.iter().count() } // FIXME(clemens): O(n)
+    fn len(&self) -> usize {
+        match self.offsets {
+            BlockOffsets::U16(ref offsets) => offsets.len(),
+            BlockOffsets::U32(ref offsets) => offsets.len(),
+        }
+    }
+
+    fn codec_name(&self) -> &'static str { "packed" }
+
+    fn string_range(&self) -> Option<(&str, &str)> {
+        let sorted_rows = self.sorted_rows.as_ref()?;
+        match (sorted_rows.first(), sorted_rows.last()) {
+            (Some(&min), Some(&max)) => Some((self.get(min as usize), self.get(max as usize))),
+            _ => None,
+        }
+    }
+
+    fn lookup_str(&self, value: &str) -> Option<Vec<usize>> {
+        let sorted_rows = self.sorted_rows.as_ref()?;
+        // Comparators never return `Equal` and so yield the first position with a value `>= value` (`> value`)
+        let start = sorted_rows
+            .binary_search_by(|&row| self.get(row as usize).cmp(value).then(Ordering::Greater))
+            .unwrap_err();
+        let end = sorted_rows
+            .binary_search_by(|&row| self.get(row as usize).cmp(value).then(Ordering::Less))
+            .unwrap_err();
+        let mut rows = sorted_rows[start..end].iter().map(|&row| row as usize).collect::<Vec<_>>();
+        rows.sort();
+        Some(rows)
+    }
 }
 
 impl HeapSizeOf for StringPacker {
     fn heap_size_of_children(&self) -> usize {
+        let offsets = match self.offsets {
+            BlockOffsets::U16(ref offsets) => offsets.heap_size_of_children(),
+            BlockOffsets::U32(ref offsets) => offsets.heap_size_of_children(),
+        };
         self.data.heap_size_of_children()
+            + self.block_starts.heap_size_of_children()
+            + offsets
+            + self.sorted_rows.heap_size_of_children()
     }
 }
 
 pub struct StringPackerIterator<'a> {
-    data: &'a Vec<u8>,
-    curr_index: usize,
+    packer: &'a StringPacker,
+    row: usize,
 }
 
 impl<'a> Iterator for StringPackerIterator<'a> {
     type Item = &'a str;
 
     fn next(&mut self) -> Option<&'a str> {
-        if self.curr_index >= self.data.len() {
+        if self.row >= self.packer.len() {
             return None;
         }
-
-        let mut index = self.curr_index;
-        while self.data[index] != 0 {
-            index += 1;
-        }
-        let result = unsafe { str::from_utf8_unchecked(&self.data[self.curr_index..index]) };
-        self.curr_index = index + 1;
-        Some(result)
+        self.row += 1;
+        Some(self.packer.get(self.row - 1))
     }
 }
 
-struct DictEncodedStrings {
+struct DictEncodedStrings<T> {
+    /// Sorted, so that codes preserve the order of strings
     mapping: Vec<Option<String>>,
-    encoded_values: Vec<u16>,
+    encoded_values: Vec<T>,
 }
 
-impl DictEncodedStrings {
+impl<T: IntLike> DictEncodedStrings<T> {
     pub fn from_strings(strings: &[Option<Rc<String>>],
                         unique_values: HashSet<Option<Rc<String>>>)
-                        -> DictEncodedStrings {
-        assert!(unique_values.len() <= u16::MAX as usize);
-
+                        -> DictEncodedStrings<T> {
         let mut mapping: Vec<Option<String>> =
             unique_values.into_iter().map(|o| o.map(|s| s.as_str().to_owned())).collect();
         mapping.sort();
-        let encoded_values: Vec<u16> = {
-            let reverse_mapping: HashMap<Option<&String>, u16> =
-                mapping.iter().map(Option::as_ref).zip(0..).collect();
+        let encoded_values: Vec<T> = {
+            let reverse_mapping: HashMap<Option<&String>, T> =
+                mapping.iter().enumerate().map(|(i, s)| (s.as_ref(), T::from(i).unwrap())).collect();
             strings.iter().map(|o| reverse_mapping[&o.as_ref().map(|x| &**x)]).collect()
         };
 
@@ -149,18 +268,22 @@ impl DictEncodedStrings {
             encoded_values,
         }
     }
+
+    fn get(&self, code: T) -> &str {
+        self.mapping[code.to_usize().unwrap()].as_ref().unwrap()
+    }
 }
 
-impl ColumnData for DictEncodedStrings {
+impl<T: IntLike + IntVecType<T>> ColumnData for DictEncodedStrings<T> {
     fn collect_decoded(&self) -> TypedVec {
         self.decode(&self.encoded_values)
     }
 
     fn filter_decode(&self, filter: &BitVec) -> TypedVec {
         let mut result = Vec::<&str>::with_capacity(self.encoded_values.len());
-        for (encoded_value, selected) in self.encoded_values.iter().zip(filter) {
+        for (&encoded_value, selected) in self.encoded_values.iter().zip(filter) {
             if selected {
-                result.push(self.mapping[*encoded_value as usize].as_ref().unwrap());
+                result.push(self.get(encoded_value));
             }
         }
         TypedVec::String(result)
@@ -173,40 +296,47 @@ impl ColumnData for DictEncodedStrings {
     fn basic_type(&self) -> BasicType { BasicType::String }
     fn to_codec(&self) -> Option<&ColumnCodec> { Some(self as &ColumnCodec) }
     fn len(&self) -> usize { self.encoded_values.len() }
+    fn codec_name(&self) -> &'static str { "dictionary" }
+
+    fn string_range(&self) -> Option<(&str, &str)> {
+        // Dictionary is sorted and `None` sorts first
+        let mut strings = self.mapping.iter().filter_map(|s| s.as_ref().map(String::as_str));
+        let min = strings.next()?;
+        Some((min, strings.next_back().unwrap_or(min)))
+    }
 }
 
-impl PointCodec<u16> for DictEncodedStrings {
-    fn decode(&self, data: &[u16]) -> TypedVec {
+impl<T: IntLike + IntVecType<T>> PointCodec<T> for DictEncodedStrings<T> {
+    fn decode(&self, data: &[T]) -> TypedVec {
         let mut result = Vec::<&str>::with_capacity(self.encoded_values.len());
-        for encoded_value in data {
-            result.push(self.mapping[*encoded_value as usize].as_ref().unwrap());
+        for &encoded_value in data {
+            result.push(self.get(encoded_value));
         }
         TypedVec::String(result)
     }
 
-    fn index_decode(&self, data: &[u16], filter: &[usize]) -> TypedVec {
+    fn index_decode(&self, data: &[T], filter: &[usize]) -> TypedVec {
         let mut result = Vec::<&str>::with_capacity(filter.len());
         for &i in filter {
-            let encoded_value = data[i];
-            result.push(self.mapping[encoded_value as usize].as_ref().unwrap());
+            result.push(self.get(data[i]));
         }
         TypedVec::String(result)
     }
 
-    fn to_raw(&self, elem: u16) -> RawVal {
-        RawVal::Str(self.mapping[elem as usize].as_ref().unwrap().to_string())
+    fn to_raw(&self, elem: T) -> RawVal {
+        RawVal::Str(self.get(elem).to_string())
     }
 
     fn max_cardinality(&self) -> usize { self.mapping.len() }
 }
 
-impl ColumnCodec for DictEncodedStrings {
+impl<T: IntLike + IntVecType<T>> ColumnCodec for DictEncodedStrings<T> {
     fn get_encoded(&self) -> TypedVec {
-        TypedVec::BorrowedEncodedU16(&self.encoded_values, self as &PointCodec<u16>)
+        T::borrowed_typed_vec(&self.encoded_values, self as &PointCodec<T>)
     }
 
     fn unwrap_decode<'a>(&'a self, data: &TypedVec<'a>) -> TypedVec<'a> {
-        self.decode(data.cast_ref_u16().0)
+        self.decode(T::unwrap(data))
     }
 
     fn filter_encoded(&self, filter: &BitVec) -> TypedVec {
@@ -216,7 +346,7 @@ impl ColumnCodec for DictEncodedStrings {
                 result.push(*encoded_value);
             }
         }
-        TypedVec::EncodedU16(result, self as &PointCodec<u16>)
+        T::typed_vec(result, self as &PointCodec<T>)
     }
 
     fn index_encoded(&self, filter: &[usize]) -> TypedVec {
@@ -224,18 +354,16 @@ impl ColumnCodec for DictEncodedStrings {
         for &i in filter {
             result.push(self.encoded_values[i]);
         }
-        TypedVec::EncodedU16(result, self as &PointCodec<u16>)
+        T::typed_vec(result, self as &PointCodec<T>)
     }
 
-    fn encoding_type(&self) -> EncodingType { EncodingType::U16 }
+    fn encoding_type(&self) -> EncodingType { T::t() }
 
     fn encode_str(&self, s: &str) -> RawVal {
-        for (i, val) in self.mapping.iter().enumerate() {
-            if val.as_ref().unwrap() == s {
-                return RawVal::Int(i as i64);
-            }
+        match self.mapping.binary_search_by(|val| val.as_ref().map(String::as_str).cmp(&Some(s))) {
+            Ok(i) => RawVal::Int(i as i64),
+            Err(_) => RawVal::Int(-1),
         }
-        RawVal::Int(-1)
     }
 
     fn is_summation_preserving(&self) -> bool { false }
@@ -244,8 +372,84 @@ impl ColumnCodec for DictEncodedStrings {
     fn encoding_range(&self) -> Option<(i64, i64)> { Some((0, self.mapping.len() as i64)) }
 }
 
-impl HeapSizeOf for DictEncodedStrings {
+impl<T: IntLike> HeapSizeOf for DictEncodedStrings<T> {
     fn heap_size_of_children(&self) -> usize {
         self.mapping.heap_size_of_children() + self.encoded_values.heap_size_of_children()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mem_store::column_builder::{ColumnBuilder, StringColBuilder};
+
+    #[test]
+    fn test_string_packer_random_access() {
+        let long = "x".repeat(2000);
+        let strings = (0..200)
+            .map(|i| if i % 3 == 0 { None } else { Some(Rc::new(format!("{}{}", i, if i == 100 { &long } else { "" }))) })
+            .collect::<Vec<_>>();
+        let packer = StringPacker::from_strings(&strings, true);
+        assert_eq!(packer.len(), 200);
+        assert_eq!(packer.iter().count(), 200);
+        match packer.index_decode(&[199, 0, 100, 1]) {
+            TypedVec::String(values) => assert_eq!(values, vec!["199", "", &format!("100{}", long), "1"]),
+            _ => panic!("expected strings"),
+        }
+
+        let long_strings = (0..100).map(|i| Some(Rc::new(format!("{}{}", i, long)))).collect::<Vec<_>>();
+        let packer = StringPacker::from_strings(&long_strings, true);
+        match packer.offsets {
+            BlockOffsets::U32(_) => {}
+            BlockOffsets::U16(_) => panic!("offsets exceed u16"),
+        }
+        assert_eq!(packer.get(99), format!("99{}", long));
+        assert_eq!(packer.lookup_str(&format!("42{}", long)), Some(vec![42]));
+    }
+
+    #[test]
+    fn test_string_packer_index_for_distinct_values() {
+        let build_packed = |uniques: usize| {
+            let mut builder = StringColBuilder::with_codec(Some(CodecHint::Packed));
+            for i in 0..1000 {
+                builder.push(&format!("value{}", i % uniques));
+            }
+            builder.finalize()
+        };
+        let distinct = build_packed(1000);
+        assert_eq!(distinct.lookup_str("value42"), Some(vec![42]));
+        let repeated = build_packed(10);
+        assert_eq!(repeated.codec_name(), "packed");
+        assert_eq!(repeated.lookup_str("value2"), None);
+        let values = (0..1000).map(|i| Some(Rc::new(format!("value{}", i % 10)))).collect::<Vec<_>>();
+        // Allocations may be slightly larger than requested
+        assert!(repeated.heap_size_of_children() < packed_size(&values, false) * 21 / 20);
+    }
+
+    fn build(uniques: usize, rows: usize) -> Box<ColumnData> {
+        let mut builder = StringColBuilder::new();
+        for i in 0..rows {
+            builder.push(&format!("value{}", i % uniques));
+        }
+        builder.finalize()
+    }
+
+    #[test]
+    fn test_dictionary_code_size() {
+        let encoding = |column: Box<ColumnData>| format!("{:?}", column.to_codec().map(|codec| codec.encoding_type()));
+        assert_eq!(encoding(build(10, 1000)), "Some(U8)");
+        assert_eq!(encoding(build(1000, 100_000)), "Some(U16)");
+        assert_eq!(encoding(build(1000, 1000)), "None");
+
+        let column = build(70_000, 300_000);
+        assert_eq!(format!("{:?}", column.to_codec().unwrap().encoding_type()), "U32");
+        let codec = column.to_codec().unwrap();
+        assert_eq!(codec.encode_str("value0"), RawVal::Int(0));
+        assert_eq!(codec.encode_str("value9999"), RawVal::Int(69_999));
+        assert_eq!(codec.encode_str("missing"), RawVal::Int(-1));
+        match column.index_decode(&[0, 69_999, 299_999]) {
+            TypedVec::String(values) => assert_eq!(values, vec!["value0", "value69999", "value19999"]),
+            _ => panic!("expected strings"),
+        }
+    }
+}
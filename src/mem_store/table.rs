@@ -1,34 +1,63 @@
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
 use std::str;
-use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std;
 
+use bit_vec::BitVec;
 use disk_store::db::DB;
+use engine::delete;
+use engine::types::BasicType;
 use heapsize::HeapSizeOf;
 use ingest::buffer::Buffer;
 use ingest::input_column::InputColumn;
 use ingest::raw_val::RawVal;
 use mem_store::batch::Batch;
+use mem_store::column::Column;
+use mem_store::raw_col::RawCol;
+use mem_store::shared_dictionary::{SharedDictionaries, SharedDictionary};
+use syntax::expression::Expr;
+use QueryError;
 
 
+const COMPACTION_THRESHOLD: f64 = 0.5;
+
+/// Source of table versions. Versions are unique across all tables, so that a table that is dropped and recreated
+/// never reuses the version of its predecessor.
+static NEXT_VERSION: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Table {
     name: String,
     batch_size: usize,
-    #[allow(dead_code)]
     metadata: RwLock<Metadata>,
     batches: RwLock<Vec<Batch>>,
     buffer: Mutex<Buffer>,
+    /// Dictionaries of columns declared with `CodecHint::Shared`, by column name.
+    dictionaries: SharedDictionaries,
+    evicted_rows: AtomicUsize,
+    evicted_bytes: AtomicUsize,
+    /// Changes whenever batches are added, modified or removed. Only updated while holding the `batches` lock.
+    version: AtomicUsize,
 }
 
 impl Table {
     pub fn new(batch_size: usize, name: &str, metadata: Metadata) -> Table {
+        let dictionaries = metadata.schema.iter().flat_map(|schema| schema.iter())
+            .filter(|column| column.codec == Some(CodecHint::Shared))
+            .map(|column| (column.name.to_string(), Arc::new(SharedDictionary::new())))
+            .collect();
         Table {
             name: name.to_string(),
-            batch_size: batch_size_override(batch_size, name),
+            batch_size: batch_size_override(metadata.batch_size.unwrap_or(batch_size), name),
             batches: RwLock::new(Vec::new()),
             buffer: Mutex::new(Buffer::default()),
             metadata: RwLock::new(metadata),
+            dictionaries,
+            evicted_rows: AtomicUsize::new(0),
+            evicted_bytes: AtomicUsize::new(0),
+            version: AtomicUsize::new(NEXT_VERSION.fetch_add(1, Ordering::SeqCst)),
         }
     }
 
@@ -36,9 +65,26 @@ impl Table {
         &self.name
     }
 
+    pub fn metadata(&self) -> Metadata {
+        self.metadata.read().unwrap().clone()
+    }
+
+    pub fn shared_dictionaries(&self) -> &SharedDictionaries {
+        &self.dictionaries
+    }
+
     pub fn snapshot(&self) -> Vec<Batch> {
+        self.versioned_snapshot().0
+    }
+
+    /// Returns all batches together with the table version they correspond to.
+    pub fn versioned_snapshot(&self) -> (Vec<Batch>, usize) {
         let batches = self.batches.read().unwrap();
-        batches.clone()
+        (batches.clone(), self.version.load(Ordering::SeqCst))
+    }
+
+    fn bump_version(&self) {
+        self.version.store(NEXT_VERSION.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
     }
 
     pub fn restore_from_db(batch_size: usize, storage: &DB) -> HashMap<String, Table> {
@@ -64,26 +110,170 @@ impl Table {
         }
     }
 
-    pub fn ingest(&self, row: Vec<(String, RawVal)>) {
+    pub fn ingest(&self, row: Vec<(String, RawVal)>) -> Result<(), QueryError> {
+        if let Some(ref schema) = self.metadata.read().unwrap().schema {
+            for &(ref name, ref val) in &row {
+                check_value(schema, name, val)?;
+            }
+            check_complete(schema, row.iter().map(|&(ref name, _)| (name.as_str(), 1)))?;
+        }
         let mut buffer = self.buffer.lock().unwrap();
         buffer.push_row(row);
         self.batch_if_needed(buffer.deref_mut());
+        Ok(())
     }
 
-    pub fn ingest_homogeneous(&self, columns: HashMap<String, InputColumn>) {
+    pub fn ingest_homogeneous(&self, columns: HashMap<String, InputColumn>) -> Result<(), QueryError> {
+        if let Some(ref schema) = self.metadata.read().unwrap().schema {
+            for (name, col) in &columns {
+                let column_type = match *col {
+                    InputColumn::Int(_) => BasicType::Integer,
+                    InputColumn::Str(_) => BasicType::String,
+                    InputColumn::Null(_) => BasicType::Null,
+                };
+                check_type(schema, name, column_type)?;
+            }
+            check_complete(schema, columns.iter().map(|(name, col)| (name.as_str(), col.len())))?;
+        }
         let mut buffer = self.buffer.lock().unwrap();
         buffer.push_typed_cols(columns);
+        Ok(())
     }
 
-    pub fn ingest_heterogeneous(&self, columns: HashMap<String, Vec<RawVal>>) {
+    pub fn ingest_heterogeneous(&self, columns: HashMap<String, Vec<RawVal>>) -> Result<(), QueryError> {
+        if let Some(ref schema) = self.metadata.read().unwrap().schema {
+            for (name, vals) in &columns {
+                for val in vals {
+                    check_value(schema, name, val)?;
+                }
+            }
+            check_complete(schema, columns.iter().map(|(name, vals)| (name.as_str(), vals.len())))?;
+        }
         let mut buffer = self.buffer.lock().unwrap();
         buffer.push_untyped_cols(columns);
         self.batch_if_needed(&mut buffer);
+        Ok(())
     }
 
-    pub fn load_batch(&self, batch: Batch) {
+    /// Checks all `batches` against the schema before loading any of them.
+    pub fn load_batches(&self, new_batches: Vec<Batch>) -> Result<(), QueryError> {
+        if let Some(ref schema) = self.metadata.read().unwrap().schema {
+            for batch in &new_batches {
+                for col in batch.cols() {
+                    check_type(schema, col.name(), col.data().basic_type())?;
+                }
+                check_complete(schema, batch.cols().iter().map(|col| (col.name(), col.len())))?;
+            }
+        }
+        let mut batches = self.batches.write().unwrap();
+        batches.extend(new_batches);
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Removes all data from the table but retains its metadata.
+    pub fn truncate(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
         let mut batches = self.batches.write().unwrap();
-        batches.push(batch);
+        *buffer = Buffer::default();
+        batches.clear();
+        self.reset_dictionaries_if_empty(&batches);
+        self.bump_version();
+        self.metadata.write().unwrap().batch_count = 0;
+    }
+
+    /// Marks all rows matching `predicate` (all rows if there is none) as deleted and returns the number of newly
+    /// deleted rows. Buffered rows are batched first so that they can be deleted as well.
+    pub fn delete(&self, predicate: Option<&Expr>) -> Result<usize, QueryError> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.len() > 0 {
+            self.batch(&mut buffer);
+        }
+        let mut batches = self.batches.write().unwrap();
+        let mut deletions = Vec::with_capacity(batches.len());
+        for batch in batches.iter() {
+            let mut matches = match predicate {
+                Some(predicate) => delete::matching_rows(predicate, batch)?,
+                None => BitVec::from_elem(batch.len(), true),
+            };
+            if let Some(deleted) = batch.deleted() {
+                matches.difference(deleted);
+            }
+            deletions.push(matches);
+        }
+
+        // Apply deletions and compaction to a copy, so that the table is left unchanged if compaction fails
+        let mut deleted_rows = 0;
+        let mut updated = batches.clone();
+        for (batch, matches) in updated.iter_mut().zip(deletions) {
+            let count = matches.iter().filter(|&deleted| deleted).count();
+            if count > 0 {
+                *batch = batch.with_deletions(&matches);
+                deleted_rows += count;
+            }
+        }
+        if deleted_rows > 0 {
+            *batches = self.compact(updated)?;
+            self.reset_dictionaries_if_empty(&batches);
+            self.bump_version();
+        }
+        Ok(deleted_rows)
+    }
+
+    /// Drops batches without any live rows and rewrites batches where more than `COMPACTION_THRESHOLD` of rows have
+    /// been deleted.
+    fn compact(&self, mut batches: Vec<Batch>) -> Result<Vec<Batch>, QueryError> {
+        batches.retain(|batch| batch.deleted_count() < batch.len());
+        let schema = self.metadata.read().unwrap().schema.clone();
+        for batch in batches.iter_mut() {
+            if batch.deleted_count() as f64 > COMPACTION_THRESHOLD * batch.len() as f64 {
+                *batch = compact_batch(batch, schema.as_ref().map(|s| &s[..]), &self.dictionaries)?;
+            }
+        }
+        Ok(batches)
+    }
+
+    /// Starts new generations of all shared dictionaries once the table is empty, so that strings which no longer
+    /// occur in the table can be freed.
+    fn reset_dictionaries_if_empty(&self, batches: &[Batch]) {
+        if batches.is_empty() {
+            for dictionary in self.dictionaries.values() {
+                dictionary.reset();
+            }
+        }
+    }
+
+    /// Evicts batches according to the table's retention policy. `now` is the current unix time in seconds.
+    pub fn enforce_retention(&self, now: i64) {
+        let metadata = self.metadata();
+        if metadata.retention == RetentionPolicy::default() { return; }
+
+        // Determine evictions under the read lock so that queries are only blocked while batches are removed
+        let (planned, version) = {
+            let batches = self.batches.read().unwrap();
+            (evictions(&batches, &metadata, now), self.version.load(Ordering::SeqCst))
+        };
+        if !planned.iter().any(|&evict| evict) { return; }
+
+        let mut batches = self.batches.write().unwrap();
+        let evictions = if self.version.load(Ordering::SeqCst) == version {
+            planned
+        } else {
+            // Batches were changed in the meantime
+            evictions(&batches, &metadata, now)
+        };
+        let mut evict = evictions.into_iter();
+        let (evicted, retained): (Vec<Batch>, Vec<Batch>) = batches.drain(..)
+            .partition(|_| evict.next().unwrap_or(false));
+        *batches = retained;
+        if !evicted.is_empty() {
+            self.reset_dictionaries_if_empty(&batches);
+            self.bump_version();
+        }
+
+        let rows = evicted.iter().map(|b| b.len() - b.deleted_count()).sum();
+        self.evicted_rows.fetch_add(rows, Ordering::SeqCst);
+        self.evicted_bytes.fetch_add(evicted.heap_size_of_children(), Ordering::SeqCst);
     }
 
     fn batch_if_needed(&self, buffer: &mut Buffer) {
@@ -94,13 +284,19 @@ impl Table {
     fn batch(&self, buffer: &mut Buffer) {
         let buffer = std::mem::replace(buffer, Buffer::default());
         self.persist_batch(&buffer);
-        let new_batch = buffer.into();
+        let new_batch = buffer.into_batch(self.metadata.read().unwrap().schema.as_ref().map(|s| &s[..]), &self.dictionaries)
+            .expect("buffered values were checked against the schema");
         let mut batches = self.batches.write().unwrap();
         batches.push(new_batch);
+        self.bump_version();
     }
 
     fn load_buffer(&self, buffer: Buffer) {
-        self.load_batch(buffer.into());
+        let schema = self.metadata.read().unwrap().schema.clone();
+        let mut batches = self.batches.write().unwrap();
+        batches.push(buffer.into_batch(schema.as_ref().map(|s| &s[..]), &self.dictionaries)
+            .expect("persisted values were checked against the schema"));
+        self.bump_version();
     }
 
     fn persist_batch(&self, _batch: &Buffer) {}
@@ -109,27 +305,211 @@ impl Table {
         let batches = self.snapshot();
         let size_per_column = Table::size_per_column(&batches);
         let buffer = self.buffer.lock().unwrap();
+        let deleted_rows = batches.iter().map(|b| b.deleted_count()).sum();
         TableStats {
             name: self.name().to_string(),
-            rows: batches.iter().map(|b| b.cols().get(0).map_or(0, |c| c.len())).sum(),
+            rows: batches.iter().map(|b| b.len()).sum::<usize>() - deleted_rows,
+            deleted_rows,
             batches: batches.len(),
             batches_bytes: batches.heap_size_of_children(),
             buffer_length: buffer.len(),
             buffer_bytes: buffer.heap_size_of_children(),
+            evicted_rows: self.evicted_rows.load(Ordering::SeqCst),
+            evicted_bytes: self.evicted_bytes.load(Ordering::SeqCst),
             size_per_column,
         }
     }
 
-    fn size_per_column(batches: &[Batch]) -> Vec<(String, usize)> {
-        let mut sizes: HashMap<&str, usize> = HashMap::default();
+    /// Describes all columns of the table. Since every batch is encoded separately, a column may have different
+    /// types and codecs in different batches.
+    pub fn describe(&self) -> Vec<ColumnDescription> {
+        let batches = self.snapshot();
+        let mut columns: Vec<ColumnDescription> = Vec::new();
+        for batch in &batches {
+            for col in batch.cols() {
+                let index = match columns.iter().position(|c| c.name == col.name()) {
+                    Some(index) => index,
+                    None => {
+                        columns.push(ColumnDescription {
+                            name: col.name().to_string(),
+                            types: Vec::new(),
+                            codecs: Vec::new(),
+                            nullable: false,
+                            batches: 0,
+                        });
+                        columns.len() - 1
+                    }
+                };
+                let description = &mut columns[index];
+                let basic_type = col.data().basic_type();
+                if !description.types.contains(&basic_type) {
+                    description.types.push(basic_type);
+                }
+                let codec = col.data().codec_name();
+                if !description.codecs.contains(&codec) {
+                    description.codecs.push(codec);
+                }
+                description.nullable |= basic_type == BasicType::Null;
+                description.batches += 1;
+            }
+        }
+        for description in &mut columns {
+            description.nullable |= description.batches < batches.len();
+        }
+        columns
+    }
+
+    /// Computes statistics for all columns in `batches`. Determining distinct counts requires decoding all values.
+    pub fn column_stats(batches: &[Batch]) -> Vec<ColumnStats> {
+        let mut columns: Vec<(ColumnStats, HashSet<RawVal>)> = Vec::new();
+        for batch in batches {
+            for col in batch.cols() {
+                let index = match columns.iter().position(|c| c.0.name == col.name()) {
+                    Some(index) => index,
+                    None => {
+                        columns.push((ColumnStats {
+                            name: col.name().to_string(),
+                            bytes: 0,
+                            rows: 0,
+                            batches: 0,
+                            distinct: 0,
+                        }, HashSet::new()));
+                        columns.len() - 1
+                    }
+                };
+                let (ref mut stats, ref mut uniques) = columns[index];
+                stats.bytes += col.heap_size_of_children();
+                stats.rows += col.len();
+                stats.batches += 1;
+                let decoded = col.data().collect_decoded();
+                for i in 0..decoded.len() {
+                    uniques.insert(decoded.get_raw(i));
+                }
+            }
+        }
+        columns.into_iter()
+            .map(|(mut stats, uniques)| {
+                stats.distinct = uniques.len();
+                stats
+            })
+            .collect()
+    }
+
+    fn size_per_column(batches: &[Batch]) -> Vec<(String, usize, f64)> {
+        let mut sizes: HashMap<&str, (usize, usize)> = HashMap::default();
         for batch in batches {
             for col in batch.cols() {
-                let heapsize = col.heap_size_of_children();
-                *sizes.entry(col.name()).or_insert(0) += heapsize;
+                let size = sizes.entry(col.name()).or_insert((0, 0));
+                size.0 += col.heap_size_of_children();
+                size.1 += col.uncompressed_size();
+            }
+        }
+        sizes.iter()
+            .map(|(name, &(size, uncompressed))| (name.to_string(), size, uncompressed as f64 / cmp::max(size, 1) as f64))
+            .collect()
+    }
+}
+
+/// Whether the retention policy of `metadata` evicts each of `batches`.
+fn evictions(batches: &[Batch], metadata: &Metadata, now: i64) -> Vec<bool> {
+    let policy = &metadata.retention;
+    let mut evictions = batches.iter()
+        .map(|batch| match (policy.max_age, metadata.time_column.as_ref()) {
+            (Some(max_age), Some(time_column)) =>
+                max_time(batch, time_column).map_or(false, |max| max < now - max_age as i64),
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    if let Some(max_rows) = policy.max_rows {
+        let live_rows = |batch: &Batch| batch.len() - batch.deleted_count();
+        let mut rows = batches.iter().zip(&evictions).filter(|e| !*e.1).map(|e| live_rows(e.0)).sum::<usize>();
+        for (batch, evict) in batches.iter().zip(evictions.iter_mut()) {
+            if rows <= max_rows { break; }
+            if !*evict {
+                rows -= live_rows(batch);
+                *evict = true;
+            }
+        }
+    }
+    if let Some(max_bytes) = policy.max_bytes {
+        let mut bytes = batches.iter().zip(&evictions).filter(|e| !*e.1).map(|e| e.0.heap_size_of_children()).sum::<usize>();
+        for (batch, evict) in batches.iter().zip(evictions.iter_mut()) {
+            if bytes <= max_bytes { break; }
+            if !*evict {
+                bytes -= batch.heap_size_of_children();
+                *evict = true;
             }
         }
-        sizes.iter().map(|(name, size)| (name.to_string(), *size)).collect()
     }
+    evictions
+}
+
+/// Largest value of the time column of `batch`, according to its zone map.
+fn max_time(batch: &Batch, time_column: &str) -> Option<i64> {
+    let col = batch.cols().iter().find(|c| c.name() == time_column)?;
+    match col.zone_map()?.range {
+        Some((_, RawVal::Int(max))) => Some(max),
+        _ => None,
+    }
+}
+
+fn compact_batch(batch: &Batch, schema: Option<&[ColumnSchema]>, dictionaries: &SharedDictionaries) -> Result<Batch, QueryError> {
+    let live = match batch.live_rows() {
+        Some(live) => live,
+        None => return Ok(batch.clone()),
+    };
+    let mut cols = Vec::with_capacity(batch.cols().len());
+    for col in batch.cols() {
+        let decoded = col.data().filter_decode(&live);
+        let mut raw_col = RawCol::new();
+        for i in 0..decoded.len() {
+            raw_col.push(decoded.get_raw(i));
+        }
+        let null_count = raw_col.null_count();
+        let data = match schema.and_then(|s| s.iter().find(|c| c.name == col.name())) {
+            Some(declared) => raw_col.finalize_as(declared, dictionaries)?,
+            None => raw_col.finalize(),
+        };
+        cols.push(Column::with_zone_map(col.name().to_string(), data, null_count));
+    }
+    Ok(Batch::from(cols))
+}
+
+fn check_value(schema: &[ColumnSchema], column: &str, val: &RawVal) -> Result<(), QueryError> {
+    let basic_type = match *val {
+        RawVal::Int(_) => BasicType::Integer,
+        RawVal::Str(_) => BasicType::String,
+        RawVal::Null => BasicType::Null,
+    };
+    check_type(schema, column, basic_type)
+}
+
+/// Missing values are filled with nulls, so ingested data must contain a value for each row of all `NOT NULL` columns.
+/// `columns` are the names of ingested columns and their number of values.
+fn check_complete<'a, I: Iterator<Item=(&'a str, usize)>>(schema: &[ColumnSchema], columns: I) -> Result<(), QueryError> {
+    let columns = columns.collect::<Vec<_>>();
+    let rows = columns.iter().map(|&(_, len)| len).max().unwrap_or(0);
+    for declared in schema.iter().filter(|declared| !declared.nullable) {
+        if !columns.iter().any(|&(name, len)| name == declared.name && len == rows) {
+            bail!(QueryError::SchemaViolation, "Missing value for column {}, which is declared as NOT NULL", declared.name)
+        }
+    }
+    Ok(())
+}
+
+fn check_type(schema: &[ColumnSchema], column: &str, basic_type: BasicType) -> Result<(), QueryError> {
+    match schema.iter().find(|c| c.name == column) {
+        Some(declared) => if basic_type == BasicType::Null {
+            if !declared.nullable {
+                bail!(QueryError::SchemaViolation, "Column {} is declared as NOT NULL but got null", column)
+            }
+        } else if basic_type != declared.column_type.basic_type() {
+            bail!(QueryError::SchemaViolation, "Column {} is declared as {:?} but got {:?}",
+                  column, declared.column_type, basic_type)
+        },
+        None => bail!(QueryError::SchemaViolation, "Column {} is not declared", column),
+    }
+    Ok(())
 }
 
 fn batch_size_override(batch_size: usize, tablename: &str) -> usize {
@@ -146,7 +526,10 @@ impl HeapSizeOf for Table {
             let buffer = self.buffer.lock().unwrap();
             buffer.heap_size_of_children()
         };
-        batches_size + buffer_size
+        let dictionaries_size = self.dictionaries.values()
+            .map(|dictionary| dictionary.heap_size_of_children())
+            .sum::<usize>();
+        batches_size + buffer_size + dictionaries_size
     }
 }
 
@@ -154,17 +537,124 @@ impl HeapSizeOf for Table {
 pub struct Metadata {
     pub name: String,
     pub batch_count: u64,
+    /// Declared columns. Tables created implicitly on first ingest have no schema and accept any column.
+    #[serde(default)]
+    pub schema: Option<Vec<ColumnSchema>>,
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+    #[serde(default)]
+    pub time_column: Option<String>,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+}
+
+impl Metadata {
+    pub fn new(name: &str) -> Metadata {
+        Metadata {
+            name: name.to_string(),
+            batch_count: 0,
+            schema: None,
+            batch_size: None,
+            time_column: None,
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
+
+/// Limits on the data kept by a table. Eviction always removes whole batches, oldest first.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Maximum age in seconds of the newest value of the time column in a batch
+    pub max_age: Option<u64>,
+    pub max_rows: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub codec: Option<CodecHint>,
+    /// Columns are nullable unless declared as `NOT NULL`.
+    #[serde(default = "nullable_by_default")]
+    pub nullable: bool,
+}
+
+fn nullable_by_default() -> bool { true }
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ColumnType {
+    Integer,
+    String,
+}
+
+impl ColumnType {
+    pub fn basic_type(&self) -> BasicType {
+        match *self {
+            ColumnType::Integer => BasicType::Integer,
+            ColumnType::String => BasicType::String,
+        }
+    }
+}
+
+/// Overrides the codec that would otherwise be chosen automatically for each batch.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum CodecHint {
+    /// Dictionary encoding for strings (falls back to `Packed` if there are too many unique values)
+    Dictionary,
+    /// Packed strings, or integers that are bit-packed even if that saves little memory
+    Packed,
+    /// Dictionary shared by all batches of the table, which allows grouping on codes across batches
+    Shared,
+    /// LZ4-compressed blocks, for large strings that are rarely queried
+    Compressed,
+    /// Plain 64bit integers, no offset encoding
+    Plain,
+}
+
+impl CodecHint {
+    pub fn supports(&self, column_type: ColumnType) -> bool {
+        match *self {
+            CodecHint::Dictionary | CodecHint::Shared | CodecHint::Compressed => column_type == ColumnType::String,
+            CodecHint::Packed => true,
+            CodecHint::Plain => column_type == ColumnType::Integer,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct TableStats {
     pub name: String,
     pub rows: usize,
+    /// Rows that have been deleted but not yet removed by compaction
+    pub deleted_rows: usize,
     pub batches: usize,
     pub batches_bytes: usize,
     pub buffer_length: usize,
     pub buffer_bytes: usize,
-    pub size_per_column: Vec<(String, usize)>,
+    /// Rows and bytes removed by the retention policy
+    pub evicted_rows: usize,
+    pub evicted_bytes: usize,
+    /// Bytes and compression ratio (uncompressed / actual bytes) of each column
+    pub size_per_column: Vec<(String, usize, f64)>,
 }
 
 
+
+#[derive(Debug)]
+pub struct ColumnDescription {
+    pub name: String,
+    pub types: Vec<BasicType>,
+    pub codecs: Vec<&'static str>,
+    pub nullable: bool,
+    pub batches: usize,
+}
+
+#[derive(Debug)]
+pub struct ColumnStats {
+    pub name: String,
+    pub bytes: usize,
+    pub rows: usize,
+    pub batches: usize,
+    pub distinct: usize,
+}
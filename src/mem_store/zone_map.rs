@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use engine::typed_vec::TypedVec;
+use engine::types::BasicType;
+use ingest::raw_val::RawVal;
+use mem_store::column::ColumnData;
+use syntax::expression::*;
+
+
+/// Bounds of the values in a single column of a batch, used to skip batches that cannot match a filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneMap {
+    /// Smallest and largest value, `None` if the column is empty, contains only nulls or values of mixed types.
+    pub range: Option<(RawVal, RawVal)>,
+    pub null_count: usize,
+}
+
+impl ZoneMap {
+    /// Nulls are stored as default values in integer and string columns, so `null_count` has to be counted
+    /// on the raw values before they are encoded.
+    pub fn compute(data: &ColumnData, null_count: usize) -> ZoneMap {
+        let range = match data.basic_type() {
+            BasicType::Integer => data.range().map(|(min, max)| (RawVal::Int(min), RawVal::Int(max))),
+            BasicType::String => match data.string_range() {
+                Some((min, max)) => Some((RawVal::Str(min.to_string()), RawVal::Str(max.to_string()))),
+                None => match data.collect_decoded() {
+                    TypedVec::String(ref values) => match (values.iter().min(), values.iter().max()) {
+                        (Some(min), Some(max)) => Some((RawVal::Str(min.to_string()), RawVal::Str(max.to_string()))),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+            },
+            _ => None,
+        };
+        let null_count = match data.basic_type() {
+            BasicType::Null => data.len(),
+            _ => null_count,
+        };
+        ZoneMap { range, null_count }
+    }
+}
+
+/// Returns false if the zone maps prove that no row of the batch satisfies `filter`.
+/// Columns without zone map and unsupported expressions are assumed to match.
+pub fn may_match(filter: &Expr, zone_maps: &HashMap<&str, &ZoneMap>) -> bool {
+    match *filter {
+        Expr::Func(FuncType::And, ref lhs, ref rhs) => may_match(lhs, zone_maps) && may_match(rhs, zone_maps),
+        Expr::Func(FuncType::Or, ref lhs, ref rhs) => may_match(lhs, zone_maps) || may_match(rhs, zone_maps),
+        Expr::Func(FuncType::LT, ref lhs, ref rhs) => match (bounds(lhs, zone_maps), bounds(rhs, zone_maps)) {
+            (Some((RawVal::Int(lmin), _)), Some((_, RawVal::Int(rmax)))) => lmin < rmax,
+            _ => true,
+        },
+        Expr::Func(FuncType::GT, ref lhs, ref rhs) => match (bounds(lhs, zone_maps), bounds(rhs, zone_maps)) {
+            (Some((_, RawVal::Int(lmax))), Some((RawVal::Int(rmin), _))) => lmax > rmin,
+            _ => true,
+        },
+        Expr::Func(FuncType::Equals, ref lhs, ref rhs) => match (bounds(lhs, zone_maps), bounds(rhs, zone_maps)) {
+            (Some((lmin, lmax)), Some((rmin, rmax))) => !same_type(&lmin, &rmin) || (lmin <= rmax && rmin <= lmax),
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+fn bounds(expr: &Expr, zone_maps: &HashMap<&str, &ZoneMap>) -> Option<(RawVal, RawVal)> {
+    match *expr {
+        Expr::ColName(ref name) => zone_maps.get(name.as_str()).and_then(|zone_map| zone_map.range.clone()),
+        Expr::Const(RawVal::Null) => None,
+        Expr::Const(ref value) => Some((value.clone(), value.clone())),
+        _ => None,
+    }
+}
+
+fn same_type(left: &RawVal, right: &RawVal) -> bool {
+    match (left, right) {
+        (&RawVal::Int(_), &RawVal::Int(_)) | (&RawVal::Str(_), &RawVal::Str(_)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mem_store::column::Column;
+    use mem_store::raw_col::RawCol;
+
+    fn int_zone_map(min: i64, max: i64) -> ZoneMap {
+        ZoneMap { range: Some((RawVal::Int(min), RawVal::Int(max))), null_count: 0 }
+    }
+
+    fn compare(ftype: FuncType, col: &str, value: i64) -> Expr {
+        Expr::func(ftype, Expr::ColName(col.to_string()), Expr::Const(RawVal::Int(value)))
+    }
+
+    #[test]
+    fn test_may_match() {
+        let timestamp = int_zone_map(100, 200);
+        let mut zone_maps = HashMap::new();
+        zone_maps.insert("timestamp", &timestamp);
+
+        assert!(may_match(&compare(FuncType::GT, "timestamp", 150), &zone_maps));
+        assert!(!may_match(&compare(FuncType::GT, "timestamp", 200), &zone_maps));
+        assert!(!may_match(&compare(FuncType::LT, "timestamp", 100), &zone_maps));
+        assert!(may_match(&compare(FuncType::Equals, "timestamp", 100), &zone_maps));
+        assert!(!may_match(&compare(FuncType::Equals, "timestamp", 201), &zone_maps));
+        assert!(may_match(&compare(FuncType::Equals, "unknown", 201), &zone_maps));
+        assert!(!may_match(&Expr::func(FuncType::And,
+                                       compare(FuncType::GT, "timestamp", 150),
+                                       compare(FuncType::LT, "timestamp", 50)), &zone_maps));
+        assert!(may_match(&Expr::func(FuncType::Or,
+                                      compare(FuncType::GT, "timestamp", 150),
+                                      compare(FuncType::LT, "timestamp", 50)), &zone_maps));
+    }
+
+    #[test]
+    fn test_may_match_strings() {
+        let mut raw_col = RawCol::new();
+        for tld in &["de", "com", "net"] {
+            raw_col.push(RawVal::Str(tld.to_string()));
+        }
+        let col = Column::with_zone_map("tld".to_string(), raw_col.finalize(), 0);
+        let mut zone_maps = HashMap::new();
+        zone_maps.insert("tld", col.zone_map().unwrap());
+
+        let equals = |value: &str| Expr::func(FuncType::Equals,
+                                               Expr::ColName("tld".to_string()),
+                                               Expr::Const(RawVal::Str(value.to_string())));
+        assert!(may_match(&equals("de"), &zone_maps));
+        assert!(may_match(&equals("io"), &zone_maps));
+        assert!(!may_match(&equals("ai"), &zone_maps));
+        assert!(!may_match(&equals("org"), &zone_maps));
+    }
+
+    #[test]
+    fn test_null_count() {
+        let mut raw_col = RawCol::new();
+        raw_col.push(RawVal::Int(3));
+        raw_col.push(RawVal::Null);
+        raw_col.push(RawVal::Int(7));
+        raw_col.push(RawVal::Null);
+        let null_count = raw_col.null_count();
+        let col = Column::with_zone_map("a".to_string(), raw_col.finalize(), null_count);
+        assert_eq!(col.zone_map().map(|z| z.null_count), Some(2));
+
+        let nulls = RawCol::with_nulls(5);
+        let null_count = nulls.null_count();
+        let col = Column::with_zone_map("b".to_string(), nulls.finalize(), null_count);
+        assert_eq!(col.zone_map().map(|z| (z.null_count, z.range.clone())), Some((5, None)));
+
+        assert!(Column::new("c".to_string(), RawCol::with_nulls(5).finalize()).zone_map().is_none());
+    }
+}
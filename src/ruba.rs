@@ -1,5 +1,8 @@
-use std::str;
+use std::cmp;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::usize;
+use std::time::Duration;
 
 // use rocksdb::{DB, Options, WriteBatch, IteratorMode, Direction};
 // use tempdir::TempDir;
@@ -7,19 +10,29 @@ use QueryResult;
 use QueryError;
 use disk_store::db::*;
 use disk_store::noop_storage::NoopStorage;
-use engine::query_task::QueryTask;
+use engine::introspection;
+use engine::query::Query;
+use engine::query_cache::{CacheKey, CacheStats, QueryKey};
+use engine::query_task::{QueryTask, QueryOutput, ResultSink};
 use futures::*;
-use futures_channel::oneshot;
+use futures_channel::{mpsc, oneshot};
 use ingest::csv_loader::CSVIngestionTask;
 use ingest::extractor::Extractor;
+use mem_store::batch::Batch;
 use mem_store::table::TableStats;
-use nom;
 use scheduler::*;
 use syntax::parser;
+use syntax::statement::Statement;
+use time;
 use trace::{Trace, TraceBuilder};
 
+/// Number of chunks of a streamed query that may wait for the client before workers stop producing more.
+const STREAM_BUFFER_CHUNKS: usize = 4;
+
 pub struct Ruba {
-    inner_ruba: Arc<InnerRuba>
+    inner_ruba: Arc<InnerRuba>,
+    /// `usize::MAX` if queries are not limited
+    query_memory_limit: AtomicUsize,
 }
 
 impl Ruba {
@@ -30,46 +43,201 @@ impl Ruba {
     pub fn new(storage: Box<DB>, load_tabledata: bool) -> Ruba {
         let ruba = Arc::new(InnerRuba::new(storage, load_tabledata));
         InnerRuba::start_worker_threads(&ruba);
-        Ruba { inner_ruba: ruba }
+        InnerRuba::start_retention_thread(&ruba);
+        Ruba { inner_ruba: ruba, query_memory_limit: AtomicUsize::new(usize::MAX) }
+    }
+
+    /// Limits the memory used for intermediate results of each query to `bytes`.
+    /// Queries that need more memory fail with `QueryError::MemoryLimitExceeded`.
+    /// Partial results, blocks decompressed by query readers and chunks waiting for streaming clients are counted.
+    /// The limit is approximate: memory is accounted after it has been allocated, and the decoded rows of the final
+    /// output as well as small allocations of individual operators are not counted.
+    /// Applies to queries started after the call.
+    pub fn set_query_memory_limit(&self, bytes: Option<usize>) {
+        self.query_memory_limit.store(bytes.unwrap_or(usize::MAX), Ordering::SeqCst);
+    }
+
+    fn query_memory_limit(&self) -> Option<usize> {
+        match self.query_memory_limit.load(Ordering::SeqCst) {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
     }
 
     // TODO(clemens): proper error handling throughout query stack. panics! panics everywhere!
-    pub fn run_query(&self, query: &str) -> Box<Future<Item=(QueryResult, Trace), Error=oneshot::Canceled>> {
-        let (sender, receiver) = oneshot::channel();
+    pub fn run_query(&self, query: &str) -> QueryFuture {
+        self.start_query(query, CancellationHandle::new(), None)
+    }
+
+    /// Like `run_query`, but aborts the query with `QueryError::Timeout` once it has run for longer than `timeout`.
+    pub fn run_query_with_timeout(&self, query: &str, timeout: Duration) -> QueryFuture {
+        self.start_query(query, CancellationHandle::new(), Some(timeout))
+    }
+
+    /// Like `run_query`, but uses `cancellation` to cancel the query, which may be cancelled before it is scheduled.
+    pub fn run_query_with_cancellation(&self, query: &str, cancellation: CancellationHandle) -> QueryFuture {
+        self.start_query(query, cancellation, None)
+    }
 
+    fn start_query(&self, query: &str, cancellation: CancellationHandle, timeout: Option<Duration>) -> QueryFuture {
         // TODO(clemens): perform compilation and table snapshot in asynchronous task?
-        let query = match parser::parse_query(query.as_bytes()) {
-            nom::IResult::Done(remaining, query) => {
-                if !remaining.is_empty() {
-                    let error = match str::from_utf8(remaining) {
-                        Ok(chars) => QueryError::SytaxErrorCharsRemaining(chars.to_owned()),
-                        Err(_) => QueryError::SyntaxErrorBytesRemaining(remaining.to_vec()),
-                    };
-                    return Box::new(future::ok((Err(error), TraceBuilder::new("empty".to_owned()).finalize())));
-                }
-                query
+        let result = match parser::parse(query) {
+            Ok(statement) => self.execute(statement, &cancellation, timeout),
+            Err(err) => Box::new(future::ok((Err(err), TraceBuilder::new("empty".to_owned()).finalize()))),
+        };
+        QueryFuture { result, cancellation }
+    }
+
+    /// Like `run_query`, but yields result rows in chunks as soon as they are available.
+    /// Selects without aggregation or ordering are streamed batch by batch, other statements yield a single chunk.
+    pub fn run_query_stream(&self, query: &str) -> QueryStream {
+        let cancellation = CancellationHandle::new();
+        let chunks: Box<Stream<Item=QueryResult, Error=oneshot::Canceled>> = match parser::parse(query) {
+            Ok(Statement::Select(queries)) => {
+                let streams = queries.into_iter()
+                    .map(|query| self.stream_select(query, cancellation.clone()))
+                    .collect::<Vec<_>>();
+                Box::new(stream::iter_ok(streams).flatten())
             }
-            nom::IResult::Error(err) => return Box::new(future::ok((
-                Err(QueryError::ParseError(format!("{:?}", err))),
-                TraceBuilder::new("empty".to_owned()).finalize()))),
-            nom::IResult::Incomplete(needed)=> return Box::new(future::ok((
-                Err(QueryError::ParseError(format!("Incomplete. Needed: {:?}", needed))),
-                TraceBuilder::new("empty".to_owned()).finalize()))),
+            Ok(statement) => Box::new(self.execute(statement, &cancellation, None)
+                .map(|(result, _)| result)
+                .into_stream()),
+            Err(err) => Box::new(stream::once(Ok(Err(err)))),
         };
+        QueryStream { chunks, cancellation }
+    }
 
-        // TODO(clemens): A table may not exist on all nodes, so querying empty table is valid and should return empty result.
-        let data = self.inner_ruba.snapshot(&query.table)
-            .expect(&format!("Table {} does not exist!", &query.table));
-        let task = QueryTask::new(query, data, SharedSender::new(sender));
+    fn execute(&self,
+               statement: Statement,
+               cancellation: &CancellationHandle,
+               timeout: Option<Duration>) -> Box<Future<Item=(QueryResult, Trace), Error=oneshot::Canceled>> {
+        match statement {
+            Statement::Select(queries) => {
+                let mut results = queries.into_iter()
+                    .map(|query| self.run_select(query, cancellation.clone(), timeout))
+                    .collect::<Vec<_>>();
+                if results.len() == 1 {
+                    results.pop().unwrap()
+                } else {
+                    Box::new(future::join_all(results).map(union_all))
+                }
+            }
+            Statement::ShowTables => {
+                let inner = self.inner_ruba.clone();
+                self.run_statement(move || Ok(introspection::show_tables(inner.stats())))
+            }
+            Statement::Describe(table) => {
+                let inner = self.inner_ruba.clone();
+                self.run_statement(move || inner.describe(&table)
+                    .map(introspection::describe)
+                    .ok_or_else(|| QueryError::TableNotFound(table.clone())))
+            }
+            Statement::ShowStats(table) => {
+                let inner = self.inner_ruba.clone();
+                self.run_statement(move || inner.column_stats(&table)
+                    .map(introspection::show_stats)
+                    .ok_or_else(|| QueryError::TableNotFound(table.clone())))
+            }
+            Statement::CreateTable(metadata) => {
+                let inner = self.inner_ruba.clone();
+                self.run_statement(move || inner.create_table(metadata.clone()).map(|_| QueryOutput::empty()))
+            }
+            Statement::DropTable(table) => {
+                let inner = self.inner_ruba.clone();
+                self.run_statement(move || inner.drop_table(&table).map(|_| QueryOutput::empty()))
+            }
+            Statement::Truncate(table) => {
+                let inner = self.inner_ruba.clone();
+                self.run_statement(move || inner.truncate(&table).map(|_| QueryOutput::empty()))
+            }
+            Statement::Delete { table, filter } => {
+                let inner = self.inner_ruba.clone();
+                let filter = filter.map(|filter| filter.resolve_time_macros(time::now().to_timespec().sec));
+                self.run_statement(move || inner.delete(&table, filter.as_ref()).map(introspection::deleted_rows))
+            }
+        }
+    }
+
+    fn run_statement<F>(&self, f: F) -> Box<Future<Item=(QueryResult, Trace), Error=oneshot::Canceled>>
+        where F: Fn() -> QueryResult + Sync + Send + 'static {
+        let (task, receiver) = Task::from_fn(f);
         let trace_receiver = self.schedule(task);
         Box::new(receiver.join(trace_receiver))
     }
 
+    fn run_select(&self,
+                  query: Query,
+                  cancellation: CancellationHandle,
+                  timeout: Option<Duration>) -> Box<Future<Item=(QueryResult, Trace), Error=oneshot::Canceled>> {
+        let now = time::now().to_timespec().sec;
+        let (data, dimension, cache_key) = match self.snapshot(&query, now) {
+            Ok(snapshot) => snapshot,
+            Err(err) => return Box::new(future::ok((Err(err), TraceBuilder::new("empty".to_owned()).finalize()))),
+        };
+        if let Some(output) = self.inner_ruba.query_cache().get(&cache_key) {
+            return Box::new(future::ok((Ok(output), TraceBuilder::new("cached".to_owned()).finalize())));
+        }
+        let (sender, receiver) = oneshot::channel();
+        let sink = ResultSink::Complete(SharedSender::new(sender));
+        let query = query.resolve_time_macros(now);
+        let task = match QueryTask::new(query, data, dimension, sink, cancellation, timeout, self.query_memory_limit()) {
+            Ok(task) => task.with_partial_cache(self.inner_ruba.query_cache(), cache_key.query()),
+            Err(err) => return Box::new(future::ok((Err(err), TraceBuilder::new("empty".to_owned()).finalize()))),
+        };
+        let trace_receiver = self.schedule(task);
+        let inner = self.inner_ruba.clone();
+        let result = receiver.map(move |result| {
+            if let Ok(ref output) = result {
+                inner.query_cache().insert(cache_key, output.clone());
+            }
+            result
+        });
+        Box::new(result.join(trace_receiver))
+    }
+
+    fn stream_select(&self,
+                     query: Query,
+                     cancellation: CancellationHandle) -> Box<Stream<Item=QueryResult, Error=oneshot::Canceled>> {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER_CHUNKS);
+        let now = time::now().to_timespec().sec;
+        let (data, dimension, _) = match self.snapshot(&query, now) {
+            Ok(snapshot) => snapshot,
+            Err(err) => return Box::new(stream::once(Ok(Err(err)))),
+        };
+        let sink = ResultSink::stream(sender);
+        let query = query.resolve_time_macros(now);
+        match QueryTask::new(query, data, dimension, sink, cancellation, None, self.query_memory_limit()) {
+            Ok(task) => {
+                self.schedule(task.into_stream_task(&self.inner_ruba));
+                Box::new(receiver.map_err(|never| never.never_into()))
+            }
+            Err(err) => Box::new(stream::once(Ok(Err(err)))),
+        }
+    }
+
+    /// Returns the batches of the queried table and the joined table, and the key for caching the query result.
+    /// `now` is the time that the time macros of the query are resolved with.
+    /// Fails if a table does not exist, or if a wildcard does not match any table.
+    fn snapshot(&self, query: &Query, now: i64) -> Result<(Vec<Batch>, Option<Vec<Batch>>, CacheKey), QueryError> {
+        // TODO(clemens): A table may not exist on all nodes, so querying empty table is valid and should return empty result.
+        let (data, versions) = self.inner_ruba.versioned_snapshot(&query.table)
+            .ok_or_else(|| QueryError::TableNotFound(query.table.clone()))?;
+        let (dimension, dimension_versions) = match query.join {
+            Some(ref join) => {
+                let (batches, versions) = self.inner_ruba.versioned_snapshot(&join.table)
+                    .ok_or_else(|| QueryError::TableNotFound(join.table.clone()))?;
+                (Some(batches), versions)
+            }
+            None => (None, Vec::new()),
+        };
+        Ok((data, dimension, CacheKey::new(QueryKey::new(query, now, dimension_versions), versions)))
+    }
+
     pub fn load_csv(&self,
                     path: &str,
                     table_name: &str,
                     chunk_size: usize,
-                    extractors: Vec<(String, Extractor)>) -> impl Future<Item=(), Error=oneshot::Canceled> {
+                    extractors: Vec<(String, Extractor)>) -> impl Future<Item=Result<(), QueryError>, Error=oneshot::Canceled> {
         let (sender, receiver) = oneshot::channel();
         let task = CSVIngestionTask::new(
             path.to_string(),
@@ -82,6 +250,15 @@ impl Ruba {
         receiver
     }
 
+    pub fn cache_stats(&self) -> CacheStats {
+        self.inner_ruba.query_cache().stats()
+    }
+
+    /// Bounds the memory used for caching query results. A size of 0 disables the cache.
+    pub fn set_query_cache_size(&self, bytes: usize) {
+        self.inner_ruba.query_cache().set_max_bytes(bytes);
+    }
+
     pub fn table_stats(&self) -> impl Future<Item=Vec<TableStats>, Error=oneshot::Canceled> {
         let inner = self.inner_ruba.clone();
         let (task, receiver) = Task::from_fn(move || inner.stats());
@@ -89,11 +266,92 @@ impl Ruba {
         receiver
     }
 
+    /// Evicts data according to the retention policy of each table. Also runs periodically in the background.
+    pub fn enforce_retention(&self) -> impl Future<Item=(), Error=oneshot::Canceled> {
+        let inner = self.inner_ruba.clone();
+        let (task, receiver) = Task::from_fn(move || inner.enforce_retention());
+        self.schedule(task);
+        receiver
+    }
+
     fn schedule<T: Task + 'static>(&self, task: T) -> impl Future<Item=Trace, Error=oneshot::Canceled> {
         self.inner_ruba.schedule(task)
     }
 }
 
+/// Result of a statement that is still running. Resolves once the statement has completed.
+pub struct QueryFuture {
+    result: Box<Future<Item=(QueryResult, Trace), Error=oneshot::Canceled>>,
+    cancellation: CancellationHandle,
+}
+
+impl QueryFuture {
+    /// Handle that can be used to cancel the query while it is running. Has no effect on statements other than SELECT.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.cancellation.clone()
+    }
+}
+
+impl Future for QueryFuture {
+    type Item = (QueryResult, Trace);
+    type Error = oneshot::Canceled;
+
+    fn poll(&mut self, cx: &mut task::Context) -> Poll<Self::Item, Self::Error> {
+        self.result.poll(cx)
+    }
+}
+
+/// Result chunks of a statement that is still running. Ends once the statement has completed.
+pub struct QueryStream {
+    chunks: Box<Stream<Item=QueryResult, Error=oneshot::Canceled>>,
+    cancellation: CancellationHandle,
+}
+
+impl QueryStream {
+    /// Handle that can be used to cancel the query while it is running. Has no effect on statements other than SELECT.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.cancellation.clone()
+    }
+}
+
+impl Stream for QueryStream {
+    type Item = QueryResult;
+    type Error = oneshot::Canceled;
+
+    fn poll_next(&mut self, cx: &mut task::Context) -> Poll<Option<Self::Item>, Self::Error> {
+        self.chunks.poll_next(cx)
+    }
+}
+
+fn union_all(results: Vec<(QueryResult, Trace)>) -> (QueryResult, Trace) {
+    let mut traces = Vec::with_capacity(results.len());
+    let mut union: Option<QueryOutput> = None;
+    let mut error = None;
+    for (result, trace) in results {
+        traces.push(trace);
+        match (result, union.as_mut()) {
+            (Err(err), _) => if error.is_none() { error = Some(err) },
+            (Ok(output), None) => union = Some(output),
+            (Ok(output), Some(union)) => {
+                if output.colnames.len() != union.colnames.len() {
+                    error = Some(QueryError::TypeError(format!(
+                        "Each part of UNION ALL must select the same number of columns. Found {} and {}",
+                        union.colnames.len(), output.colnames.len())));
+                }
+                union.rows.extend(output.rows);
+                union.stats.runtime_ns = cmp::max(union.stats.runtime_ns, output.stats.runtime_ns);
+                union.stats.rows_scanned += output.stats.rows_scanned;
+                union.stats.batches_skipped += output.stats.batches_skipped;
+            }
+        }
+    }
+    let trace = Trace::combine("union all", traces);
+    match error {
+        Some(err) => (Err(err), trace),
+        None => (Ok(union.unwrap()), trace),
+    }
+}
+
 impl Drop for Ruba {
     fn drop(&mut self) {
         self.inner_ruba.stop();
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Allows cancelling a running query from another thread, e.g. in response to Ctrl-C.
+#[derive(Clone, Default)]
+pub struct CancellationHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationHandle {
+    pub fn new() -> CancellationHandle {
+        CancellationHandle::default()
+    }
+
+    /// Skips all batches that have not been processed yet and resolves the query with `QueryError::Cancelled`.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
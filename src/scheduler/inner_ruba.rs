@@ -2,20 +2,25 @@ use std::collections::{HashMap, VecDeque};
 use std::mem;
 use std::str;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, RwLock, Condvar};
+use std::sync::{Arc, Mutex, RwLock, Condvar, Weak};
 use std::thread;
+use std::time::Duration;
 
 use disk_store::db::*;
+use engine::query_cache::QueryCache;
 use futures::*;
 use futures_channel::oneshot;
 use ingest::input_column::InputColumn;
 use ingest::raw_val::RawVal;
 use mem_store::batch::Batch;
+use mem_store::shared_dictionary::SharedDictionaries;
 use mem_store::table::*;
 use num_cpus;
 use scheduler::*;
+use syntax::expression::Expr;
 use time;
 use trace::*;
+use QueryError;
 
 
 pub struct InnerRuba {
@@ -25,8 +30,14 @@ pub struct InnerRuba {
     running: AtomicBool,
     idle_queue: Condvar,
     task_queue: Mutex<VecDeque<Arc<TaskState>>>,
+    retention_lock: Mutex<()>,
+    retention_wakeup: Condvar,
+    query_cache: Arc<QueryCache>,
 }
 
+const RETENTION_INTERVAL: u64 = 60;
+const QUERY_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
 struct TaskState {
     trace_builder: RwLock<Option<TraceBuilder>>,
     trace_sender: SharedSender<Trace>,
@@ -54,6 +65,9 @@ impl InnerRuba {
             running: AtomicBool::new(true),
             idle_queue: Condvar::new(),
             task_queue: Mutex::new(VecDeque::new()),
+            retention_lock: Mutex::new(()),
+            retention_wakeup: Condvar::new(),
+            query_cache: Arc::new(QueryCache::new(QUERY_CACHE_BYTES)),
         }
     }
 
@@ -64,9 +78,43 @@ impl InnerRuba {
         }
     }
 
+    /// Periodically schedules a task that evicts data according to the retention policy of each table.
+    pub fn start_retention_thread(ruba: &Arc<InnerRuba>) {
+        let cloned = ruba.clone();
+        thread::spawn(move || InnerRuba::retention_loop(cloned));
+    }
+
+    /// Returns the batches of `table`. If `table` contains `*` wildcards, returns the union of the batches of all
+    /// matching tables, or `None` if no table matches.
     pub fn snapshot(&self, table: &str) -> Option<Vec<Batch>> {
+        self.versioned_snapshot(table).map(|(batches, _)| batches)
+    }
+
+    /// Returns the batches of all tables matching `table`, together with the sorted versions of those tables.
+    pub fn versioned_snapshot(&self, table: &str) -> Option<(Vec<Batch>, Vec<usize>)> {
         let tables = self.tables.read().unwrap();
-        tables.get(table).map(|t| t.snapshot())
+        if table.contains('*') {
+            let mut batches = Vec::new();
+            let mut versions = Vec::new();
+            for (name, t) in tables.iter() {
+                if matches_wildcard(table, name) {
+                    let (table_batches, version) = t.versioned_snapshot();
+                    batches.extend(table_batches);
+                    versions.push(version);
+                }
+            }
+            versions.sort();
+            if versions.is_empty() { None } else { Some((batches, versions)) }
+        } else {
+            tables.get(table).map(|t| {
+                let (batches, version) = t.versioned_snapshot();
+                (batches, vec![version])
+            })
+        }
+    }
+
+    pub fn query_cache(&self) -> &Arc<QueryCache> {
+        &self.query_cache
     }
 
     pub fn stop(&self) {
@@ -74,6 +122,22 @@ impl InnerRuba {
         let _ = self.task_queue.lock();
         self.running.store(false, Ordering::SeqCst);
         self.idle_queue.notify_all();
+        let _guard = self.retention_lock.lock().unwrap();
+        self.retention_wakeup.notify_all();
+    }
+
+    fn retention_loop(ruba: Arc<InnerRuba>) {
+        let mut guard = ruba.retention_lock.lock().unwrap();
+        while ruba.running.load(Ordering::SeqCst) {
+            guard = ruba.retention_wakeup.wait_timeout(guard, Duration::from_secs(RETENTION_INTERVAL)).unwrap().0;
+            if !ruba.running.load(Ordering::SeqCst) { break; }
+            // Task must not keep `ruba` alive since it is stored in the task queue
+            let weak: Weak<InnerRuba> = Arc::downgrade(&ruba);
+            let (task, _) = Task::from_fn(move || if let Some(ruba) = weak.upgrade() {
+                ruba.enforce_retention();
+            });
+            ruba.schedule(task);
+        }
     }
 
     fn worker_loop(ruba: Arc<InnerRuba>, thread_id: usize) {
@@ -130,40 +194,131 @@ impl InnerRuba {
         trace_receiver
     }
 
-    pub fn load_batches(&self, table: &str, batches: Vec<Batch>) {
+    /// Loads either all or none of `batches`.
+    pub fn load_batches(&self, table: &str, batches: Vec<Batch>) -> Result<(), QueryError> {
         self.create_if_empty(table);
         let tables = self.tables.read().unwrap();
-        let table = tables.get(table).unwrap();
-        for batch in batches {
-            table.load_batch(batch);
-        }
+        tables.get(table).unwrap().load_batches(batches)
     }
 
-    pub fn ingest(&self, table: &str, row: Vec<(String, RawVal)>) {
+    pub fn ingest(&self, table: &str, row: Vec<(String, RawVal)>) -> Result<(), QueryError> {
         self.create_if_empty(table);
         let tables = self.tables.read().unwrap();
         tables.get(table).unwrap().ingest(row)
     }
 
     #[allow(dead_code)]
-    pub fn ingest_homogeneous(&self, table: &str, columns: HashMap<String, InputColumn>) {
+    pub fn ingest_homogeneous(&self, table: &str, columns: HashMap<String, InputColumn>) -> Result<(), QueryError> {
         self.create_if_empty(table);
         let tables = self.tables.read().unwrap();
         tables.get(table).unwrap().ingest_homogeneous(columns)
     }
 
     #[allow(dead_code)]
-    pub fn ingest_heterogeneous(&self, table: &str, columns: HashMap<String, Vec<RawVal>>) {
+    pub fn ingest_heterogeneous(&self, table: &str, columns: HashMap<String, Vec<RawVal>>) -> Result<(), QueryError> {
         self.create_if_empty(table);
         let tables = self.tables.read().unwrap();
         tables.get(table).unwrap().ingest_heterogeneous(columns)
     }
 
+    pub fn create_table(&self, metadata: Metadata) -> Result<(), QueryError> {
+        if let Some(ref schema) = metadata.schema {
+            for (i, column) in schema.iter().enumerate() {
+                if schema[..i].iter().any(|c| c.name == column.name) {
+                    bail!(QueryError::SchemaViolation, "Column {} is declared more than once", &column.name)
+                }
+                if let Some(codec) = column.codec {
+                    if !codec.supports(column.column_type) {
+                        bail!(QueryError::SchemaViolation, "Codec {:?} cannot be used for {:?} column {}",
+                              codec, column.column_type, &column.name)
+                    }
+                }
+            }
+            if let Some(ref time_column) = metadata.time_column {
+                match schema.iter().find(|c| &c.name == time_column) {
+                    Some(column) if column.column_type == ColumnType::Integer => {}
+                    Some(_) => bail!(QueryError::SchemaViolation, "Time column {} must be an integer", time_column),
+                    None => bail!(QueryError::SchemaViolation, "Time column {} is not declared", time_column),
+                }
+            }
+        }
+        if metadata.retention.max_age.is_some() && metadata.time_column.is_none() {
+            bail!(QueryError::SchemaViolation, "max_age requires a time_column")
+        }
+        if metadata.batch_size == Some(0) {
+            bail!(QueryError::SchemaViolation, "batch_size must be positive")
+        }
+        let name = metadata.name.clone();
+        {
+            let mut tables = self.tables.write().unwrap();
+            if tables.contains_key(&name) {
+                bail!(QueryError::TableAlreadyExists, name)
+            }
+            tables.insert(name.clone(), Table::new(10_000, &name, metadata));
+        }
+        self.log_table_creation(&name);
+        Ok(())
+    }
+
+    pub fn drop_table(&self, table: &str) -> Result<(), QueryError> {
+        let mut tables = self.tables.write().unwrap();
+        match tables.remove(table) {
+            Some(_) => Ok(()),
+            None => Err(QueryError::TableNotFound(table.to_string())),
+        }
+    }
+
+    pub fn truncate(&self, table: &str) -> Result<(), QueryError> {
+        let tables = self.tables.read().unwrap();
+        match tables.get(table) {
+            Some(table) => {
+                table.truncate();
+                Ok(())
+            }
+            None => Err(QueryError::TableNotFound(table.to_string())),
+        }
+    }
+
+    pub fn delete(&self, table: &str, filter: Option<&Expr>) -> Result<usize, QueryError> {
+        let tables = self.tables.read().unwrap();
+        match tables.get(table) {
+            Some(table) => table.delete(filter),
+            None => Err(QueryError::TableNotFound(table.to_string())),
+        }
+    }
+
+    pub fn metadata(&self, table: &str) -> Option<Metadata> {
+        let tables = self.tables.read().unwrap();
+        tables.get(table).map(|t| t.metadata())
+    }
+
+    pub fn shared_dictionaries(&self, table: &str) -> SharedDictionaries {
+        let tables = self.tables.read().unwrap();
+        tables.get(table).map_or_else(SharedDictionaries::new, |t| t.shared_dictionaries().clone())
+    }
+
+    pub fn enforce_retention(&self) {
+        let now = time::now().to_timespec().sec;
+        let tables = self.tables.read().unwrap();
+        for table in tables.values() {
+            table.enforce_retention(now);
+        }
+    }
+
     pub fn stats(&self) -> Vec<TableStats> {
         let tables = self.tables.read().unwrap();
         tables.values().map(|table| table.stats()).collect()
     }
 
+    pub fn describe(&self, table: &str) -> Option<Vec<ColumnDescription>> {
+        let tables = self.tables.read().unwrap();
+        tables.get(table).map(|t| t.describe())
+    }
+
+    pub fn column_stats(&self, table: &str) -> Option<Vec<ColumnStats>> {
+        self.snapshot(table).map(|batches| Table::column_stats(&batches))
+    }
+
     fn create_if_empty(&self, table: &str) {
         let exists = {
             let tables = self.tables.read().unwrap();
@@ -172,13 +327,53 @@ impl InnerRuba {
         if !exists {
             {
                 let mut tables = self.tables.write().unwrap();
-                tables.insert(table.to_string(), Table::new(10_000, table, Metadata { batch_count: 0, name: table.to_string() }));
+                if tables.contains_key(table) { return; }
+                tables.insert(table.to_string(), Table::new(10_000, table, Metadata::new(table)));
             }
-            self.ingest("_meta_tables", vec![
-                ("timestamp".to_string(), RawVal::Int(time::now().to_timespec().sec)),
-                ("name".to_string(), RawVal::Str(table.to_string())),
-            ]);
+            self.log_table_creation(table);
+        }
+    }
+
+    fn log_table_creation(&self, table: &str) {
+        // `_meta_tables` has no schema so this cannot fail
+        let _ = self.ingest("_meta_tables", vec![
+            ("timestamp".to_string(), RawVal::Int(time::now().to_timespec().sec)),
+            ("name".to_string(), RawVal::Str(table.to_string())),
+        ]);
+    }
+}
+
+
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+    let mut remaining = &name[first.len()..];
+    let mut parts = parts.collect::<Vec<_>>();
+    let last = parts.pop().unwrap_or("");
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
         }
     }
+    remaining.len() >= last.len() && remaining.ends_with(last)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_wildcard() {
+        assert!(matches_wildcard("events_*", "events_20261015"));
+        assert!(matches_wildcard("events_*", "events_"));
+        assert!(matches_wildcard("*_2026*", "events_20261015"));
+        assert!(matches_wildcard("e*s*5", "events_20261015"));
+        assert!(!matches_wildcard("events_*", "event_20261015"));
+        assert!(!matches_wildcard("*_2025*", "events_20261015"));
+        assert!(!matches_wildcard("events*s", "events"));
+    }
+}
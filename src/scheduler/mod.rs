@@ -1,7 +1,9 @@
+mod cancellation;
 mod shared_sender;
 mod task;
 mod inner_ruba;
 
 pub use self::inner_ruba::InnerRuba;
 pub use self::task::Task;
-pub use self::shared_sender::SharedSender;
\ No newline at end of file
+pub use self::shared_sender::SharedSender;
+pub use self::cancellation::CancellationHandle;
\ No newline at end of file
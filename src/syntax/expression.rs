@@ -3,14 +3,22 @@ use std::collections::HashSet;
 use ingest::raw_val::RawVal;
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Expr {
     ColName(String),
     Func(FuncType, Box<Expr>, Box<Expr>),
     Const(RawVal),
+    /// Filter on recent rows such as `$LAST_HOUR`, which is resolved right before the query is executed.
+    TimeMacro(TimeMacro),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimeMacro {
+    LastHour,
+    LastDay,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum FuncType {
     Equals,
     LT,
@@ -44,5 +52,57 @@ impl Expr {
     pub fn func(ftype: FuncType, expr1: Expr, expr2: Expr) -> Expr {
         Func(ftype, Box::new(expr1), Box::new(expr2))
     }
+
+    /// Replaces time macros with filters on the `timestamp` column relative to `now` (in seconds).
+    pub fn resolve_time_macros(self, now: i64) -> Expr {
+        match self {
+            TimeMacro(time_macro) => Expr::func(FuncType::GT,
+                                                ColName("timestamp".to_string()),
+                                                Const(RawVal::Int(now - time_macro.seconds()))),
+            Func(ftype, expr1, expr2) => Expr::func(ftype, expr1.resolve_time_macros(now), expr2.resolve_time_macros(now)),
+            other => other,
+        }
+    }
+
+    pub fn has_time_macros(&self) -> bool {
+        match *self {
+            TimeMacro(_) => true,
+            Func(_, ref expr1, ref expr2) => expr1.has_time_macros() || expr2.has_time_macros(),
+            _ => false,
+        }
+    }
+
+    /// Equivalent expression with the operands of commutative functions in a fixed order.
+    pub fn canonical(&self) -> Expr {
+        match *self {
+            Func(ftype, ref expr1, ref expr2) => {
+                let (expr1, expr2) = (expr1.canonical(), expr2.canonical());
+                if ftype.is_commutative() && expr2 < expr1 {
+                    Expr::func(ftype, expr2, expr1)
+                } else {
+                    Expr::func(ftype, expr1, expr2)
+                }
+            }
+            ref other => other.clone(),
+        }
+    }
+}
+
+impl TimeMacro {
+    fn seconds(self) -> i64 {
+        match self {
+            TimeMacro::LastHour => 3600,
+            TimeMacro::LastDay => 86400,
+        }
+    }
+}
+
+impl FuncType {
+    fn is_commutative(self) -> bool {
+        match self {
+            FuncType::Equals | FuncType::And | FuncType::Or | FuncType::Add | FuncType::Multiply => true,
+            _ => false,
+        }
+    }
 }
 
@@ -1,5 +1,13 @@
-#[derive(Clone, Debug, Hash, PartialEq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct LimitClause {
-    pub limit: u64,
+    /// `None` if all rows should be returned, i.e. no limit or `LIMIT ALL`
+    pub limit: Option<u64>,
     pub offset: u64,
 }
+
+impl LimitClause {
+    /// Number of rows required to produce the result, i.e. limit plus offset.
+    pub fn combined(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.saturating_add(self.offset) as usize)
+    }
+}
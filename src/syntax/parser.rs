@@ -1,61 +1,400 @@
 #![allow(unused_parens)]
 
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::mem;
 use std::str;
 use std::str::FromStr;
-use nom::{digit, is_alphabetic, is_alphanumeric, multispace};
+use std::usize;
+use nom::{digit, is_alphabetic, is_alphanumeric, multispace, ErrorKind, IResult};
 
 use syntax::expression::*;
 use syntax::limit::LimitClause;
+use syntax::statement::Statement;
 use engine::query::*;
 use engine::aggregator::Aggregator;
+use engine::join::JoinClause;
 use ingest::raw_val::RawVal;
-use std::boxed::Box;
-use time;
+use mem_store::table::{CodecHint, ColumnSchema, ColumnType, Metadata};
+use ::QueryError;
+use errors::SyntaxError;
+
+
+thread_local!(
+    // Remaining input length at the furthest position where any parser failed and the tokens expected there
+    static FURTHEST_FAILURE: RefCell<(usize, BTreeSet<&'static str>)> = RefCell::new((usize::MAX, BTreeSet::new()));
+);
+
+fn record_expected(remaining: &[u8], token: &'static str) {
+    FURTHEST_FAILURE.with(|failure| {
+        let mut failure = failure.borrow_mut();
+        if remaining.len() < failure.0 {
+            *failure = (remaining.len(), BTreeSet::new());
+        }
+        if remaining.len() == failure.0 {
+            failure.1.insert(token);
+        }
+    })
+}
+
+/// Applies a parser and records `token` as expected at the current position if the parser fails.
+macro_rules! expecting (
+    ($i:expr, $token:expr, $submac:ident!( $($args:tt)* )) => ({
+        let input = $i;
+        let result = $submac!(input, $($args)*);
+        if !result.is_done() {
+            record_expected(input, $token);
+        }
+        result
+    });
+);
+
+macro_rules! keyword (
+    ($i:expr, $keyword:tt) => ( expecting!($i, concat!("`", $keyword, "`"), tag_no_case!($keyword)) );
+);
+
+macro_rules! symbol (
+    ($i:expr, $symbol:tt) => ( expecting!($i, concat!("`", $symbol, "`"), tag!($symbol)) );
+);
+
+/// Parses a complete statement. On failure, reports the furthest position any parser reached.
+pub fn parse(query: &str) -> Result<Statement, QueryError> {
+    FURTHEST_FAILURE.with(|failure| *failure.borrow_mut() = (usize::MAX, BTreeSet::new()));
+    let result = parse_statement(query.as_bytes());
+    let (failure_remaining, expected) = FURTHEST_FAILURE.with(|failure|
+        mem::replace(&mut *failure.borrow_mut(), (usize::MAX, BTreeSet::new())));
+    let remaining = match result {
+        IResult::Done(remaining, statement) => {
+            if remaining.is_empty() {
+                return Ok(statement);
+            }
+            remaining.len()
+        }
+        IResult::Error(_) | IResult::Incomplete(_) => query.len(),
+    };
+    let (remaining, expected) = if failure_remaining <= remaining {
+        (failure_remaining, expected.into_iter().map(|token| token.to_string()).collect())
+    } else {
+        (remaining, Vec::new())
+    };
+    Err(QueryError::SyntaxError(SyntaxError::new(query, query.len() - remaining, expected)))
+}
+
+
+// Whitespace, `-- line comments` and `/* block comments */`
+named!(ws<&[u8], ()>,
+    map!(
+        many1!(alt_complete!(
+            multispace |
+            recognize!(preceded!(tag!("--"), opt!(complete!(is_not!("\n"))))) |
+            recognize!(preceded!(tag!("/*"), take_until_and_consume!("*/")))
+        )),
+        |_| ()
+    )
+);
+
+named!(pub parse_query<&[u8], Query>,
+    do_parse!(
+        query: select_query >>
+        opt!(complete!(symbol!(";"))) >>
+        (query)
+    )
+);
+
+named!(pub parse_statement<&[u8], Statement>,
+    do_parse!(
+        opt!(ws) >>
+        statement: alt_complete!(
+            show_tables |
+            show_stats |
+            describe |
+            create_table |
+            drop_table |
+            truncate |
+            delete |
+            map!(separated_nonempty_list_complete!(union_all, select_query), Statement::Select)
+        ) >>
+        opt!(complete!(ws)) >>
+        opt!(complete!(symbol!(";"))) >>
+        (statement)
+    )
+);
+
+named!(show_tables<&[u8], Statement>,
+    do_parse!(
+        keyword!("show") >>
+        ws >>
+        keyword!("tables") >>
+        (Statement::ShowTables)
+    )
+);
+
+named!(show_stats<&[u8], Statement>,
+    do_parse!(
+        keyword!("show") >>
+        ws >>
+        keyword!("stats") >>
+        ws >>
+        table: identifier >>
+        (Statement::ShowStats(table))
+    )
+);
+
+named!(describe<&[u8], Statement>,
+    do_parse!(
+        keyword!("describe") >>
+        ws >>
+        table: identifier >>
+        (Statement::Describe(table))
+    )
+);
+
+named!(create_table<&[u8], Statement>,
+    do_parse!(
+        keyword!("create") >>
+        ws >>
+        keyword!("table") >>
+        ws >>
+        table: identifier >>
+        columns: opt!(complete!(do_parse!(
+            opt!(ws) >>
+            symbol!("(") >>
+            columns: separated_nonempty_list_complete!(symbol!(","), column_definition) >>
+            symbol!(")") >>
+            (columns)
+        ))) >>
+        options: opt!(complete!(table_options)) >>
+        (construct_create_table(&table, columns, options.unwrap_or_default()))
+    )
+);
+
+named!(column_definition<&[u8], ColumnSchema>,
+    do_parse!(
+        opt!(ws) >>
+        name: identifier >>
+        ws >>
+        column_type: column_type >>
+        codec: opt!(complete!(preceded!(ws, codec_hint))) >>
+        not_null: opt!(complete!(preceded!(ws, not_null))) >>
+        opt!(ws) >>
+        (ColumnSchema { name, column_type, codec, nullable: not_null.is_none() })
+    )
+);
+
+named!(not_null<&[u8], ()>,
+    do_parse!(
+        keyword!("not") >>
+        ws >>
+        keyword!("null") >>
+        ()
+    )
+);
+
+named!(column_type<&[u8], ColumnType>,
+    alt_complete!(
+        map!(alt_complete!(keyword!("integer") | keyword!("int")), |_| ColumnType::Integer) |
+        map!(alt_complete!(keyword!("string") | keyword!("varchar") | keyword!("text")),
+             |_| ColumnType::String)
+    )
+);
+
+named!(codec_hint<&[u8], CodecHint>,
+    alt_complete!(
+        map!(keyword!("dictionary"), |_| CodecHint::Dictionary) |
+        map!(keyword!("packed"), |_| CodecHint::Packed) |
+        map!(keyword!("shared"), |_| CodecHint::Shared) |
+        map!(keyword!("compressed"), |_| CodecHint::Compressed) |
+        map!(keyword!("plain"), |_| CodecHint::Plain)
+    )
+);
+
+named!(table_options<&[u8], Vec<TableOption>>,
+    do_parse!(
+        ws >>
+        keyword!("with") >>
+        opt!(ws) >>
+        symbol!("(") >>
+        options: separated_nonempty_list_complete!(symbol!(","), table_option) >>
+        symbol!(")") >>
+        (options)
+    )
+);
+
+named!(table_option<&[u8], TableOption>,
+    do_parse!(
+        opt!(ws) >>
+        option: alt_complete!(
+            do_parse!(
+                keyword!("batch_size") >>
+                opt!(ws) >>
+                symbol!("=") >>
+                opt!(ws) >>
+                batch_size: number >>
+                (TableOption::BatchSize(batch_size as usize))
+            ) |
+            do_parse!(
+                keyword!("time_column") >>
+                opt!(ws) >>
+                symbol!("=") >>
+                opt!(ws) >>
+                column: identifier >>
+                (TableOption::TimeColumn(column))
+            ) |
+            do_parse!(
+                keyword!("max_age") >>
+                opt!(ws) >>
+                symbol!("=") >>
+                opt!(ws) >>
+                max_age: duration >>
+                (TableOption::MaxAge(max_age))
+            ) |
+            do_parse!(
+                keyword!("max_rows") >>
+                opt!(ws) >>
+                symbol!("=") >>
+                opt!(ws) >>
+                max_rows: number >>
+                (TableOption::MaxRows(max_rows as usize))
+            ) |
+            do_parse!(
+                keyword!("max_bytes") >>
+                opt!(ws) >>
+                symbol!("=") >>
+                opt!(ws) >>
+                max_bytes: number >>
+                (TableOption::MaxBytes(max_bytes as usize))
+            )
+        ) >>
+        opt!(ws) >>
+        (option)
+    )
+);
 
+// Duration in seconds, e.g. `3600`, `60m` or `7d`
+named!(duration<&[u8], u64>,
+    do_parse!(
+        value: number >>
+        unit: opt!(complete!(one_of!("smhd"))) >>
+        (value * match unit {
+            Some('m') => 60,
+            Some('h') => 3600,
+            Some('d') => 86_400,
+            _ => 1,
+        })
+    )
+);
 
-named!(pub parse_query<&[u8], Query>, alt_complete!(full_query | simple_query));
+fn construct_create_table(table: &str, columns: Option<Vec<ColumnSchema>>, options: Vec<TableOption>) -> Statement {
+    let mut metadata = Metadata::new(table);
+    metadata.schema = columns;
+    for option in options {
+        match option {
+            TableOption::BatchSize(batch_size) => metadata.batch_size = Some(batch_size),
+            TableOption::TimeColumn(column) => metadata.time_column = Some(column),
+            TableOption::MaxAge(max_age) => metadata.retention.max_age = Some(max_age),
+            TableOption::MaxRows(max_rows) => metadata.retention.max_rows = Some(max_rows),
+            TableOption::MaxBytes(max_bytes) => metadata.retention.max_bytes = Some(max_bytes),
+        }
+    }
+    Statement::CreateTable(metadata)
+}
+
+named!(drop_table<&[u8], Statement>,
+    do_parse!(
+        keyword!("drop") >>
+        ws >>
+        keyword!("table") >>
+        ws >>
+        table: identifier >>
+        (Statement::DropTable(table))
+    )
+);
+
+named!(truncate<&[u8], Statement>,
+    do_parse!(
+        keyword!("truncate") >>
+        ws >>
+        opt!(terminated!(keyword!("table"), ws)) >>
+        table: identifier >>
+        (Statement::Truncate(table))
+    )
+);
+
+named!(delete<&[u8], Statement>,
+    do_parse!(
+        keyword!("delete") >>
+        ws >>
+        keyword!("from") >>
+        ws >>
+        table: identifier >>
+        filter: opt!(complete!(do_parse!(
+            ws >>
+            keyword!("where") >>
+            ws >>
+            filter: expr >>
+            (filter)
+        ))) >>
+        (Statement::Delete { table, filter })
+    )
+);
+
+named!(union_all<&[u8], ()>,
+    do_parse!(
+        keyword!("union") >>
+        ws >>
+        keyword!("all") >>
+        ws >>
+        ()
+    )
+);
+
+named!(select_query<&[u8], Query>, alt_complete!(full_query | simple_query));
 
 named!(full_query<&[u8], Query>,
     do_parse!(
-        tag_no_case!("select") >>
-        multispace >>
+        keyword!("select") >>
+        ws >>
+        distinct: opt!(complete!(terminated!(keyword!("distinct"), ws))) >>
         select: select_clauses >>
-        opt!(multispace) >>
+        opt!(ws) >>
         table: from_clause >>
-        multispace >>
-        tag_no_case!("where") >>
-        multispace >>
+        join: opt!(complete!(join_clause)) >>
+        ws >>
+        keyword!("where") >>
+        ws >>
         filter: expr >>
-        opt!(multispace) >>
+        opt!(ws) >>
         order_by: opt!(order_by_clause) >>
-        opt!(multispace) >>
+        opt!(ws) >>
         limit: opt!(limit_clause) >>
-        opt!(multispace) >>
-        char!(';') >>
-        (construct_query(select, table, filter, order_by, limit))
+        opt!(ws) >>
+        (construct_query(select, distinct.is_some(), table, join, filter, order_by, limit))
     )
 );
 
 
 named!(simple_query<&[u8], Query>,
     do_parse!(
-        tag_no_case!("select") >>
-        multispace >>
+        keyword!("select") >>
+        ws >>
+        distinct: opt!(complete!(terminated!(keyword!("distinct"), ws))) >>
         select: select_clauses >>
-        opt!(multispace) >>
+        opt!(ws) >>
         table: from_clause >>
-        opt!(multispace) >>
+        join: opt!(complete!(join_clause)) >>
+        opt!(ws) >>
         order_by: opt!(order_by_clause) >>
-        opt!(multispace) >>
+        opt!(ws) >>
         limit: opt!(limit_clause) >>
-        opt!(multispace) >>
-        opt!(char!(';')) >>
-        (construct_query(select, table, Expr::Const(RawVal::Int(1)), order_by, limit))
+        opt!(ws) >>
+        (construct_query(select, distinct.is_some(), table, join, Expr::Const(RawVal::Int(1)), order_by, limit))
     )
 );
 
 fn construct_query(select_clauses: Vec<AggregateOrSelect>,
-                       table: &str,
+                       distinct: bool,
+                       table: String,
+                       join: Option<JoinClause>,
                        filter: Expr,
                        order_by: Option<(String, bool)>,
                        limit: Option<LimitClause>)
@@ -64,12 +403,14 @@ fn construct_query(select_clauses: Vec<AggregateOrSelect>,
     let order_desc = order_by.as_ref().map(|x| x.1).unwrap_or(false);
     Query {
         select,
-        table: table.to_string(),
+        distinct,
+        table,
+        join,
         filter,
         aggregate,
         order_by: order_by.map(|x| x.0),
         order_desc,
-        limit: limit.unwrap_or(LimitClause { limit: 100, offset: 0 }),
+        limit: limit.unwrap_or(LimitClause { limit: None, offset: 0 }),
         order_by_index: None,
     }
 }
@@ -97,25 +438,58 @@ fn partition(select_or_aggregates: Vec<AggregateOrSelect>)
          .collect())
 }
 
-named!(from_clause<&[u8], &str>,
+named!(from_clause<&[u8], String>,
     do_parse!(
-        tag_no_case!("from") >>
-        multispace >>
-        from: identifier >>
+        keyword!("from") >>
+        ws >>
+        from: table_identifier >>
         (from)
     )
 );
 
+named!(join_clause<&[u8], JoinClause>,
+    do_parse!(
+        ws >>
+        opt!(terminated!(keyword!("inner"), ws)) >>
+        keyword!("join") >>
+        ws >>
+        table: identifier >>
+        ws >>
+        keyword!("on") >>
+        ws >>
+        lhs: qualified_colname >>
+        opt!(ws) >>
+        symbol!("=") >>
+        opt!(ws) >>
+        rhs: qualified_colname >>
+        (construct_join(table, lhs, rhs))
+    )
+);
+
+named!(qualified_colname<&[u8], (String, String)>,
+    do_parse!(
+        table: identifier >>
+        symbol!(".") >>
+        column: identifier >>
+        ((table, column))
+    )
+);
+
+fn construct_join(table: String, lhs: (String, String), rhs: (String, String)) -> JoinClause {
+    let (fact_key, dim_key) = if lhs.0 == table && rhs.0 != table { (rhs.1, lhs.1) } else { (lhs.1, rhs.1) };
+    JoinClause { table, fact_key, dim_key }
+}
+
 named!(select_clauses<&[u8], Vec<AggregateOrSelect>>,
     alt!(
         do_parse!(
-            opt!(multispace) >>
-            tag!("*") >>
-            opt!(multispace) >>
+            opt!(ws) >>
+            symbol!("*") >>
+            opt!(ws) >>
             (vec![AggregateOrSelect::Select(Expr::ColName("*".to_string()))])
         ) |
         separated_list!(
-            tag!(","),
+            symbol!(","),
             alt_complete!(aggregate_clause | select_clause)
         )
     )
@@ -123,12 +497,12 @@ named!(select_clauses<&[u8], Vec<AggregateOrSelect>>,
 
 named!(aggregate_clause<&[u8], AggregateOrSelect>,
     do_parse!(
-        opt!(multispace) >>
+        opt!(ws) >>
         atype: aggregate_func >>
-        char!('(') >>
+        symbol!("(") >>
         e: expr >>
-        opt!(multispace) >>
-        char!(')') >>
+        opt!(ws) >>
+        symbol!(")") >>
         (AggregateOrSelect::Aggregate((atype, e)))
     )
 );
@@ -138,16 +512,16 @@ named!(select_clause<&[u8], AggregateOrSelect>, map!(expr, AggregateOrSelect::Se
 named!(aggregate_func<&[u8], Aggregator>, alt!(count | sum));
 
 named!(count<&[u8], Aggregator>,
-    map!( tag_no_case!("count"), |_| Aggregator::Count )
+    map!( keyword!("count"), |_| Aggregator::Count )
 );
 
 named!(sum<&[u8], Aggregator>,
-    map!( tag_no_case!("sum"), |_| Aggregator::Sum )
+    map!( keyword!("sum"), |_| Aggregator::Sum )
 );
 
 named!(expr<&[u8], Expr>,
     do_parse!(
-        opt!(multispace) >>
+        opt!(ws) >>
         result: alt!(infix_expr | expr_no_left_recur) >>
         (result)
     )
@@ -155,18 +529,18 @@ named!(expr<&[u8], Expr>,
 
 named!(expr_no_left_recur<&[u8], Expr>,
     do_parse!(
-        opt!(multispace) >>
-        result: alt!(parentheses | template | function | negation | colname | constant) >>
+        opt!(ws) >>
+        result: alt!(parentheses | template | function | constant | negation | colname) >>
         (result)
     )
 );
 
 named!(parentheses<&[u8], Expr>,
     do_parse!(
-        char!('(') >>
+        symbol!("(") >>
         e1: expr >>
-        opt!(multispace) >>
-        char!(')') >>
+        opt!(ws) >>
+        symbol!(")") >>
         (e1)
     )
 );
@@ -176,37 +550,23 @@ named!(template<&[u8], Expr>,
 );
 
 named!(last_hour<&[u8], Expr>,
-    map!(
-        tag_no_case!("$LAST_HOUR"),
-        |_| Expr::Func(
-                FuncType::GT,
-                Box::new(Expr::ColName("timestamp".to_string())),
-                Box::new(Expr::Const(RawVal::Int(time::now().to_timespec().sec - 3600)))
-        )
-    )
+    map!(keyword!("$LAST_HOUR"), |_| Expr::TimeMacro(TimeMacro::LastHour))
 );
 
 named!(last_day<&[u8], Expr>,
-    map!(
-        tag_no_case!("$LAST_DAY"),
-        |_| Expr::Func(
-                FuncType::GT,
-                Box::new(Expr::ColName("timestamp".to_string())),
-                Box::new(Expr::Const(RawVal::Int(time::now().to_timespec().sec - 86400)))
-        )
-    )
+    map!(keyword!("$LAST_DAY"), |_| Expr::TimeMacro(TimeMacro::LastDay))
 );
 
 named!(function<&[u8], Expr>,
     do_parse!(
         ft: function_name >>
-        char!('(') >>
+        symbol!("(") >>
         e1: expr >>
-        opt!(multispace) >>
-        char!(',') >>
+        opt!(ws) >>
+        symbol!(",") >>
         e2: expr >>
-        opt!(multispace) >>
-        char!(')') >>
+        opt!(ws) >>
+        symbol!(")") >>
         (Expr::func(ft, e1, e2))
     )
 );
@@ -214,7 +574,7 @@ named!(function<&[u8], Expr>,
 named!(infix_expr<&[u8], Expr>,
     do_parse!(
         e1: expr_no_left_recur >>
-        opt!(multispace) >>
+        opt!(ws) >>
         ft: infix_function_name >>
         e2: expr >>
         (Expr::func(ft, e1, e2))
@@ -223,8 +583,8 @@ named!(infix_expr<&[u8], Expr>,
 
 named!(negation<&[u8], Expr>,
     do_parse!(
-        char!('-') >>
-        opt!(multispace) >>
+        symbol!("-") >>
+        opt!(ws) >>
         e: expr >>
         (Expr::func(FuncType::Negate, e, Expr::Const(RawVal::Null)))
     )
@@ -238,43 +598,80 @@ named!(constant<&[u8], Expr>,
 );
 
 
+// Signed decimal or scientific literal with an integral value such as `-5`, `2.0` or `1e3`.
+// There is no floating point type, so fractional values such as `2.5` fail to parse at the position of the literal.
 named!(integer<&[u8], RawVal>,
-    map!(
+    expecting!("integer", map!(
         map_res!(
             map_res!(
-                digit,
+                recognize!(do_parse!(
+                    opt!(complete!(one_of!("+-"))) >>
+                    digit >>
+                    opt!(complete!(preceded!(char!('.'), digit))) >>
+                    opt!(complete!(tuple!(one_of!("eE"), opt!(complete!(one_of!("+-"))), digit))) >>
+                    ()
+                )),
                 str::from_utf8
             ),
-            FromStr::from_str
+            integral_value
         ),
         RawVal::Int
-    )
+    ))
 );
 
+fn integral_value(literal: &str) -> Result<i64, String> {
+    if let Ok(int) = i64::from_str(literal) {
+        return Ok(int);
+    }
+    let float = f64::from_str(literal).map_err(|err| err.to_string())?;
+    if float.fract() == 0.0 && float.abs() < i64::MAX as f64 {
+        Ok(float as i64)
+    } else {
+        Err(format!("{} is not an integer", literal))
+    }
+}
+
 named!(number<&[u8], u64>,
-    map_res!(
+    expecting!("number", map_res!(
         map_res!(
             digit,
             str::from_utf8
         ),
         FromStr::from_str
-    )
+    ))
 );
 
+// Single-quoted string, e.g. `'O''Brien'`
 named!(string<&[u8], RawVal>,
-    do_parse!(
-        char!('"') >>
-        s: is_not!("\"") >>
-        char!('"') >>
-        (RawVal::Str(str::from_utf8(s).unwrap().to_string()))
-    )
+    expecting!("string", map!(call!(quoted, b'\''), RawVal::Str))
 );
 
+/// Parses text enclosed in `quote` characters, where a doubled quote character stands for itself.
+fn quoted(input: &[u8], quote: u8) -> IResult<&[u8], String> {
+    if input.first() != Some(&quote) {
+        return IResult::Error(error_position!(ErrorKind::Char, input));
+    }
+    let mut text = Vec::new();
+    let mut i = 1;
+    while i < input.len() {
+        if input[i] != quote {
+            text.push(input[i]);
+            i += 1;
+        } else if input.get(i + 1) == Some(&quote) {
+            text.push(quote);
+            i += 2;
+        } else {
+            return match String::from_utf8(text) {
+                Ok(text) => IResult::Done(&input[i + 1..], text),
+                Err(_) => IResult::Error(error_position!(ErrorKind::Char, input)),
+            };
+        }
+    }
+    IResult::Error(error_position!(ErrorKind::Char, input))
+}
+
 named!(colname<&[u8], Expr>,
-    map!(
-        identifier,
-        |ident: &str| Expr::ColName(ident.to_string())
-    )
+    map!(identifier, Expr::ColName)
 );
 
 named!(function_name<&[u8], FuncType>,
@@ -286,53 +683,76 @@ named!(infix_function_name<&[u8], FuncType>,
 );
 
 named!(divide<&[u8], FuncType>,
-    map!( tag!("/"), |_| FuncType::Divide)
+    map!( symbol!("/"), |_| FuncType::Divide)
 );
 
 named!(add<&[u8], FuncType>,
-    map!( tag!("+"), |_| FuncType::Add)
+    map!( symbol!("+"), |_| FuncType::Add)
 );
 
 named!(multiply<&[u8], FuncType>,
-    map!( tag!("*"), |_| FuncType::Multiply)
+    map!( symbol!("*"), |_| FuncType::Multiply)
 );
 
 named!(subtract<&[u8], FuncType>,
-    map!( tag!("-"), |_| FuncType::Subtract)
+    map!( symbol!("-"), |_| FuncType::Subtract)
 );
 
 named!(equals<&[u8], FuncType>,
-    map!( tag!("="), |_| FuncType::Equals)
+    map!( symbol!("="), |_| FuncType::Equals)
 );
 
 named!(greater<&[u8], FuncType>,
-    map!( tag!(">"), |_| FuncType::GT)
+    map!( symbol!(">"), |_| FuncType::GT)
 );
 
 named!(less<&[u8], FuncType>,
-    map!( tag!("<"), |_| FuncType::LT)
+    map!( symbol!("<"), |_| FuncType::LT)
 );
 
 named!(and<&[u8], FuncType>,
-    map!( tag_no_case!("and"), |_| FuncType::And)
+    map!( keyword!("and"), |_| FuncType::And)
 );
 
 named!(or<&[u8], FuncType>,
-    map!( tag_no_case!("or"), |_| FuncType::Or)
+    map!( keyword!("or"), |_| FuncType::Or)
 );
 
 named!(regex<&[u8], FuncType>,
-    map!( tag_no_case!("regex"), |_| FuncType::RegexMatch)
+    map!( keyword!("regex"), |_| FuncType::RegexMatch)
 );
 
 
-named!(identifier<&[u8], &str>,
-    map_res!(
-        take_while1!(is_ident_char),
-        create_sql_identifier
-    )
+named!(identifier<&[u8], String>,
+    expecting!("identifier", alt_complete!(
+        quoted_identifier |
+        map!(
+            map_res!(take_while1!(is_ident_char), create_sql_identifier),
+            |ident: &str| ident.to_string()
+        )
+    ))
+);
+
+// Table names may contain `*` wildcards, e.g. `events_*`
+named!(table_identifier<&[u8], String>,
+    expecting!("identifier", alt_complete!(
+        quoted_identifier |
+        map!(
+            map_res!(take_while1!(is_table_ident_char), str::from_utf8),
+            |ident: &str| ident.to_string()
+        )
+    ))
 );
 
+// Double-quoted or backtick-quoted identifier which may contain arbitrary characters, e.g. `"Trip Pickup"`
+named!(quoted_identifier<&[u8], String>,
+    alt_complete!(call!(quoted, b'"') | call!(quoted, b'`'))
+);
+
+fn is_table_ident_char(chr: u8) -> bool {
+    is_ident_char(chr) || chr == b'*'
+}
+
 fn create_sql_identifier(bytes: &[u8]) -> Result<&str, String> {
     if is_ident_start_char(bytes[0]) {
         str::from_utf8(bytes).map_err(|_| "UTF8Error".to_string())
@@ -351,14 +771,14 @@ fn is_ident_char(chr: u8) -> bool {
 
 named!(limit_clause<&[u8], LimitClause>,
     do_parse!(
-        tag_no_case!("limit") >>
-        multispace >>
-        limit_val: number >>
+        keyword!("limit") >>
+        ws >>
+        limit_val: alt!(value!(None, keyword!("all")) | map!(number, Some)) >>
         offset_val: opt!(
             do_parse!(
-                multispace >>
-                tag_no_case!("offset") >>
-                multispace >>
+                ws >>
+                keyword!("offset") >>
+                ws >>
                 val: number >>
                 (val)
             )) >>
@@ -374,23 +794,31 @@ named!(limit_clause<&[u8], LimitClause>,
 named!(order_by_clause<&[u8], (String, bool)>,
     alt!(
         do_parse!(
-            tag_no_case!("order by") >>
-            multispace >>
+            keyword!("order by") >>
+            ws >>
             order_by: identifier >>
-            multispace >>
-            tag_no_case!("desc") >>
-            (order_by.to_string(), true)
+            ws >>
+            keyword!("desc") >>
+            (order_by, true)
         ) |
         do_parse!(
-            tag_no_case!("order by") >>
-            multispace >>
+            keyword!("order by") >>
+            ws >>
             order_by: identifier >>
-            opt!(preceded!(multispace, tag_no_case!("asc"))) >>
-            (order_by.to_string(), false)
+            opt!(preceded!(ws, keyword!("asc"))) >>
+            (order_by, false)
         )
     )
 );
 
+enum TableOption {
+    BatchSize(usize),
+    TimeColumn(String),
+    MaxAge(u64),
+    MaxRows(usize),
+    MaxBytes(usize),
+}
+
 enum AggregateOrSelect {
     Aggregate((Aggregator, Expr)),
     Select(Expr),
@@ -405,14 +833,83 @@ mod tests {
     fn test_select_star() {
         assert_eq!(
             format!("{:?}", parse_query("select * from default;".as_bytes())),
-            "Done([], Query { select: [ColName(\"*\")], table: \"default\", filter: Const(Int(1)), aggregate: [], order_by: None, order_desc: false, limit: LimitClause { limit: 100, offset: 0 }, order_by_index: None })");
+            "Done([], Query { select: [ColName(\"*\")], distinct: false, table: \"default\", join: None, filter: Const(Int(1)), aggregate: [], order_by: None, order_desc: false, limit: LimitClause { limit: None, offset: 0 }, order_by_index: None })");
     }
 
     #[test]
     fn test_last_hour() {
-        assert!(
-        format!("{:?}", parse_query("select * from default where $LAST_HOUR;".as_bytes())).starts_with(
-            "Done([], Query { select: [ColName(\"*\")], table: \"default\", filter: Func(GT, ColName(\"timestamp\"), Const(Int(")
-        )
+        assert_eq!(
+            format!("{:?}", parse_query("select * from default where $LAST_HOUR;".as_bytes())),
+            "Done([], Query { select: [ColName(\"*\")], distinct: false, table: \"default\", join: None, filter: TimeMacro(LastHour), aggregate: [], order_by: None, order_desc: false, limit: LimitClause { limit: None, offset: 0 }, order_by_index: None })");
+        assert_eq!(
+            format!("{:?}", Expr::TimeMacro(TimeMacro::LastHour).resolve_time_macros(7200)),
+            "Func(GT, ColName(\"timestamp\"), Const(Int(3600)))");
+    }
+
+    #[test]
+    fn test_create_table() {
+        assert_eq!(
+            format!("{:?}", parse_statement(
+                "CREATE TABLE events (ts int not null, url string packed NOT NULL, status INTEGER) \
+                 WITH (batch_size = 512, time_column = ts);".as_bytes())),
+            "Done([], CreateTable(Metadata { name: \"events\", batch_count: 0, schema: Some([\
+             ColumnSchema { name: \"ts\", column_type: Integer, codec: None, nullable: false }, \
+             ColumnSchema { name: \"url\", column_type: String, codec: Some(Packed), nullable: false }, \
+             ColumnSchema { name: \"status\", column_type: Integer, codec: None, nullable: true }]), \
+             batch_size: Some(512), time_column: Some(\"ts\"), \
+             retention: RetentionPolicy { max_age: None, max_rows: None, max_bytes: None } }))");
+    }
+
+    #[test]
+    fn test_create_table_retention() {
+        assert_eq!(
+            format!("{:?}", parse_statement("create table events with (time_column = ts, max_age = 7d, max_rows = 1000);".as_bytes())),
+            "Done([], CreateTable(Metadata { name: \"events\", batch_count: 0, schema: None, batch_size: None, \
+             time_column: Some(\"ts\"), retention: RetentionPolicy { max_age: Some(604800), max_rows: Some(1000), max_bytes: None } }))");
+    }
+
+    #[test]
+    fn test_lexical_syntax() {
+        assert_eq!(
+            format!("{:?}", parse_statement(
+                "select \"Trip Pickup\", 'O''Brien' -- trailing comment\n\
+                 from `trips.2026` /* block\ncomment */ where fare > -2.5e1 limit 1;".as_bytes())),
+            "Done([], Select([Query { select: [ColName(\"Trip Pickup\"), Const(Str(\"O'Brien\"))], distinct: false, table: \"trips.2026\", \
+             join: None, filter: Func(GT, ColName(\"fare\"), Const(Int(-25))), aggregate: [], order_by: None, \
+             order_desc: false, limit: LimitClause { limit: Some(1), offset: 0 }, order_by_index: None }]))");
+    }
+
+    #[test]
+    fn test_fractional_literal() {
+        let err = syntax_error("select a from t where b > 2.5;");
+        assert_eq!((err.offset, err.column), (26, 27));
+        assert!(err.expected.contains(&"integer".to_string()));
+        assert_eq!(syntax_error("select a from t\nwhere b > -2.5e-1 limit 1;").offset, 27);
+        assert_eq!(syntax_error("select a from t where b > 2.5 +;").offset, 26);
+    }
+
+    fn syntax_error(query: &str) -> SyntaxError {
+        match parse(query) {
+            Err(QueryError::SyntaxError(err)) => err,
+            other => panic!("Expected syntax error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_syntax_error_expected_tokens() {
+        let err = syntax_error("show tablez;");
+        assert_eq!((err.offset, err.line, err.column), (5, 1, 6));
+        assert_eq!(err.expected, vec!["`stats`", "`tables`"]);
+        assert_eq!(err.to_string(),
+                   "Syntax error at line 1, column 6: expected one of `stats`, `tables`, found `tablez`\n\
+                    show tablez;\n     ^");
+    }
+
+    #[test]
+    fn test_syntax_error_multiline() {
+        let err = syntax_error("select a from t\nlimit x;");
+        assert_eq!((err.offset, err.line, err.column), (22, 2, 7));
+        assert_eq!(err.snippet, "limit x;");
+        assert_eq!(err.message(), "expected one of `all`, number, found `x`");
     }
 }
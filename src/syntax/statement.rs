@@ -0,0 +1,18 @@
+use engine::query::Query;
+use mem_store::table::Metadata;
+use syntax::expression::Expr;
+
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    /// One or more queries whose results are concatenated (`UNION ALL`).
+    Select(Vec<Query>),
+    ShowTables,
+    Describe(String),
+    ShowStats(String),
+    CreateTable(Metadata),
+    DropTable(String),
+    Truncate(String),
+    /// Deletes all rows if there is no filter.
+    Delete { table: String, filter: Option<Expr> },
+}
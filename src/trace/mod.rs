@@ -84,6 +84,19 @@ pub struct Span {
 }
 
 impl Trace {
+    /// Combines independent traces into a single trace with one child span per trace.
+    pub fn combine(name: &str, traces: Vec<Trace>) -> Trace {
+        let children = traces.into_iter().map(|t| t.toplevel_span).collect::<Vec<_>>();
+        Trace {
+            toplevel_span: Span {
+                name: name.to_owned(),
+                start_time: children.iter().map(|s| s.start_time).min().unwrap_or(0),
+                end_time: children.iter().map(|s| s.end_time).max().unwrap_or(0),
+                children,
+            }
+        }
+    }
+
     pub fn print(&self) {
         Trace::_print(&self.toplevel_span, 0, self.toplevel_span.start_time);
     }
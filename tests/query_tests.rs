@@ -5,6 +5,8 @@ extern crate env_logger;
 
 use ruba::*;
 use futures::executor::block_on;
+use futures::StreamExt;
+use std::time::Duration;
 
 
 fn test_query(query: &str, expected_rows: &[Vec<Value>]) {
@@ -56,16 +58,15 @@ fn test_sort_string() {
     )
 }
 
-/*
 #[test]
 fn test_sort_string_desc() {
     test_query(
         &"select first_name from default order by first_name desc limit 2;",
-        vec![vec!["Willie".into()],
-             vec!["William".into()],
+        &[vec!["Willie".into()],
+          vec!["William".into()],
         ],
     )
-}*/
+}
 
 #[test]
 fn group_by_integer_filter_integer_lt() {
@@ -92,7 +93,7 @@ fn lt_filter_on_offset_encoded_column() {
 #[test]
 fn group_by_string_filter_string_eq() {
     test_query(
-        "select first_name, count(1) from default where first_name = \"Adam\";",
+        "select first_name, count(1) from default where first_name = 'Adam';",
         &[vec!["Adam".into(), 2.into()]],
     )
 }
@@ -100,7 +101,7 @@ fn group_by_string_filter_string_eq() {
 #[test]
 fn test_and_or() {
     test_query(
-        "select first_name, last_name from default where ((first_name = \"Adam\") OR (first_name = \"Catherine\")) AND (num = 3);",
+        "select first_name, last_name from default where ((first_name = 'Adam') OR (first_name = 'Catherine')) AND (num = 3);",
         &[vec!["Adam".into(), "Crawford".into()]],
     )
 }
@@ -108,7 +109,7 @@ fn test_and_or() {
 #[test]
 fn test_sum() {
     test_query(
-        "select tld, sum(num) from default where (tld = \"name\");",
+        "select tld, sum(num) from default where (tld = 'name');",
         &[vec!["name".into(), 26.into()]],
     )
 }
@@ -154,3 +155,872 @@ fn test_multiple_group_by_2() {
         ],
     )
 }
+
+#[test]
+fn test_group_by_three_columns() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query(
+        "select enum, non_dense_ints, u8_offset_encoded, count(1) from default where u8_offset_encoded < 300;")).unwrap().0.unwrap();
+    assert_eq!(
+        result.rows,
+        vec![
+            vec!["aa".into(), 0.into(), 256.into(), 1.into()],
+            vec!["aa".into(), 2.into(), 258.into(), 1.into()],
+            vec!["aa".into(), 3.into(), 259.into(), 1.into()],
+            vec!["bb".into(), 1.into(), 257.into(), 1.into()],
+            vec!["bb".into(), 4.into(), 275.into(), 1.into()],
+        ]
+    );
+}
+
+#[test]
+fn test_group_by_key_wider_than_64_bits() {
+    test_query(
+        "select ts, first_name, ts, ts, count(1) from default where num = 5;",
+        &[
+            vec![1471953776.into(), "Joshua".into(), 1471953776.into(), 1471953776.into(), 1.into()],
+            vec![1480294290.into(), "Christina".into(), 1480294290.into(), 1480294290.into(), 1.into()],
+        ],
+    )
+}
+
+#[test]
+fn test_group_by_unencoded_columns() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table default (enum string packed, non_dense_ints integer plain, u8_offset_encoded int);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("select enum, non_dense_ints, sum(u8_offset_encoded) from default;")).unwrap().0.unwrap();
+    assert_eq!(
+        result.rows,
+        vec![
+            vec!["aa".into(), 0.into(), 756.into()],
+            vec!["aa".into(), 1.into(), 432.into()],
+            vec!["aa".into(), 2.into(), 258.into()],
+            vec!["aa".into(), 3.into(), 259.into()],
+            vec!["bb".into(), 1.into(), 257.into()],
+            vec!["bb".into(), 3.into(), 500.into()],
+            vec!["bb".into(), 4.into(), 275.into()],
+            vec!["cc".into(), 2.into(), 854.into()],
+        ]
+    );
+}
+
+#[test]
+fn test_join_group_by_dimension_column() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/tiny.csv", "default", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/tld_dimension.csv", "tlds", 4, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query(
+        "select tld_type, count(1) from default join tlds on default.tld = tlds.tld;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![
+        vec!["generic".into(), 63.into()],
+        vec!["sponsored".into(), 29.into()],
+    ]);
+}
+
+#[test]
+fn test_join_filter_dimension_column() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/tiny.csv", "default", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/tld_dimension.csv", "tlds", 4, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query(
+        "select tld, count(1) from default inner join tlds on tlds.tld = default.tld where tld_rank < 3;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![
+        vec!["com".into(), 9.into()],
+        vec!["net".into(), 6.into()],
+    ]);
+}
+
+#[test]
+fn test_join_duplicate_dimension_key() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/tiny.csv", "default", 40, vec![]));
+    let _ = block_on(ruba.load_csv("test_data/tiny.csv", "duplicates", 40, vec![]));
+    match block_on(ruba.run_query("select count(1) from default join duplicates on default.tld = duplicates.tld;")).unwrap() {
+        (Err(QueryError::SchemaViolation(_)), _) => {}
+        (other, _) => panic!("Expected schema violation, got {:?}", other.map(|o| o.rows)),
+    }
+}
+
+#[test]
+fn test_union_all() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/tiny.csv", "events_20261015", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "events_20261107", 20, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query(
+        "select tld, count(1) from events_20261015 where tld = 'name' \
+         union all select enum, count(1) from events_20261107 where enum = 'cc';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![
+        vec!["name".into(), 17.into()],
+        vec!["cc".into(), 2.into()],
+    ]);
+}
+
+#[test]
+fn test_table_wildcard() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/tiny.csv", "events_20261015", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/tiny.csv", "events_20261016", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "events_20261107", 20, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("select tld, count(1) from events_202610* where tld = 'name';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec!["name".into(), 34.into()]]);
+}
+
+#[test]
+fn test_table_wildcard_missing_columns() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/tiny.csv", "events_20261015", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "events_20261107", 20, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query(
+        "select first_name, u8_offset_encoded from events_* where u8_offset_encoded = 256;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![Value::Null, 256.into()]]);
+}
+
+#[test]
+fn test_missing_table() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/tiny.csv", "default", 40, vec![]));
+    for query in &["select tld from missing;",
+                   "select tld from events_*;",
+                   "select tld, count(1) from default join missing on default.tld = missing.tld;"] {
+        match block_on(ruba.run_query(query)).unwrap().0 {
+            Err(QueryError::TableNotFound(_)) => {}
+            other => panic!("Expected TableNotFound for {}, got {:?}", query, other.map(|o| o.rows)),
+        }
+    }
+}
+
+#[test]
+fn test_group_by_range_wider_than_63_bits() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/extreme_ints.csv", "default", 10, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("select value, count(0) from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![(-9_000_000_000_000_000_000i64).into(), 2.into()],
+                                 vec![9_000_000_000_000_000_000i64.into(), 1.into()]]);
+}
+
+#[test]
+fn test_show_tables() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let output = block_on(ruba.run_query("show tables;")).unwrap().0.unwrap();
+    let names = output.rows.iter().map(|row| row[0].clone()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["_meta_tables".into(), "default".into()]);
+    assert_eq!(output.rows[1][1], 10.into());
+    assert_eq!(output.rows[1][2], 3.into());
+}
+
+#[test]
+fn test_describe() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    assert_eq!(
+        block_on(ruba.run_query("describe default;")).unwrap().0.unwrap().rows,
+        vec![
+            vec!["enum".into(), "string".into(), "dictionary".into(), "false".into()],
+            vec!["non_dense_ints".into(), "integer".into(), "offset u8".into(), "false".into()],
+            vec!["u8_offset_encoded".into(), "integer".into(), "offset u8".into(), "false".into()],
+        ]
+    );
+}
+
+#[test]
+fn test_show_stats() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let output = block_on(ruba.run_query("SHOW STATS default;")).unwrap().0.unwrap();
+    assert_eq!(output.colnames, vec!["column", "bytes", "rows", "batches", "distinct"]);
+    let without_bytes = output.rows.iter()
+        .map(|row| vec![row[0].clone(), row[2].clone(), row[3].clone(), row[4].clone()])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        without_bytes,
+        vec![
+            vec!["enum".into(), 10.into(), 3.into(), 3.into()],
+            vec!["non_dense_ints".into(), 10.into(), 3.into(), 5.into()],
+            vec!["u8_offset_encoded".into(), 10.into(), 3.into(), 9.into()],
+        ]
+    );
+}
+
+#[test]
+fn test_describe_missing_table() {
+    let ruba = Ruba::memory_only();
+    let result = block_on(ruba.run_query("describe missing;")).unwrap().0;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_load_rejects_values_of_wrong_type() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query("create table default (id int, value int);")).unwrap().0.unwrap();
+    // Only the last of three batches contains a string
+    match block_on(ruba.load_csv("test_data/late_type_error.csv", "default", 2, vec![])).unwrap() {
+        Err(QueryError::SchemaViolation(_)) => {}
+        other => panic!("Expected schema violation, got {:?}", other),
+    }
+    let result = block_on(ruba.run_query("select id from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+}
+
+#[test]
+fn test_create_table_codec_hints() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "CREATE TABLE default (enum string packed, non_dense_ints integer plain, u8_offset_encoded int);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    assert_eq!(
+        block_on(ruba.run_query("describe default;")).unwrap().0.unwrap().rows,
+        vec![
+            vec!["enum".into(), "string".into(), "packed".into(), "false".into()],
+            vec!["non_dense_ints".into(), "integer".into(), "i64".into(), "false".into()],
+            vec!["u8_offset_encoded".into(), "integer".into(), "offset u8".into(), "false".into()],
+        ]
+    );
+}
+
+#[test]
+fn test_create_table_enforces_schema() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query("create table default (enum string, non_dense_ints string);")).unwrap().0.unwrap();
+    match block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap() {
+        Err(QueryError::SchemaViolation(_)) => {}
+        other => panic!("Expected schema violation, got {:?}", other),
+    }
+    match block_on(ruba.run_query("create table default (enum string);")).unwrap().0 {
+        Err(QueryError::TableAlreadyExists(_)) => {}
+        other => panic!("Expected TableAlreadyExists, got {:?}", other.map(|o| o.rows)),
+    }
+    assert!(block_on(ruba.run_query("create table other (ts string) with (time_column = ts);")).unwrap().0.is_err());
+}
+
+#[test]
+fn test_create_table_nullable_columns() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let columns = "ts int not null, guid string, first_name string, last_name string, hash string, \
+                   version string, num int, opaque_json string compressed";
+    block_on(ruba.run_query(&format!("create table default ({}, tld string);", columns))).unwrap().0.unwrap();
+    block_on(ruba.run_query(&format!("create table strict ({}, tld string not null);", columns))).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "implicit", 40, vec![])).unwrap().unwrap();
+    match block_on(ruba.load_csv("test_data/small.csv", "strict", 40, vec![])).unwrap() {
+        Err(QueryError::SchemaViolation(_)) => {}
+        other => panic!("Expected schema violation, got {:?}", other),
+    }
+    let counts = |table: &str| {
+        let query = format!("select tld, num, count(1) from {};", table);
+        let mut rows = block_on(ruba.run_query(&query)).unwrap().0.unwrap().rows
+            .iter().map(|row| format!("{:?}", row)).collect::<Vec<_>>();
+        rows.sort();
+        rows
+    };
+    assert_eq!(counts("default"), counts("implicit"));
+}
+
+#[test]
+fn test_truncate_and_drop_table() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "other", 4, vec![])).unwrap().unwrap();
+    block_on(ruba.run_query("truncate table default;")).unwrap().0.unwrap();
+    let result = block_on(ruba.run_query("select count(1) from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+    block_on(ruba.run_query("drop table other;")).unwrap().0.unwrap();
+    let result = block_on(ruba.run_query("show tables;")).unwrap().0.unwrap();
+    let tables = result.rows.iter().map(|row| row[0].clone()).collect::<Vec<_>>();
+    assert_eq!(tables, vec!["_meta_tables".into(), "default".into()]);
+    assert!(block_on(ruba.run_query("drop table other;")).unwrap().0.is_err());
+}
+
+#[test]
+fn test_delete() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("delete from default where non_dense_ints = 2;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![Value::Int(3)]]);
+    let result = block_on(ruba.run_query("select enum, count(1) from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec!["aa".into(), 4.into()], vec!["bb".into(), 3.into()]]);
+    let result = block_on(ruba.run_query("select u8_offset_encoded from default where non_dense_ints = 2;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+}
+
+#[test]
+fn test_delete_non_boolean_predicate() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    assert!(block_on(ruba.run_query("delete from default where enum;")).unwrap().0.is_err());
+    assert!(block_on(ruba.run_query("delete from default where 5;")).unwrap().0.is_err());
+    let result = block_on(ruba.run_query("select u8_offset_encoded from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows.len(), 10);
+}
+
+#[test]
+fn test_delete_with_compaction() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("delete from default where non_dense_ints < 3;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![Value::Int(7)]]);
+    let result = block_on(ruba.run_query("select enum, count(1) from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec!["aa".into(), 1.into()], vec!["bb".into(), 2.into()]]);
+    let result = block_on(ruba.run_query("delete from default where enum = 'bb';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![Value::Int(2)]]);
+    // Batches without live rows are dropped
+    let result = block_on(ruba.run_query("show tables;")).unwrap().0.unwrap();
+    assert_eq!(result.rows[1][..3].to_vec(), vec!["default".into(), 1.into(), 1.into()]);
+    let result = block_on(ruba.run_query("select u8_offset_encoded from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![259.into()]]);
+    let result = block_on(ruba.run_query("delete from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![Value::Int(1)]]);
+    let result = block_on(ruba.run_query("select u8_offset_encoded from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+}
+
+#[test]
+fn test_retention_max_rows() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query("create table default with (max_rows = 50);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/tiny.csv", "default", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.enforce_retention()).unwrap();
+    let stats = block_on(ruba.table_stats()).unwrap().into_iter().find(|t| t.name == "default").unwrap();
+    assert_eq!(stats.rows, 20);
+    assert_eq!(stats.batches, 1);
+    assert_eq!(stats.evicted_rows, 80);
+    assert!(stats.evicted_bytes > 0);
+}
+
+#[test]
+fn test_retention_max_age() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query("create table default with (time_column = ts, max_age = 365d);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/tiny.csv", "default", 40, vec![])).unwrap().unwrap();
+    block_on(ruba.enforce_retention()).unwrap();
+    let stats = block_on(ruba.table_stats()).unwrap().into_iter().find(|t| t.name == "default").unwrap();
+    assert_eq!(stats.rows, 0);
+    assert_eq!(stats.evicted_rows, 100);
+}
+
+#[test]
+fn test_quoted_identifiers_and_comments() {
+    test_query(
+        "select `first_name`, count(1) -- rows per name\n\
+         from \"default\" /* whole table */ where (\"first_name\" = 'Adam') and (num < 2.0e1);",
+        &[vec!["Adam".into(), 2.into()]],
+    )
+}
+
+#[test]
+fn test_select_distinct() {
+    test_query_ec(
+        "select distinct enum from default;",
+        &[
+            vec!["aa".into()],
+            vec!["bb".into()],
+            vec!["cc".into()],
+        ],
+    )
+}
+
+#[test]
+fn test_select_distinct_multiple_columns() {
+    test_query_ec(
+        "select distinct enum, non_dense_ints from default where enum = 'cc';",
+        &[vec!["cc".into(), 2.into()]],
+    )
+}
+
+#[test]
+fn test_select_distinct_stops_at_limit() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    let output = block_on(ruba.run_query("select distinct non_dense_ints from default limit 1;")).unwrap().0.unwrap();
+    assert_eq!(output.rows.len(), 1);
+    assert!(output.stats.rows_scanned < 10);
+}
+
+#[test]
+fn test_no_implicit_limit() {
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/small.csv", "default", 400, vec![]));
+    let result = block_on(ruba.run_query("select ts from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows.len(), 4000);
+}
+
+#[test]
+fn test_limit_all() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![])).unwrap().unwrap();
+    for &(query, rows) in &[("select u8_offset_encoded from default limit all offset 8;", 2),
+                            ("select u8_offset_encoded from default order by u8_offset_encoded limit all;", 10),
+                            ("select u8_offset_encoded from default limit 18446744073709551615 offset 8;", 2)] {
+        let result = block_on(ruba.run_query(query)).unwrap().0.unwrap();
+        assert_eq!(result.rows.len(), rows, "{}", query);
+    }
+}
+
+#[test]
+fn test_query_stream() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![]));
+
+    let chunks = block_on(ruba.run_query_stream("select enum from default limit 7 offset 2;").collect()).unwrap();
+    assert!(chunks.len() > 1);
+    let chunks = chunks.into_iter().map(|chunk| chunk.unwrap()).collect::<Vec<_>>();
+    assert_eq!(chunks.iter().map(|chunk| chunk.rows.len()).sum::<usize>(), 7);
+    assert_eq!(chunks.last().unwrap().stats.rows_scanned, 10);
+
+    let mut chunks = block_on(ruba.run_query_stream("select enum, count(1) from default;").collect()).unwrap();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks.pop().unwrap().unwrap().rows, vec![
+        vec!["aa".into(), 5.into()],
+        vec!["bb".into(), 3.into()],
+        vec!["cc".into(), 2.into()],
+    ]);
+}
+
+#[test]
+fn test_query_stream_more_chunks_than_buffered() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/tiny.csv", "default", 1, vec![]));
+
+    let (first, rest) = block_on(ruba.run_query_stream("select first_name from default;").into_future()).map_err(|e| e.0).unwrap();
+    assert_eq!(first.unwrap().unwrap().rows.len(), 1);
+    // Workers wait for the client instead of buffering all chunks
+    std::thread::sleep(Duration::from_millis(50));
+    let chunks = block_on(rest.collect()).unwrap();
+    assert_eq!(chunks.into_iter().map(|chunk| chunk.unwrap().rows.len()).sum::<usize>(), 99);
+}
+
+#[test]
+fn test_query_stream_does_not_block_workers() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/tiny.csv", "default", 1, vec![]));
+    let stream = ruba.run_query_stream("select first_name from default;");
+    // The client does not receive any chunks while other queries run
+    std::thread::sleep(Duration::from_millis(50));
+    let result = block_on(ruba.run_query("select first_name from default limit 3;")).unwrap().0.unwrap();
+    assert_eq!(result.rows.len(), 3);
+    let chunks = block_on(stream.collect()).unwrap();
+    assert_eq!(chunks.into_iter().map(|chunk| chunk.unwrap().rows.len()).sum::<usize>(), 100);
+}
+
+#[test]
+fn test_top_k_across_batches() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("select ts from default order by ts limit 3;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1456447721.into()], vec![1456471749.into()], vec![1456477908.into()]]);
+    let result = block_on(ruba.run_query("select ts from default order by ts desc limit 3;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1488060155.into()], vec![1488046904.into()], vec![1488033085.into()]]);
+    let result = block_on(ruba.run_query("select ts from default order by ts desc limit 2 offset 1;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1488046904.into()], vec![1488033085.into()]]);
+}
+
+#[test]
+fn test_top_k_prunes_batches() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/small.csv", "default", 10, vec![]));
+    let result = block_on(ruba.run_query("select num from default order by num limit 1;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![0.into()]]);
+    assert!(result.stats.rows_scanned < 2000);
+}
+
+#[test]
+fn test_query_timeout() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/small.csv", "default", 10, vec![]));
+    let result = block_on(ruba.run_query_with_timeout("select ts from default;", Duration::from_secs(0))).unwrap().0;
+    match result {
+        Err(QueryError::Timeout(0)) => {}
+        other => panic!("Expected timeout, got {:?}", other.map(|output| output.rows.len())),
+    }
+    let result = block_on(ruba.run_query_with_timeout("select ts from default;", Duration::from_secs(60))).unwrap().0;
+    assert_eq!(result.unwrap().rows.len(), 4000);
+}
+
+#[test]
+fn test_query_cancellation() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/small.csv", "default", 10, vec![]));
+    let cancellation = CancellationHandle::new();
+    cancellation.cancel();
+    let query = ruba.run_query_with_cancellation("select ts, first_name from default order by ts;", cancellation);
+    match block_on(query).unwrap().0 {
+        Err(QueryError::Cancelled) => {}
+        other => panic!("Expected cancellation, got {:?}", other.map(|output| output.rows.len())),
+    }
+}
+
+#[test]
+fn test_query_stream_cancellation() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/tiny.csv", "default", 1, vec![]));
+    let stream = ruba.run_query_stream("select first_name from default;");
+    let cancellation = stream.cancellation_handle();
+    let (first, rest) = block_on(stream.into_future()).map_err(|e| e.0).unwrap();
+    assert_eq!(first.unwrap().unwrap().rows.len(), 1);
+    // Workers wait for the client, so most batches have not been processed yet
+    cancellation.cancel();
+    let mut chunks = block_on(rest.collect()).unwrap();
+    match chunks.pop() {
+        Some(Err(QueryError::Cancelled)) => {}
+        other => panic!("Expected cancellation, got {:?}", other.map(|chunk| chunk.map(|output| output.rows.len()))),
+    }
+    assert!(chunks.len() < 98);
+}
+
+#[test]
+fn test_query_memory_limit() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/small.csv", "default", 400, vec![]));
+    ruba.set_query_memory_limit(Some(16 * 1024));
+    for query in &["select * from default;", "select ts, guid, count(1) from default;"] {
+        match block_on(ruba.run_query(query)).unwrap().0 {
+            Err(QueryError::MemoryLimitExceeded(_)) => {}
+            other => panic!("Expected memory limit to be exceeded, got {:?}", other.map(|output| output.rows.len())),
+        }
+    }
+    let result = block_on(ruba.run_query("select num, count(1) from default where num < 2;")).unwrap().0;
+    assert_eq!(result.unwrap().rows.len(), 2);
+
+    ruba.set_query_memory_limit(Some(64 * 1024 * 1024));
+    let result = block_on(ruba.run_query("select ts, guid, count(1) from default;")).unwrap().0;
+    assert_eq!(result.unwrap().rows.len(), 4000);
+}
+
+#[test]
+fn test_query_memory_limit_counts_decompressed_blocks() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table default (ts int, guid string, first_name string, last_name string, hash string, \
+         version string, tld string, num int, opaque_json string compressed);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+    // Within the range of values of every batch, so all blocks are decompressed
+    let query = "select num, count(1) from default where opaque_json = '[{\"firstname\":\"M';";
+    ruba.set_query_memory_limit(Some(64 * 1024));
+    match block_on(ruba.run_query(query)).unwrap().0 {
+        Err(QueryError::MemoryLimitExceeded(_)) => {}
+        other => panic!("Expected memory limit to be exceeded, got {:?}", other.map(|output| output.rows.len())),
+    }
+    ruba.set_query_memory_limit(None);
+    assert!(block_on(ruba.run_query(query)).unwrap().0.unwrap().rows.is_empty());
+}
+
+#[test]
+fn test_query_cache() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![]));
+    let query = "select enum, count(1) from default;";
+    let expected = vec![
+        vec!["aa".into(), 5.into()],
+        vec!["bb".into(), 3.into()],
+        vec!["cc".into(), 2.into()],
+    ];
+    assert_eq!(block_on(ruba.run_query(query)).unwrap().0.unwrap().rows, expected);
+    assert_eq!(block_on(ruba.run_query(query)).unwrap().0.unwrap().rows, expected);
+    assert_eq!(block_on(ruba.run_query("SELECT enum,  count(1) -- same query\nFROM default;")).unwrap().0.unwrap().rows, expected);
+    let stats = ruba.cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 1));
+
+    // Loading data invalidates cached results
+    let _ = block_on(ruba.load_csv("test_data/edge_cases.csv", "default", 4, vec![]));
+    assert_eq!(block_on(ruba.run_query(query)).unwrap().0.unwrap().rows, vec![
+        vec!["aa".into(), 10.into()],
+        vec!["bb".into(), 6.into()],
+        vec!["cc".into(), 4.into()],
+    ]);
+    let stats = ruba.cache_stats();
+    assert_eq!(stats.misses, 2);
+    // Results on the batches loaded before are reused
+    assert!(stats.partial_hits > 0);
+
+    ruba.set_query_cache_size(0);
+    assert_eq!(ruba.cache_stats().entries, 0);
+    block_on(ruba.run_query(query)).unwrap().0.unwrap();
+    let stats = ruba.cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 3, 0));
+}
+
+#[test]
+fn test_query_cache_time_macros() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/time_macros.csv", "default", 2, vec![]));
+    let query = "select enum, count(1) from default where $LAST_HOUR;";
+    assert_eq!(block_on(ruba.run_query(query)).unwrap().0.unwrap().rows, Vec::<Vec<Value>>::new());
+    assert_eq!(block_on(ruba.run_query(query)).unwrap().0.unwrap().rows, Vec::<Vec<Value>>::new());
+    let stats = ruba.cache_stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+    block_on(ruba.run_query("select enum, count(1) from default where $LAST_DAY;")).unwrap().0.unwrap();
+    assert_eq!(ruba.cache_stats().misses, 2);
+}
+
+#[test]
+fn test_zone_maps_skip_batches() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    let _ = block_on(ruba.load_csv("test_data/small.csv", "default", 10, vec![]));
+    let result = block_on(ruba.run_query("select ts from default where ts > 1488033085;")).unwrap().0.unwrap();
+    assert_eq!(result.rows.len(), 2);
+    assert!(result.stats.batches_skipped > 0);
+    assert!(result.stats.rows_scanned < 4000);
+
+    let result = block_on(ruba.run_query("select tld, count(0) from default where ts > 1488060155;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+    assert_eq!(result.stats.batches_skipped, 400);
+
+    let result = block_on(ruba.run_query("select ts from default where tld = 'zzz';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+    assert_eq!(result.stats.batches_skipped, 400);
+}
+
+#[test]
+fn test_string_index_lookup() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table default (ts int, guid string packed, first_name string, last_name string, hash string, \
+         version string, tld string, num int, opaque_json string);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 10, vec![])).unwrap().unwrap();
+
+    let result = block_on(ruba.run_query(
+        "select ts, first_name from default where guid = '2c9b62a6-3ce5-4257-8aae-a9a29502c0a2';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1472763607.into(), "Victor".into()]]);
+    assert_eq!(result.stats.batches_skipped, 399);
+
+    let result = block_on(ruba.run_query(
+        "select first_name, count(0) from default where guid = '2c9b62a6-3ce5-4257-8aae-a9a29502c0a2';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec!["Victor".into(), 1.into()]]);
+
+    let result = block_on(ruba.run_query("select ts from default where guid = 'missing';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+    assert_eq!(result.stats.batches_skipped, 400);
+}
+
+#[test]
+fn test_sort_packed_strings() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table default (ts int, guid string, first_name string, last_name string, hash string packed, \
+         version string, tld string, num int, opaque_json string);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("select hash from default order by hash limit 2;")).unwrap().0.unwrap();
+    let plain = Ruba::memory_only();
+    block_on(plain.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+    let expected = block_on(plain.run_query("select hash from default order by hash limit 2;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, expected.rows);
+    assert_eq!(result.rows.len(), 2);
+}
+
+#[test]
+fn test_sort_compressed_strings_across_batches() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table default (ts int, guid string, first_name string, last_name string, hash string compressed, \
+         version string, tld string, num int, opaque_json string);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+    let plain = Ruba::memory_only();
+    block_on(plain.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+    for query in &["select hash from default order by hash limit 3;",
+                   "select hash, count(0) from default where tld = 'mil';"] {
+        let result = block_on(ruba.run_query(query)).unwrap().0.unwrap().rows;
+        let expected = block_on(plain.run_query(query)).unwrap().0.unwrap().rows;
+        assert_eq!(result, expected, "{}", query);
+    }
+}
+
+#[test]
+fn test_compressed_strings() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table compressed (ts int, guid string, first_name string, last_name string, hash string compressed, \
+         version string, tld string, num int, opaque_json string compressed);")).unwrap().0.unwrap();
+    block_on(ruba.run_query(
+        "create table packed (ts int, guid string, first_name string, last_name string, hash string packed, \
+         version string, tld string, num int, opaque_json string packed);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "compressed", 400, vec![])).unwrap().unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "packed", 400, vec![])).unwrap().unwrap();
+
+    let compressed_bytes = || block_on(ruba.table_stats()).unwrap().into_iter()
+        .find(|t| t.name == "compressed").unwrap().batches_bytes;
+    let bytes_before_queries = compressed_bytes();
+    let result = block_on(ruba.run_query("describe compressed;")).unwrap().0.unwrap();
+    assert!(result.rows.iter().any(|row| row[0] == "hash".into() && row.contains(&"lz4".into())));
+    assert!(result.rows.iter().any(|row| row[0] == "opaque_json".into() && row.contains(&"lz4".into())));
+
+    for query in &["select ts, hash, opaque_json from {} limit 5;",
+                   "select opaque_json from {} where ts = 1477514391;",
+                   "select hash from {} order by hash limit 3;",
+                   "select first_name, count(0) from {} \
+                    where hash = 'df72ff04ce7a3cd358f9206c80396065e43f51f9fc258719813a498b1141a53e';"] {
+        let result = block_on(ruba.run_query(&query.replace("{}", "compressed"))).unwrap().0.unwrap();
+        let expected = block_on(ruba.run_query(&query.replace("{}", "packed"))).unwrap().0.unwrap();
+        assert_eq!(result.rows, expected.rows, "{}", query);
+        assert!(!result.rows.is_empty());
+    }
+    // Decompressed blocks are freed after each query
+    block_on(ruba.run_query("select * from compressed;")).unwrap().0.unwrap();
+    assert_eq!(compressed_bytes(), bytes_before_queries);
+
+    let stats = block_on(ruba.table_stats()).unwrap().into_iter().find(|t| t.name == "compressed").unwrap();
+    let &(_, _, ratio) = stats.size_per_column.iter().find(|c| c.0 == "opaque_json").unwrap();
+    assert!(ratio > 2.0, "compression ratio {}", ratio);
+    let &(_, _, ratio) = stats.size_per_column.iter().find(|c| c.0 == "ts").unwrap();
+    assert_eq!(ratio, 1.0);
+
+    let hash = "df72ff04ce7a3cd358f9206c80396065e43f51f9fc258719813a498b1141a53e";
+    let result = block_on(ruba.run_query(&format!("delete from compressed where hash = '{}';", hash))).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1.into()]]);
+    let result = block_on(ruba.run_query(&format!("select ts from compressed where hash = '{}';", hash))).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+}
+
+#[test]
+fn test_group_by_shared_dictionary() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.run_query(
+        "create table default (ts int, guid string, first_name string shared, last_name string, hash string, \
+         version string, tld string shared, num int, opaque_json string);")).unwrap().0.unwrap();
+    block_on(ruba.load_csv("test_data/small.csv", "default", 10, vec![])).unwrap().unwrap();
+    let plain = Ruba::memory_only();
+    block_on(plain.load_csv("test_data/small.csv", "default", 400, vec![])).unwrap().unwrap();
+
+    for query in &["select tld, count(0) from default;",
+                   "select tld, first_name, count(0) from default;",
+                   "select tld, version, count(0) from default;",
+                   "select first_name, count(0) from default where tld = 'mil';"] {
+        let result = block_on(ruba.run_query(query)).unwrap().0.unwrap().rows;
+        let expected = block_on(plain.run_query(query)).unwrap().0.unwrap().rows;
+        assert_eq!(result, expected, "{}", query);
+    }
+
+    let result = block_on(ruba.run_query("describe default;")).unwrap().0.unwrap();
+    assert!(result.rows.iter().any(|row| row[0] == "tld".into() && row.contains(&"shared dictionary".into())));
+}
+
+
+#[test]
+fn test_delta_encoded_timestamps() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/timestamps.csv", "default", 500, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("describe default;")).unwrap().0.unwrap();
+    assert!(result.rows.iter().any(|row| row[0] == "ts".into() && row.contains(&"delta".into())));
+
+    let result = block_on(ruba.run_query("select ts from default where ts > 1500005000;")).unwrap().0.unwrap();
+    assert_eq!(result.rows.len(), 499);
+    let result = block_on(ruba.run_query("select value, count(0) from default where ts < 1500000070;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![0.into(), 1.into()], vec![1.into(), 1.into()], vec![2.into(), 1.into()],
+                                 vec![3.into(), 1.into()], vec![4.into(), 1.into()], vec![5.into(), 1.into()],
+                                 vec![6.into(), 1.into()]]);
+    let result = block_on(ruba.run_query("select value from default where ts = 1500001010;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![3.into()]]);
+    let result = block_on(ruba.run_query("select ts from default order by ts desc limit 2;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1500009990.into()], vec![1500009980.into()]]);
+    let result = block_on(ruba.run_query("select ts, count(0) from default where ts < 1500000030;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1500000000.into(), 1.into()], vec![1500000010.into(), 1.into()],
+                                 vec![1500000020.into(), 1.into()]]);
+}
+
+#[test]
+fn test_delta_encoded_timestamps_conjunctions() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/timestamps.csv", "default", 500, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query(
+        "select ts from default where (ts > 1500000100) and (ts < 1500000140) and (1500000110 < ts);")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1500000120.into()], vec![1500000130.into()]]);
+    let result = block_on(ruba.run_query(
+        "select ts from default where (ts > 1500000200) and (ts < 1500000100);")).unwrap().0.unwrap();
+    assert_eq!(result.rows, Vec::<Vec<Value>>::new());
+    let result = block_on(ruba.run_query(
+        "select ts from default where (ts > 1500000000) and (value = 3) and (ts < 1500000200);")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1500000030.into()], vec![1500000100.into()], vec![1500000170.into()]]);
+    let result = block_on(ruba.run_query(
+        "select value, count(0) from default where (value < 2) and (ts < 1500000140);")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![0.into(), 2.into()], vec![1.into(), 2.into()]]);
+}
+
+#[test]
+fn test_bit_packed_integers() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/timestamps.csv", "default", 500, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("describe default;")).unwrap().0.unwrap();
+    // Chosen from the range of values, 3 bits per value take less memory than u8
+    assert!(result.rows.iter().any(|row| row[0] == "value".into() && row.contains(&"bit packed".into())));
+
+    let result = block_on(ruba.run_query("select value, count(0) from default where value < 3;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![0.into(), 143.into()], vec![1.into(), 143.into()], vec![2.into(), 143.into()]]);
+    let result = block_on(ruba.run_query("select value, count(0) from default where value = 6;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![6.into(), 142.into()]]);
+    let result = block_on(ruba.run_query("select ts, value from default where value > 5 limit 2;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1500000060.into(), 6.into()], vec![1500000130.into(), 6.into()]]);
+    // Compared on the packed values since the disjunction can't be answered with an index lookup
+    let result = block_on(ruba.run_query("select value, count(0) from default where (value < 1) or (value > 5);"))
+        .unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![0.into(), 143.into()], vec![6.into(), 142.into()]]);
+}
+
+#[test]
+fn test_run_length_encoded_columns() {
+    let _ = env_logger::try_init();
+    let ruba = Ruba::memory_only();
+    block_on(ruba.load_csv("test_data/clustered.csv", "default", 500, vec![])).unwrap().unwrap();
+    let result = block_on(ruba.run_query("describe default;")).unwrap().0.unwrap();
+    assert!(result.rows.iter().any(|row| row[0] == "tld".into() && row.contains(&"run length".into())));
+    assert!(result.rows.iter().any(|row| row[0] == "passengers".into() && row.contains(&"run length".into())));
+
+    let result = block_on(ruba.run_query("select tld, count(0) from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec!["com".into(), 400.into()], vec!["de".into(), 200.into()],
+                                 vec!["net".into(), 200.into()], vec!["org".into(), 200.into()]]);
+    let result = block_on(ruba.run_query("select passengers, sum(passengers), count(0) from default;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![1.into(), 250.into(), 250.into()], vec![2.into(), 500.into(), 250.into()],
+                                 vec![3.into(), 750.into(), 250.into()], vec![4.into(), 1000.into(), 250.into()]]);
+    let result = block_on(ruba.run_query("select passengers, count(0) from default where tld = 'net';")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec![3.into(), 100.into()], vec![4.into(), 100.into()]]);
+    let result = block_on(ruba.run_query("select tld, count(0) from default where passengers > 2;")).unwrap().0.unwrap();
+    assert_eq!(result.rows, vec![vec!["com".into(), 100.into()], vec!["de".into(), 100.into()],
+                                 vec!["net".into(), 200.into()], vec!["org".into(), 100.into()]]);
+}